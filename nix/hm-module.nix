@@ -3,18 +3,8 @@
 let
   cfg = config.programs.constellation-cursor;
 
-  configFile = ''
-    cursor_scale=${toString cfg.settings.cursor_scale}
-    outline_thickness=${toString cfg.settings.outline_thickness}
-    fade_enabled=${lib.boolToString cfg.settings.fade_enabled}
-    fade_in_enabled=${lib.boolToString cfg.settings.fade_in_enabled}
-    fade_speed=${toString cfg.settings.fade_speed}
-    frost_intensity=${toString cfg.settings.frost_intensity}
-    hotspot_smoothing=${lib.boolToString cfg.settings.hotspot_smoothing}
-    hotspot_threshold=${toString cfg.settings.hotspot_threshold}
-    config_polling=${lib.boolToString cfg.settings.config_polling}
-    config_poll_interval=${toString cfg.settings.config_poll_interval}
-  '';
+  # Every key of cursor.conf works here, e.g. idle_hide_ms or log_level
+  configFile = lib.generators.toKeyValue { } cfg.settings;
 in
 {
   options.programs.constellation-cursor = {
@@ -26,7 +16,8 @@ in
         };
 
     settings = lib.mkOption {
-      type = lib.types.attrs;
+      type = with lib.types; attrsOf (oneOf [ bool int float str ]);
+      description = "Written to cursor.conf as key=value lines, see the README for the keys.";
       default = {
         cursor_scale = 1.5;
         outline_thickness = 0.0;
@@ -34,8 +25,6 @@ in
         fade_in_enabled = false;
         fade_speed = 30;
         frost_intensity = 0;
-        hotspot_smoothing = false;
-        hotspot_threshold = 0;
         config_polling = true;
         config_poll_interval = 50;
       };
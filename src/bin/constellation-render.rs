@@ -0,0 +1,175 @@
+//! Render a cursor design to PNG without a compositor, through the same
+//! rasterizer the preload library draws the cursor plane with.
+//!
+//! ```bash
+//! constellation-render my_cursor.json --scale 2 --ascii
+//! constellation-render pointer --rotation 30 --out pointer.png
+//! constellation-render --xcursor-theme ~/.local/share/icons/constellation
+//! ```
+
+use std::process::exit;
+
+use the_constellation_cursor::{
+    export_xcursor_theme, render_to_canvas, Canvas, CursorType, Design, CURSOR_DISPLAY_SIZE,
+};
+
+const USAGE: &str = "\
+Usage: constellation-render <design> [options]
+       constellation-render --xcursor-theme <dir>
+
+<design> is a built-in cursor type (default, pointer, text, crosshair, wait,
+grab, not-allowed) or a designer JSON / SVG file.
+
+Options:
+  -s, --scale <n>      Cursor scale, 1.5 = default size (0.5-10)
+  -r, --rotation <deg> Rotation in degrees, added to the design's own
+  -o, --out <file>     Where to write the PNG (default <name>@<scale>x.png)
+  -a, --ascii          Also print the cursor to the terminal
+  --xcursor-theme <dir> Write the built-in cursors as an XCursor theme into
+                       <dir>, named after it (24-96px, for X11 and XWayland)
+  -h, --help           Show this help";
+
+fn fail(message: &str) -> ! {
+    eprintln!("constellation-render: {}", message);
+    eprintln!("Try --help for usage.");
+    exit(1);
+}
+
+fn parse_number(flag: &str, value: Option<String>) -> f32 {
+    value
+        .and_then(|v| v.parse::<f32>().ok())
+        .unwrap_or_else(|| fail(&format!("{} needs a number", flag)))
+}
+
+/// Two characters per pixel so the aspect ratio survives the terminal,
+/// brighter pixels get denser characters and the hotspot is an X
+fn print_ascii(canvas: &Canvas) {
+    const RAMP: &[u8] = b".:-=+*#%@";
+    let (hot_x, hot_y) = canvas.hotspot();
+    let pixels = canvas.to_argb();
+
+    let mut lines = Vec::new();
+    for (y, row) in pixels.chunks(canvas.width()).enumerate() {
+        let mut line = String::with_capacity(row.len() * 2);
+        for (x, pixel) in row.iter().enumerate() {
+            let alpha = pixel >> 24;
+            let cell = if (x as i32, y as i32) == (hot_x, hot_y) {
+                "XX".to_string()
+            } else if alpha < 32 {
+                "  ".to_string()
+            } else {
+                let [b, g, r, _] = pixel.to_le_bytes();
+                let lightness = (r as usize * 3 + g as usize * 6 + b as usize) / 10;
+                let c = RAMP[lightness * (RAMP.len() - 1) / 255] as char;
+                format!("{}{}", c, c)
+            };
+            line.push_str(&cell);
+        }
+        lines.push(line.trim_end().to_string());
+    }
+    // The canvas is padded to the plane size, no need to print the padding
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    for line in lines {
+        println!("{}", line);
+    }
+    println!("hotspot: {},{}", hot_x, hot_y);
+}
+
+fn main() {
+    let mut design = None;
+    let mut scale = 1.5;
+    let mut rotation = 0.0;
+    let mut out = None;
+    let mut ascii = false;
+    let mut theme_dir = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-s" | "--scale" => scale = parse_number(&arg, args.next()),
+            "-r" | "--rotation" => rotation = parse_number(&arg, args.next()),
+            "-o" | "--out" => out = Some(args.next().unwrap_or_else(|| fail("--out needs a file"))),
+            "-a" | "--ascii" => ascii = true,
+            "--xcursor-theme" => {
+                theme_dir = Some(
+                    args.next()
+                        .unwrap_or_else(|| fail("--xcursor-theme needs a directory")),
+                )
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => fail(&format!("unknown option {}", arg)),
+            _ if design.is_none() => design = Some(arg),
+            _ => fail("only one design at a time"),
+        }
+    }
+
+    if let Some(dir) = theme_dir {
+        let dir = std::path::Path::new(&dir);
+        let name = dir.file_name().map_or("constellation".into(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+        if let Err(e) = export_xcursor_theme(dir, &name) {
+            fail(&format!(
+                "can't write the theme to {}: {}",
+                dir.display(),
+                e
+            ));
+        }
+        eprintln!(
+            "Wrote XCursor theme {} to {}, use it with XCURSOR_THEME={}",
+            name,
+            dir.display(),
+            name
+        );
+        return;
+    }
+
+    let design = design.unwrap_or_else(|| fail("no design given"));
+    if !(0.5..=10.0).contains(&scale) {
+        fail("scale must be between 0.5 and 10");
+    }
+
+    // A file wins over a cursor type of the same name
+    let path = std::path::Path::new(&design);
+    let (name, canvas) = if path.is_file() {
+        let content = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| fail(&format!("can't read {}: {}", design, e)));
+        let design = Design::from_file(path, &content);
+        let name = path
+            .file_stem()
+            .map_or("design".into(), |stem| stem.to_string_lossy().into_owned());
+        (name, render_to_canvas(&design, scale, rotation))
+    } else {
+        let cursor_type = CursorType::from_name(&design)
+            .unwrap_or_else(|| fail(&format!("{} is not a file or a cursor type", design)));
+        let name = format!("{:?}", cursor_type).to_lowercase();
+        (
+            name,
+            render_to_canvas(&Design::Builtin(cursor_type), scale, rotation),
+        )
+    };
+
+    // Smaller renders are padded up to what the plane shows
+    let canvas = canvas.trimmed(CURSOR_DISPLAY_SIZE as usize);
+    let out = out.unwrap_or_else(|| format!("{}@{}x.png", name, scale));
+    if let Err(e) = std::fs::write(&out, canvas.to_png()) {
+        fail(&format!("can't write {}: {}", out, e));
+    }
+
+    if ascii {
+        print_ascii(&canvas);
+    }
+    eprintln!(
+        "Wrote {} ({}x{}, hotspot {},{})",
+        out,
+        canvas.width(),
+        canvas.height(),
+        canvas.hotspot().0,
+        canvas.hotspot().1
+    );
+}
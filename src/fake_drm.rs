@@ -0,0 +1,1038 @@
+//! Fake DRM device for tests, so the hooks can run without a GPU.
+//!
+//! The device fd is a memfd: dumb buffers are slices of it, which makes the
+//! library's own MAP_DUMB + mmap work unchanged. `real_ioctl` hands ioctls on
+//! that fd to us, the libdrm plane functions are swapped for fakes and every
+//! legacy cursor call and atomic property is recorded for the test to check.
+//!
+//! The library lives in globals, so a `FakeDevice` also serializes tests and
+//! resets that state when it's opened.
+
+use super::*;
+use std::sync::MutexGuard;
+
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+static DEVICE: Mutex<Option<DeviceState>> = Mutex::new(None);
+
+const PAGE_SIZE: u64 = 4096;
+const FIRST_PROP_ID: u32 = 100;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PlaneKind {
+    Primary = 1,
+    Cursor = 2,
+}
+
+/// A legacy cursor ioctl as the driver got it
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CursorCall {
+    pub flags: u32,
+    pub crtc_id: u32,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub handle: u32,
+    pub hot_x: i32,
+    pub hot_y: i32,
+}
+
+/// One drmModeAtomicAddProperty that reached libdrm
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AtomicProperty {
+    pub req: usize,
+    pub object_id: u32,
+    pub property_id: u32,
+    pub value: u64,
+}
+
+struct DumbBuffer {
+    handle: u32,
+    offset: u64,
+    width: u32,
+    height: u32,
+    pitch: u32,
+}
+
+struct Framebuffer {
+    fb_id: u32,
+    handle: u32,
+    format: u32,
+    // None when the FB was added without DRM_MODE_FB_MODIFIERS
+    modifier: Option<u64>,
+}
+
+struct Plane {
+    plane_id: u32,
+    props: Vec<(u32, u64)>,
+}
+
+#[derive(Default)]
+struct DeviceState {
+    fd: i32,
+    size: u64,
+    next_id: u32,
+    buffers: Vec<DumbBuffer>,
+    framebuffers: Vec<Framebuffer>,
+    // Ids of removed FBs, handed out again first like the kernel does
+    free_fb_ids: Vec<u32>,
+    planes: Vec<Plane>,
+    crtcs: Vec<u32>,
+    // Requests that fail with EINVAL, as the test asked for
+    failing: Vec<libc::c_ulong>,
+    // Property names, the property id is FIRST_PROP_ID + index
+    prop_names: Vec<&'static str>,
+    cursor_calls: Vec<CursorCall>,
+    atomic: Vec<AtomicProperty>,
+    // (property name, value) pairs a commit fails with EINVAL on
+    rejected: Vec<(&'static str, u64)>,
+    // The next commit fails with this errno, whatever is in it
+    commit_errno: Option<i32>,
+    // The next property add fails, like libdrm out of memory
+    add_fails: bool,
+    commits: u32,
+    dirty_fbs: Vec<u32>,
+}
+
+impl DeviceState {
+    fn next_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn fb_id(&mut self) -> u32 {
+        self.free_fb_ids.pop().unwrap_or_else(|| self.next_id())
+    }
+
+    fn prop_id(&mut self, name: &'static str) -> u32 {
+        let idx = match self.prop_names.iter().position(|n| *n == name) {
+            Some(idx) => idx,
+            None => {
+                self.prop_names.push(name);
+                self.prop_names.len() - 1
+            }
+        };
+        FIRST_PROP_ID + idx as u32
+    }
+
+    /// Carve a dumb buffer out of the memfd, page aligned for mmap
+    fn create_dumb(&mut self, width: u32, height: u32, bpp: u32) -> &DumbBuffer {
+        let pitch = width * bpp.div_ceil(8);
+        let offset = self.size;
+        let size = (pitch as u64 * height as u64).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        self.size += size.max(PAGE_SIZE);
+        unsafe {
+            libc::ftruncate(self.fd, self.size as i64);
+        }
+
+        let handle = self.next_id();
+        self.buffers.push(DumbBuffer {
+            handle,
+            offset,
+            width,
+            height,
+            pitch,
+        });
+        self.buffers.last().unwrap()
+    }
+}
+
+/// Fill a count + pointer pair the way the kernel does: copy when there's
+/// room, always report how many there are
+unsafe fn copy_ids(ids: &[u32], ptr: u64, count: &mut u32) {
+    if *count as usize >= ids.len() && ptr != 0 {
+        std::ptr::copy_nonoverlapping(ids.as_ptr(), ptr as *mut u32, ids.len());
+    }
+    *count = ids.len() as u32;
+}
+
+fn device() -> MutexGuard<'static, Option<DeviceState>> {
+    DEVICE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Handle an ioctl on the fake device, None for any other fd
+pub(crate) unsafe fn ioctl(fd: i32, request: libc::c_ulong, arg: *mut c_void) -> Option<i32> {
+    let mut guard = device();
+    let state = guard.as_mut().filter(|s| s.fd == fd)?;
+
+    let ret = match request {
+        _ if state.failing.contains(&request) => -1,
+        DRM_IOCTL_MODE_CREATE_DUMB => {
+            let create = &mut *(arg as *mut DrmModeCreateDumb);
+            let buffer = state.create_dumb(create.width, create.height, create.bpp);
+            create.handle = buffer.handle;
+            create.pitch = buffer.pitch;
+            create.size = buffer.pitch as u64 * buffer.height as u64;
+            0
+        }
+        DRM_IOCTL_MODE_MAP_DUMB => {
+            let map = &mut *(arg as *mut DrmModeMapDumb);
+            match state.buffers.iter().find(|b| b.handle == map.handle) {
+                Some(buffer) => {
+                    map.offset = buffer.offset;
+                    0
+                }
+                None => -1,
+            }
+        }
+        DRM_IOCTL_MODE_ADDFB => {
+            let fb = &mut *(arg as *mut DrmModeFbCmd);
+            fb.fb_id = state.fb_id();
+            state.framebuffers.push(Framebuffer {
+                fb_id: fb.fb_id,
+                handle: fb.handle,
+                format: DRM_FORMAT_XRGB8888,
+                modifier: None,
+            });
+            0
+        }
+        DRM_IOCTL_MODE_ADDFB2 => {
+            let fb = &mut *(arg as *mut DrmModeFB2);
+            fb.fb_id = state.fb_id();
+            let modifiers = fb.flags & DRM_MODE_FB_MODIFIERS != 0;
+            state.framebuffers.push(Framebuffer {
+                fb_id: fb.fb_id,
+                handle: fb.handles[0],
+                format: fb.pixel_format,
+                modifier: modifiers.then_some(fb.modifier[0]),
+            });
+            0
+        }
+        DRM_IOCTL_MODE_GETFB2 => {
+            let fb = &mut *(arg as *mut DrmModeFB2);
+            let found = state.framebuffers.iter().find(|f| f.fb_id == fb.fb_id);
+            let buffer = found.and_then(|f| state.buffers.iter().find(|b| b.handle == f.handle));
+            match (found, buffer) {
+                (Some(found), Some(buffer)) => {
+                    fb.width = buffer.width;
+                    fb.height = buffer.height;
+                    fb.pixel_format = found.format;
+                    if let Some(modifier) = found.modifier {
+                        fb.flags |= DRM_MODE_FB_MODIFIERS;
+                        fb.modifier[0] = modifier;
+                    }
+                    fb.handles[0] = buffer.handle;
+                    fb.pitches[0] = buffer.pitch;
+                    0
+                }
+                _ => -1,
+            }
+        }
+        // Nothing scanned out, backdrop sampling finds no primary FB
+        DRM_IOCTL_MODE_GETCRTC => {
+            (*(arg as *mut DrmModeCrtc)).fb_id = 0;
+            0
+        }
+        DRM_IOCTL_MODE_CURSOR | DRM_IOCTL_MODE_CURSOR2 => {
+            let cursor = &*(arg as *const DrmModeCursor2);
+            let legacy = request == DRM_IOCTL_MODE_CURSOR;
+            state.cursor_calls.push(CursorCall {
+                flags: cursor.flags,
+                crtc_id: cursor.crtc_id,
+                x: cursor.x,
+                y: cursor.y,
+                width: cursor.width,
+                height: cursor.height,
+                handle: cursor.handle,
+                hot_x: if legacy { 0 } else { cursor.hot_x },
+                hot_y: if legacy { 0 } else { cursor.hot_y },
+            });
+            0
+        }
+        DRM_IOCTL_MODE_DIRTYFB => {
+            let dirty = &*(arg as *const DrmModeFbDirtyCmd);
+            state.dirty_fbs.push(dirty.fb_id);
+            0
+        }
+        DRM_IOCTL_MODE_RMFB => {
+            let fb_id = *(arg as *const u32);
+            let before = state.framebuffers.len();
+            state.framebuffers.retain(|f| f.fb_id != fb_id);
+            if state.framebuffers.len() < before {
+                state.free_fb_ids.push(fb_id);
+                0
+            } else {
+                -1
+            }
+        }
+        DRM_IOCTL_MODE_DESTROY_DUMB => {
+            let handle = (*(arg as *const DrmModeDestroyDumb)).handle;
+            let before = state.buffers.len();
+            state.buffers.retain(|b| b.handle != handle);
+            if state.buffers.len() < before {
+                0
+            } else {
+                -1
+            }
+        }
+        DRM_IOCTL_GEM_CLOSE => 0,
+        DRM_IOCTL_VERSION => {
+            let version = &mut *(arg as *mut DrmVersion);
+            let (name, desc) = (b"fake", b"Fake DRM device");
+            if version.name_len >= name.len() && version.name != 0 {
+                std::ptr::copy_nonoverlapping(name.as_ptr(), version.name as *mut u8, name.len());
+            }
+            if version.desc_len >= desc.len() && version.desc != 0 {
+                std::ptr::copy_nonoverlapping(desc.as_ptr(), version.desc as *mut u8, desc.len());
+            }
+            version.major = 1;
+            version.name_len = name.len();
+            version.desc_len = desc.len();
+            version.date_len = 0;
+            0
+        }
+        DRM_IOCTL_GET_CAP => {
+            let cap = &mut *(arg as *mut DrmGetCap);
+            let value = match cap.capability {
+                DRM_CAP_DUMB_BUFFER => Some(1),
+                DRM_CAP_CURSOR_WIDTH | DRM_CAP_CURSOR_HEIGHT => Some(64),
+                _ => None,
+            };
+            cap.value = value.unwrap_or(0);
+            if value.is_some() {
+                0
+            } else {
+                -1
+            }
+        }
+        DRM_IOCTL_MODE_GETRESOURCES => {
+            let res = &mut *(arg as *mut DrmModeCardRes);
+            copy_ids(&state.crtcs, res.crtc_id_ptr, &mut res.count_crtcs);
+            0
+        }
+        DRM_IOCTL_MODE_GETPLANERESOURCES => {
+            let res = &mut *(arg as *mut DrmModeGetPlaneRes);
+            let ids: Vec<u32> = state.planes.iter().map(|p| p.plane_id).collect();
+            copy_ids(&ids, res.plane_id_ptr, &mut res.count_planes);
+            0
+        }
+        DRM_IOCTL_MODE_GETPLANE => {
+            let plane = &mut *(arg as *mut DrmModeGetPlaneCmd);
+            match state.planes.iter().find(|p| p.plane_id == plane.plane_id) {
+                Some(found) => {
+                    let value = |name| {
+                        let id = FIRST_PROP_ID
+                            + state
+                                .prop_names
+                                .iter()
+                                .position(|n| *n == name)
+                                .unwrap_or(0) as u32;
+                        found
+                            .props
+                            .iter()
+                            .find(|(prop, _)| *prop == id)
+                            .map_or(0, |(_, value)| *value as u32)
+                    };
+                    plane.crtc_id = value("CRTC_ID");
+                    plane.fb_id = value("FB_ID");
+                    plane.possible_crtcs = 1;
+                    0
+                }
+                None => -1,
+            }
+        }
+        _ => -1,
+    };
+    if ret < 0 {
+        *libc::__errno_location() = libc::EINVAL;
+    }
+    Some(ret)
+}
+
+unsafe extern "C" fn fake_get_plane(fd: i32, plane_id: u32) -> *mut DrmModePlane {
+    let guard = device();
+    let Some(state) = guard.as_ref().filter(|s| s.fd == fd) else {
+        return std::ptr::null_mut();
+    };
+    if !state.planes.iter().any(|p| p.plane_id == plane_id) {
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(DrmModePlane {
+        count_formats: 0,
+        formats: std::ptr::null_mut(),
+        plane_id,
+        crtc_id: 0,
+        fb_id: 0,
+        crtc_x: 0,
+        crtc_y: 0,
+        x: 0,
+        y: 0,
+        possible_crtcs: 1,
+        gamma_size: 0,
+    }))
+}
+
+unsafe extern "C" fn fake_free_plane(plane: *mut DrmModePlane) {
+    if !plane.is_null() {
+        drop(Box::from_raw(plane));
+    }
+}
+
+unsafe extern "C" fn fake_get_object_properties(
+    fd: i32,
+    object_id: u32,
+    _object_type: u32,
+) -> *mut DrmModeObjectProperties {
+    let guard = device();
+    let Some(state) = guard.as_ref().filter(|s| s.fd == fd) else {
+        return std::ptr::null_mut();
+    };
+    let Some(plane) = state.planes.iter().find(|p| p.plane_id == object_id) else {
+        return std::ptr::null_mut();
+    };
+
+    let props: Box<[u32]> = plane.props.iter().map(|(id, _)| *id).collect();
+    let values: Box<[u64]> = plane.props.iter().map(|(_, value)| *value).collect();
+    Box::into_raw(Box::new(DrmModeObjectProperties {
+        count_props: props.len() as u32,
+        props: Box::into_raw(props) as *mut u32,
+        prop_values: Box::into_raw(values) as *mut u64,
+    }))
+}
+
+unsafe extern "C" fn fake_free_object_properties(props: *mut DrmModeObjectProperties) {
+    if props.is_null() {
+        return;
+    }
+    let props = Box::from_raw(props);
+    let count = props.count_props as usize;
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        props.props,
+        count,
+    )));
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        props.prop_values,
+        count,
+    )));
+}
+
+fn c_name(name: &str) -> [i8; 32] {
+    let mut out = [0i8; 32];
+    for (dst, src) in out.iter_mut().zip(name.bytes().take(31)) {
+        *dst = src as i8;
+    }
+    out
+}
+
+unsafe extern "C" fn fake_get_property(fd: i32, prop_id: u32) -> *mut DrmModePropertyRes {
+    let guard = device();
+    let Some(state) = guard.as_ref().filter(|s| s.fd == fd) else {
+        return std::ptr::null_mut();
+    };
+    let Some(name) = prop_id
+        .checked_sub(FIRST_PROP_ID)
+        .and_then(|idx| state.prop_names.get(idx as usize))
+    else {
+        return std::ptr::null_mut();
+    };
+
+    // Same enum values as the kernel uses
+    let enums: Box<[DrmModePropertyEnum]> = if *name == "pixel blend mode" {
+        [("None", 0), ("Pre-multiplied", 1), ("Coverage", 2)]
+            .iter()
+            .map(|(name, value)| DrmModePropertyEnum {
+                value: *value,
+                name: c_name(name),
+            })
+            .collect()
+    } else {
+        Box::new([])
+    };
+    Box::into_raw(Box::new(DrmModePropertyRes {
+        prop_id,
+        flags: 0,
+        name: c_name(name),
+        count_values: 0,
+        values: std::ptr::null_mut(),
+        count_enums: enums.len() as u32,
+        enums: Box::into_raw(enums) as *mut c_void,
+        count_blobs: 0,
+        blob_ids: std::ptr::null_mut(),
+    }))
+}
+
+unsafe extern "C" fn fake_free_property(prop: *mut DrmModePropertyRes) {
+    if prop.is_null() {
+        return;
+    }
+    let prop = Box::from_raw(prop);
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        prop.enums as *mut DrmModePropertyEnum,
+        prop.count_enums as usize,
+    )));
+}
+
+unsafe extern "C" fn fake_atomic_add(
+    req: *mut c_void,
+    object_id: u32,
+    property_id: u32,
+    value: u64,
+) -> i32 {
+    if let Some(state) = device().as_mut() {
+        if std::mem::take(&mut state.add_fails) {
+            return -libc::ENOMEM;
+        }
+        state.atomic.push(AtomicProperty {
+            req: req as usize,
+            object_id,
+            property_id,
+            value,
+        });
+        // libdrm returns the request's new cursor
+        return state
+            .atomic
+            .iter()
+            .filter(|p| p.req == req as usize)
+            .count() as i32;
+    }
+    0
+}
+
+/// Fails when a property ends up with a value the test rejected, going by the
+/// last value added like libdrm does
+unsafe extern "C" fn fake_atomic_commit(
+    _fd: i32,
+    req: *mut c_void,
+    _flags: u32,
+    _user_data: *mut c_void,
+) -> i32 {
+    let mut guard = device();
+    let Some(state) = guard.as_mut() else {
+        return -libc::ENODEV;
+    };
+    state.commits += 1;
+    if let Some(errno) = state.commit_errno.take() {
+        return -errno;
+    }
+    let props: Vec<AtomicProperty> = state
+        .atomic
+        .iter()
+        .filter(|p| p.req == req as usize)
+        .copied()
+        .collect();
+    for (idx, prop) in props.iter().enumerate() {
+        let overridden = props[idx + 1..]
+            .iter()
+            .any(|p| p.object_id == prop.object_id && p.property_id == prop.property_id);
+        if overridden {
+            continue;
+        }
+        let name = state.prop_names[(prop.property_id - FIRST_PROP_ID) as usize];
+        if state.rejected.contains(&(name, prop.value)) {
+            return -libc::EINVAL;
+        }
+    }
+    0
+}
+
+/// Requests are only ever compared, never read, so any unique address does
+unsafe extern "C" fn fake_atomic_alloc() -> *mut c_void {
+    match device().as_mut() {
+        Some(state) => (0x1000 + state.next_id() as usize) as *mut c_void,
+        None => std::ptr::null_mut(),
+    }
+}
+
+unsafe extern "C" fn fake_atomic_duplicate(old: *mut c_void) -> *mut c_void {
+    let new = fake_atomic_alloc();
+    if let Some(state) = device().as_mut() {
+        let copies: Vec<AtomicProperty> = state
+            .atomic
+            .iter()
+            .filter(|p| p.req == old as usize)
+            .map(|p| AtomicProperty {
+                req: new as usize,
+                ..*p
+            })
+            .collect();
+        state.atomic.extend(copies);
+    }
+    new
+}
+
+unsafe extern "C" fn fake_atomic_merge(base: *mut c_void, augment: *mut c_void) -> i32 {
+    if let Some(state) = device().as_mut() {
+        let added: Vec<AtomicProperty> = state
+            .atomic
+            .iter()
+            .filter(|p| p.req == augment as usize)
+            .map(|p| AtomicProperty {
+                req: base as usize,
+                ..*p
+            })
+            .collect();
+        state.atomic.extend(added);
+    }
+    0
+}
+
+unsafe extern "C" fn fake_atomic_set_cursor(req: *mut c_void, cursor: i32) {
+    if let Some(state) = device().as_mut() {
+        let mut kept = 0;
+        state.atomic.retain(|p| {
+            if p.req != req as usize {
+                return true;
+            }
+            kept += 1;
+            kept <= cursor
+        });
+    }
+}
+
+unsafe extern "C" fn fake_atomic_free(req: *mut c_void) {
+    if let Some(state) = device().as_mut() {
+        state.atomic.retain(|p| p.req != req as usize);
+    }
+}
+
+/// Put every global the library keeps back to how a fresh process has it,
+/// with config coming from the test instead of ~/.config
+unsafe fn reset_library_state() {
+    // Let a fade, animation or idle timer from the last test wind down first
+    CURSOR_VISIBILITY.store(Visibility::Shown as u32, Ordering::SeqCst);
+    ANIMATION_PLAYING.store(false, Ordering::SeqCst);
+    CONFIG_IDLE_HIDE_MS.store(0, Ordering::Relaxed);
+    for _ in 0..50 {
+        if !ANIMATION_THREAD_RUNNING.load(Ordering::SeqCst)
+            && !IDLE_THREAD_RUNNING.load(Ordering::SeqCst)
+        {
+            break;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    {
+        let _render = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if !CURSOR_BUFFER.is_null() {
+            let size =
+                CURSOR_WIDTH.load(Ordering::SeqCst) * CURSOR_HEIGHT.load(Ordering::SeqCst) * 4;
+            libc::munmap(CURSOR_BUFFER as *mut c_void, size as usize);
+            CURSOR_BUFFER = std::ptr::null_mut();
+        }
+        for mapping in primary_mappings().drain(..) {
+            libc::munmap(mapping.ptr, mapping.len);
+        }
+        PRIMARY_FB_BUFFER = std::ptr::null_mut();
+    }
+
+    INITIALIZED.store(false, Ordering::SeqCst);
+    CURSOR_HANDLE.store(0, Ordering::SeqCst);
+    CURSOR_FB_ID.store(0, Ordering::SeqCst);
+    CURSOR_FD.store(-1, Ordering::SeqCst);
+    CURSOR_WIDTH.store(256, Ordering::SeqCst);
+    CURSOR_HEIGHT.store(256, Ordering::SeqCst);
+    CURSOR_HOTSPOT_X.store(0, Ordering::SeqCst);
+    CURSOR_HOTSPOT_Y.store(0, Ordering::SeqCst);
+    CURSOR_SCREEN_X.store(0, Ordering::SeqCst);
+    CURSOR_SCREEN_Y.store(0, Ordering::SeqCst);
+    CURSOR_CRTC_ID.store(0, Ordering::SeqCst);
+    LEGACY_CURSOR_CRTC.store(0, Ordering::SeqCst);
+    CURSOR_BLEND_MODE.store(0, Ordering::SeqCst);
+    BLEND_MODE_ENUM_VALUES = [None; 3];
+
+    NUM_CURSOR_PLANES = 0;
+    CURSOR_PLANE_IDS = [0; 8];
+    CURSOR_FB_PROP_IDS = [0; 8];
+    CURSOR_SRC_W_PROP_IDS = [0; 8];
+    CURSOR_SRC_H_PROP_IDS = [0; 8];
+    CURSOR_CRTC_W_PROP_IDS = [0; 8];
+    CURSOR_CRTC_H_PROP_IDS = [0; 8];
+    CURSOR_CRTC_X_PROP_IDS = [0; 8];
+    CURSOR_CRTC_Y_PROP_IDS = [0; 8];
+    CURSOR_HOTSPOT_X_PROP_IDS = [0; 8];
+    CURSOR_HOTSPOT_Y_PROP_IDS = [0; 8];
+    CURSOR_BLEND_PROP_IDS = [0; 8];
+    CURSOR_CRTC_PROP_IDS = [0; 8];
+    CURSOR_PLANE_POSITIONS = [(0, 0); 8];
+    CURSOR_PLANE_CRTCS = [0; 8];
+    NUM_PRIMARY_PLANES = 0;
+    PRIMARY_PLANES = [PrimaryPlane::EMPTY; 8];
+    PRIMARY_FB_ID.store(0, Ordering::SeqCst);
+    UNMAPPABLE_FBS = [0; PRIMARY_MAPPING_CACHE];
+    COMPOSITOR_DUMB_HANDLES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    COMPOSITOR_DUMB_FBS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+
+    IDLE_HIDDEN.store(false, Ordering::SeqCst);
+    COMPOSITOR_HOTSPOT_X.store(0, Ordering::SeqCst);
+    COMPOSITOR_HOTSPOT_Y.store(0, Ordering::SeqCst);
+    COMPOSITOR_HOTSPOT_KNOWN.store(false, Ordering::SeqCst);
+    COMPOSITOR_CURSOR_SEEN.store(false, Ordering::SeqCst);
+    COMPOSITOR_MOVE_MS.store(0, Ordering::SeqCst);
+    COMPOSITOR_SHAPES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    COMPOSITOR_BUFFER = 0;
+    COMPOSITOR_SHAPE_KEY = 0;
+    SHAPE_CHANGE = None;
+    reset_stats();
+    HOOKS_SEEN.store(0, Ordering::Relaxed);
+    reset_buffer_retries();
+    *BUFFER_ERROR.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    DOCTOR_STARTED.store(false, Ordering::SeqCst);
+    *DOCTOR_PROBE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    CURSOR_PLANE_REJECTS = [0; 8];
+    CURSOR_PLANE_GIVEN_UP = [false; 8];
+    ATOMIC_REQUESTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+
+    CONFIG_LOADED.store(true, Ordering::Relaxed);
+    CONFIG_POLLING_ENABLED.store(false, Ordering::Relaxed);
+    CURSOR_FADE_CHECKED.store(true, Ordering::Relaxed);
+    CURSOR_FADE_ENABLED.store(false, Ordering::Relaxed);
+    CONFIG_FADE_ENABLED.store(false, Ordering::Relaxed);
+    CONFIG_FADE_IN_ENABLED.store(false, Ordering::Relaxed);
+    CONFIG_FADE_SPEED.store(30, Ordering::Relaxed);
+    CONFIG_FADE_EASING.store(FadeEasing::EaseOut as u32, Ordering::Relaxed);
+    CONFIG_IDLE_HIDE_MS.store(0, Ordering::Relaxed);
+    CONFIG_FROST_INTENSITY.store(0, Ordering::Relaxed);
+    CONFIG_ADAPTIVE_MODE.store(AdaptiveMode::Off as u32, Ordering::Relaxed);
+    CONFIG_ADAPTIVE_THRESHOLD.store(50, Ordering::Relaxed);
+    BACKDROP_CLASS.store(Backdrop::Unknown as u32, Ordering::SeqCst);
+    CURSOR_SAMPLES_BACKDROP.store(false, Ordering::SeqCst);
+    CONFIG_XCURSOR_THEME
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    CONFIG_EXPORT_DIR
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    // Our own control files, not the ones a live session polls
+    let control_dir =
+        std::env::temp_dir().join(format!("constellation_control_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&control_dir);
+    let _ = std::fs::create_dir_all(&control_dir);
+    std::env::set_var("CONSTELLATION_CURSOR_CONTROL_DIR", &control_dir);
+    EXPORT_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+    REFRESH_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+    CURSOR_TYPE_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+    forget_custom_design();
+    std::env::set_var("CONSTELLATION_CURSOR_TYPE", "default");
+    std::env::set_var("CONSTELLATION_CURSOR_SCALE", "1.5");
+
+    REAL_GET_PLANE = Some(fake_get_plane);
+    REAL_FREE_PLANE = Some(fake_free_plane);
+    REAL_GET_OBJECT_PROPERTIES = Some(fake_get_object_properties);
+    REAL_FREE_OBJECT_PROPERTIES = Some(fake_free_object_properties);
+    REAL_GET_PROPERTY = Some(fake_get_property);
+    REAL_FREE_PROPERTY = Some(fake_free_property);
+    REAL_ATOMIC_ADD = Some(fake_atomic_add);
+    REAL_ATOMIC_ALLOC = Some(fake_atomic_alloc);
+    REAL_ATOMIC_DUPLICATE = Some(fake_atomic_duplicate);
+    REAL_ATOMIC_MERGE = Some(fake_atomic_merge);
+    REAL_ATOMIC_SET_CURSOR = Some(fake_atomic_set_cursor);
+    REAL_ATOMIC_COMMIT = Some(fake_atomic_commit);
+    REAL_ATOMIC_FREE = Some(fake_atomic_free);
+}
+
+/// Test lock with library state back at defaults, for tests with no device
+pub(crate) fn lock() -> MutexGuard<'static, ()> {
+    let lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe {
+        reset_library_state();
+    }
+    lock
+}
+
+/// A fake GPU for one test. Holds the test lock until dropped.
+pub(crate) struct FakeDevice {
+    pub fd: i32,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl FakeDevice {
+    pub fn open() -> FakeDevice {
+        let lock = lock();
+        let fd = unsafe { libc::memfd_create(c"fake-drm".as_ptr(), 0) };
+        assert!(fd >= 0, "memfd_create failed");
+
+        *device() = Some(DeviceState {
+            fd,
+            ..Default::default()
+        });
+        FakeDevice { fd, _lock: lock }
+    }
+
+    /// Add a plane with the given properties, `type` is set from `kind`
+    pub fn add_plane(&self, kind: PlaneKind, props: &[(&'static str, u64)]) -> u32 {
+        let mut guard = device();
+        let state = guard.as_mut().unwrap();
+        let plane_id = state.next_id();
+        let mut values = vec![(state.prop_id("type"), kind as u64)];
+        for (name, value) in props {
+            values.push((state.prop_id(name), *value));
+        }
+        state.planes.push(Plane {
+            plane_id,
+            props: values,
+        });
+        plane_id
+    }
+
+    /// A cursor plane with everything compositors set on one
+    pub fn add_cursor_plane(&self) -> u32 {
+        self.add_plane(
+            PlaneKind::Cursor,
+            &[
+                ("FB_ID", 0),
+                ("CRTC_ID", 0),
+                ("CRTC_X", 0),
+                ("CRTC_Y", 0),
+                ("CRTC_W", 0),
+                ("CRTC_H", 0),
+                ("SRC_W", 0),
+                ("SRC_H", 0),
+            ],
+        )
+    }
+
+    pub fn add_crtc(&self, crtc_id: u32) {
+        device().as_mut().unwrap().crtcs.push(crtc_id);
+    }
+
+    /// Make an ioctl fail from now on, or work again
+    pub fn set_failing(&self, request: libc::c_ulong, failing: bool) {
+        let mut guard = device();
+        let state = guard.as_mut().unwrap();
+        state.failing.retain(|r| *r != request);
+        if failing {
+            state.failing.push(request);
+        }
+    }
+
+    pub fn dumb_buffers(&self) -> usize {
+        device().as_ref().unwrap().buffers.len()
+    }
+
+    /// Look the plane up like a compositor does, which is when we scan it
+    pub fn get_plane(&self, plane_id: u32) {
+        unsafe {
+            let plane = drmModeGetPlane(self.fd, plane_id);
+            assert!(!plane.is_null(), "no plane {}", plane_id);
+            fake_free_plane(plane);
+        }
+    }
+
+    pub fn prop_id(&self, name: &'static str) -> u32 {
+        device().as_mut().unwrap().prop_id(name)
+    }
+
+    /// A compositor side dumb buffer with a linear ARGB framebuffer on it
+    pub fn add_framebuffer(&self, width: u32, height: u32, pixels: &[u32]) -> u32 {
+        self.add_framebuffer_with(width, height, pixels, Some(DRM_FORMAT_MOD_LINEAR))
+    }
+
+    /// Like `add_framebuffer`, with whatever modifier ADDFB2 would have
+    /// been given, None for none at all
+    pub fn add_framebuffer_with(
+        &self,
+        width: u32,
+        height: u32,
+        pixels: &[u32],
+        modifier: Option<u64>,
+    ) -> u32 {
+        let mut guard = device();
+        let state = guard.as_mut().unwrap();
+        let (handle, offset, pitch) = {
+            let buffer = state.create_dumb(width, height, 32);
+            (buffer.handle, buffer.offset, buffer.pitch)
+        };
+        let fb_id = state.fb_id();
+        state.framebuffers.push(Framebuffer {
+            fb_id,
+            handle,
+            format: DRM_FORMAT_ARGB8888,
+            modifier,
+        });
+
+        for (row, line) in pixels
+            .chunks(width as usize)
+            .enumerate()
+            .take(height as usize)
+        {
+            let bytes: Vec<u8> = line.iter().flat_map(|p| p.to_le_bytes()).collect();
+            let at = offset + row as u64 * pitch as u64;
+            unsafe {
+                libc::pwrite(
+                    self.fd,
+                    bytes.as_ptr() as *const c_void,
+                    bytes.len(),
+                    at as i64,
+                );
+            }
+        }
+        fb_id
+    }
+
+    /// Remove a framebuffer the way a compositor does, through the hook
+    pub fn remove_framebuffer(&self, fb_id: u32) {
+        let mut fb_id = fb_id;
+        let ret = unsafe {
+            crate::ioctl(
+                self.fd,
+                DRM_IOCTL_MODE_RMFB,
+                &mut fb_id as *mut _ as *mut c_void,
+            )
+        };
+        assert_eq!(ret, 0, "no FB {}", fb_id);
+    }
+
+    /// The GEM handle behind a framebuffer made with `add_framebuffer`
+    pub fn handle_of(&self, fb_id: u32) -> u32 {
+        let guard = device();
+        let state = guard.as_ref().unwrap();
+        state
+            .framebuffers
+            .iter()
+            .find(|f| f.fb_id == fb_id)
+            .map_or(0, |f| f.handle)
+    }
+
+    /// Add a property the way a compositor does, returns what libdrm got
+    pub fn atomic_add(&self, plane_id: u32, name: &'static str, value: u64) -> Option<u64> {
+        self.atomic_add_to(std::ptr::null_mut(), plane_id, name, value)
+    }
+
+    /// `atomic_add` into a request from `atomic_request`
+    pub fn atomic_add_to(
+        &self,
+        req: *mut c_void,
+        plane_id: u32,
+        name: &'static str,
+        value: u64,
+    ) -> Option<u64> {
+        let property_id = self.prop_id(name);
+        let before = device().as_ref().unwrap().atomic.len();
+        unsafe {
+            drmModeAtomicAddProperty(req, plane_id, property_id, value);
+        }
+        let guard = device();
+        let state = guard.as_ref().unwrap();
+        state.atomic[before..]
+            .iter()
+            .rev()
+            .find(|p| p.object_id == plane_id && p.property_id == property_id)
+            .map(|p| p.value)
+    }
+
+    /// Move the plane in a request of its own and commit it, returns the
+    /// position libdrm got
+    pub fn atomic_move(&self, plane_id: u32, x: i32, y: i32) -> (i32, i32) {
+        let req = self.atomic_request();
+        let x = self.atomic_add_to(req, plane_id, "CRTC_X", x as u64);
+        let y = self.atomic_add_to(req, plane_id, "CRTC_Y", y as u64);
+        assert_eq!(self.atomic_commit(req), 0);
+        self.atomic_free(req);
+        (x.unwrap() as i32, y.unwrap() as i32)
+    }
+
+    /// A new drmModeAtomicReq, allocated through the hook
+    pub fn atomic_request(&self) -> *mut c_void {
+        unsafe { drmModeAtomicAlloc() }
+    }
+
+    pub fn atomic_duplicate(&self, req: *mut c_void) -> *mut c_void {
+        unsafe { drmModeAtomicDuplicate(req) }
+    }
+
+    pub fn atomic_merge(&self, base: *mut c_void, augment: *mut c_void) {
+        assert_eq!(unsafe { drmModeAtomicMerge(base, augment) }, 0);
+    }
+
+    pub fn atomic_set_cursor(&self, req: *mut c_void, cursor: i32) {
+        unsafe { drmModeAtomicSetCursor(req, cursor) }
+    }
+
+    /// Commit a request through the hook like a compositor does
+    pub fn atomic_commit(&self, req: *mut c_void) -> i32 {
+        unsafe { drmModeAtomicCommit(self.fd, req, 0, std::ptr::null_mut()) }
+    }
+
+    /// A TEST_ONLY commit of a request
+    pub fn atomic_test(&self, req: *mut c_void) -> i32 {
+        unsafe {
+            drmModeAtomicCommit(
+                self.fd,
+                req,
+                DRM_MODE_ATOMIC_TEST_ONLY,
+                std::ptr::null_mut(),
+            )
+        }
+    }
+
+    pub fn fail_next_add(&self) {
+        device().as_mut().unwrap().add_fails = true;
+    }
+
+    /// Fail the next commit with `errno`, like a flip still pending does
+    pub fn fail_next_commit(&self, errno: i32) {
+        device().as_mut().unwrap().commit_errno = Some(errno);
+    }
+
+    pub fn atomic_free(&self, req: *mut c_void) {
+        unsafe { drmModeAtomicFree(req) }
+    }
+
+    /// Make commits fail while `name` is set to `value`, like a driver that
+    /// doesn't take some buffer
+    pub fn reject_property(&self, name: &'static str, value: u64) {
+        device().as_mut().unwrap().rejected.push((name, value));
+    }
+
+    /// Commits that reached the driver, retries included
+    pub fn commits(&self) -> u32 {
+        device().as_ref().unwrap().commits
+    }
+
+    pub fn last_cursor_call(&self) -> Option<CursorCall> {
+        device().as_ref().unwrap().cursor_calls.last().copied()
+    }
+
+    pub fn dirty_fbs(&self) -> Vec<u32> {
+        device().as_ref().unwrap().dirty_fbs.clone()
+    }
+
+    /// What our cursor buffer holds right now
+    pub fn cursor_pixels(&self) -> Vec<u32> {
+        let _render = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        unsafe {
+            if CURSOR_BUFFER.is_null() {
+                return Vec::new();
+            }
+            let len = (CURSOR_WIDTH.load(Ordering::SeqCst) * CURSOR_HEIGHT.load(Ordering::SeqCst))
+                as usize;
+            std::slice::from_raw_parts(CURSOR_BUFFER, len).to_vec()
+        }
+    }
+
+    /// Wait for the animation thread to get somewhere, false on timeout
+    pub fn wait_until(&self, timeout_ms: u64, done: impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(timeout_ms) {
+            if done() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        done()
+    }
+}
+
+impl Drop for FakeDevice {
+    fn drop(&mut self) {
+        unsafe {
+            reset_library_state();
+            *device() = None;
+            libc::close(self.fd);
+        }
+    }
+}
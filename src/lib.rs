@@ -96,14 +96,115 @@
 //! This enables applications to signal cursor changes without compositor integration.
 
 use std::ffi::c_void;
+use std::hash::{Hash, Hasher};
 use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-static DEBUG: AtomicBool = AtomicBool::new(false);
-static DEBUG_CHECKED: AtomicBool = AtomicBool::new(false);
+// =============================================================================
+// Logging
+// =============================================================================
+//
+// Every message has a level and the subsystem it's about, each subsystem has
+// its own threshold. Set them with a spec like "warn,drm=debug,fade=trace":
+// a bare level applies to everything, sub=level to one subsystem.
+// CONSTELLATION_CURSOR_LOG wins over the log_level config key,
+// CONSTELLATION_CURSOR_DEBUG=1 is short for "debug".
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u32)]
+enum LogLevel {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl LogLevel {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "off" | "none" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "OFF",
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+enum LogSubsystem {
+    /// Hooks, planes, buffers and properties
+    Drm = 0,
+    /// Drawing cursors, themes, animations, exports
+    Render = 1,
+    /// cursor.conf and the control files in /tmp
+    Config = 2,
+    /// Visibility: fades and idle hiding
+    Fade = 3,
+}
+
+impl LogSubsystem {
+    const ALL: [LogSubsystem; 4] = [
+        LogSubsystem::Drm,
+        LogSubsystem::Render,
+        LogSubsystem::Config,
+        LogSubsystem::Fade,
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        LogSubsystem::ALL
+            .into_iter()
+            .find(|s| s.name() == name.trim().to_lowercase())
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogSubsystem::Drm => "drm",
+            LogSubsystem::Render => "render",
+            LogSubsystem::Config => "config",
+            LogSubsystem::Fade => "fade",
+        }
+    }
+}
+
+const DEFAULT_LOG_LEVEL: LogLevel = LogLevel::Warn;
+
+/// Threshold per LogSubsystem
+static LOG_LEVELS: [AtomicU32; 4] = [
+    AtomicU32::new(DEFAULT_LOG_LEVEL as u32),
+    AtomicU32::new(DEFAULT_LOG_LEVEL as u32),
+    AtomicU32::new(DEFAULT_LOG_LEVEL as u32),
+    AtomicU32::new(DEFAULT_LOG_LEVEL as u32),
+];
+static LOG_ENV_CHECKED: AtomicBool = AtomicBool::new(false);
+// Set when the environment picked the levels, the config doesn't get a say then
+static LOG_LEVELS_FROM_ENV: AtomicBool = AtomicBool::new(false);
+static LOG_FILE_ENABLED: AtomicBool = AtomicBool::new(false);
+static LOG_FILE: Mutex<Option<std::fs::File>> = Mutex::new(None);
+// The log file is renamed to cursor.log.old past this size
+const LOG_FILE_MAX_BYTES: u64 = 4 * 1024 * 1024;
+// Size of the open log file, a trace session writes a lot
+static LOG_FILE_BYTES: AtomicU64 = AtomicU64::new(0);
 static VERSION_PRINTED: AtomicBool = AtomicBool::new(false);
 
 fn print_version_info() {
@@ -120,27 +221,199 @@ fn print_version_info() {
     eprintln!("    drmModeMoveCursor         cursor position update");
     eprintln!("    drmModeGetPlane           cursor plane detection");
     eprintln!("    drmModeAtomicAddProperty  FB_ID replacement");
+    eprintln!("    drmModeAtomicAlloc        start tracking a request");
+    eprintln!("    drmModeAtomicDuplicate    copy what we know of a request");
+    eprintln!("    drmModeAtomicMerge        follow requests merged together");
+    eprintln!("    drmModeAtomicSetCursor    drop properties rolled back");
+    eprintln!("    drmModeAtomicCommit       cursor moves, retry without our cursor on failure");
+    eprintln!("    drmModeAtomicFree         forget a request");
     eprintln!();
     eprintln!("  Environment variables:");
-    eprintln!("    CONSTELLATION_CURSOR_DEBUG=1  verbose logging");
-    eprintln!("    CONSTELLATION_CURSOR_INFO=1   show this info");
-    eprintln!("    CONSTELLATION_CURSOR_FADE=1   fade out when hiding");
+    eprintln!("    CONSTELLATION_CURSOR_LOG=debug  log level, per subsystem: warn,drm=trace");
+    eprintln!("    CONSTELLATION_CURSOR_DEBUG=1    same as CONSTELLATION_CURSOR_LOG=debug");
+    eprintln!("    CONSTELLATION_CURSOR_INFO=1     show this info");
+    eprintln!("    CONSTELLATION_CURSOR_FADE=1     fade out when hiding");
+    eprintln!(
+        "    CONSTELLATION_CURSOR_DOCTOR=1   write {}",
+        control_path("doctor.txt")
+    );
+    eprintln!("    CONSTELLATION_CURSOR_CONTROL_DIR=<dir>  control files there instead of /tmp");
     eprintln!();
 }
 
-fn debug_enabled() -> bool {
-    if !DEBUG_CHECKED.load(Ordering::Relaxed) {
-        let debug = std::env::var("CONSTELLATION_CURSOR_DEBUG").is_ok();
-        let info = std::env::var("CONSTELLATION_CURSOR_INFO").is_ok();
+/// Apply a level spec ("info", "warn,drm=trace"), unknown parts are ignored
+fn apply_log_spec(spec: &str) {
+    for part in spec.split(',') {
+        match part.split_once('=') {
+            Some((subsystem, level)) => {
+                if let (Some(subsystem), Some(level)) = (
+                    LogSubsystem::from_name(subsystem),
+                    LogLevel::from_name(level),
+                ) {
+                    LOG_LEVELS[subsystem as usize].store(level as u32, Ordering::Relaxed);
+                }
+            }
+            None => {
+                if let Some(level) = LogLevel::from_name(part) {
+                    for threshold in &LOG_LEVELS {
+                        threshold.store(level as u32, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Read the log environment variables, once
+fn check_log_env() {
+    if LOG_ENV_CHECKED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let debug = std::env::var("CONSTELLATION_CURSOR_DEBUG").is_ok();
+    if let Ok(spec) = std::env::var("CONSTELLATION_CURSOR_LOG") {
+        apply_log_spec(&spec);
+        LOG_LEVELS_FROM_ENV.store(true, Ordering::Relaxed);
+    } else if debug {
+        apply_log_spec("debug");
+        LOG_LEVELS_FROM_ENV.store(true, Ordering::Relaxed);
+    }
+
+    if debug || std::env::var("CONSTELLATION_CURSOR_INFO").is_ok() {
+        print_version_info();
+    }
+}
+
+/// log_level from cursor.conf, starting over from the default
+fn apply_config_log_level(spec: &str) {
+    check_log_env();
+    if LOG_LEVELS_FROM_ENV.load(Ordering::Relaxed) {
+        return;
+    }
+    apply_log_spec(&DEFAULT_LOG_LEVEL.name().to_lowercase());
+    apply_log_spec(spec);
+}
+
+fn log_enabled(level: LogLevel, subsystem: LogSubsystem) -> bool {
+    check_log_env();
+    level as u32 <= LOG_LEVELS[subsystem as usize].load(Ordering::Relaxed)
+}
+
+fn log_file_path() -> Option<std::path::PathBuf> {
+    let state = match std::env::var("XDG_STATE_HOME") {
+        Ok(dir) if !dir.is_empty() => std::path::PathBuf::from(dir),
+        _ => std::path::PathBuf::from(std::env::var("HOME").ok()?).join(".local/state"),
+    };
+    Some(state.join("constellation_cursor/cursor.log"))
+}
+
+fn open_log_file() -> Option<std::fs::File> {
+    let path = log_file_path()?;
+    std::fs::create_dir_all(path.parent()?).ok()?;
+    if std::fs::metadata(&path).is_ok_and(|m| m.len() > LOG_FILE_MAX_BYTES) {
+        let _ = std::fs::rename(&path, path.with_extension("log.old"));
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .ok()?;
+    let size = file.metadata().map_or(0, |m| m.len());
+    LOG_FILE_BYTES.store(size, Ordering::Relaxed);
+    Some(file)
+}
+
+/// Send a message that passed the filter to stderr and the log file. With
+/// the file on, stderr only gets warnings and errors to keep compositor
+/// logs quiet.
+fn write_log(level: LogLevel, subsystem: LogSubsystem, message: &str) {
+    let line = format!("{} {}: {}", level.name(), subsystem.name(), message);
+    if !LOG_FILE_ENABLED.load(Ordering::Relaxed) {
+        eprintln!("[constellation-cursor] {}", line);
+        return;
+    }
+    if level <= LogLevel::Warn {
+        eprintln!("[constellation-cursor] {}", line);
+    }
+
+    use std::io::Write;
+    let mut file = LOG_FILE.lock().unwrap_or_else(|e| e.into_inner());
+    if file.is_none() {
+        *file = open_log_file();
+        if file.is_none() {
+            LOG_FILE_ENABLED.store(false, Ordering::Relaxed);
+            eprintln!(
+                "[constellation-cursor] WARN config: can't open the log file, logging to stderr"
+            );
+            return;
+        }
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let entry = format!("{}.{:03} {}\n", now.as_secs(), now.subsec_millis(), line);
+    if let Some(handle) = file.as_mut() {
+        let _ = handle.write_all(entry.as_bytes());
+    }
+    // Reopening rotates it
+    let size = LOG_FILE_BYTES.fetch_add(entry.len() as u64, Ordering::Relaxed);
+    if size + entry.len() as u64 > LOG_FILE_MAX_BYTES {
+        *file = None;
+    }
+}
+
+/// Per call site limit for `log_limited!`
+struct LogRateLimit {
+    last_ms: AtomicU64,
+    dropped: AtomicU32,
+}
+
+impl LogRateLimit {
+    const INTERVAL_MS: u64 = 1000;
 
-        if debug || info {
-            print_version_info();
+    const fn new() -> Self {
+        LogRateLimit {
+            last_ms: AtomicU64::new(u64::MAX),
+            dropped: AtomicU32::new(0),
         }
+    }
 
-        DEBUG.store(debug, Ordering::Relaxed);
-        DEBUG_CHECKED.store(true, Ordering::Relaxed);
+    /// Some(messages dropped since the last one) when this one may go out
+    fn allow(&self) -> Option<u32> {
+        let now = monotonic_ms();
+        let last = self.last_ms.load(Ordering::Relaxed);
+        if last != u64::MAX && now.saturating_sub(last) < Self::INTERVAL_MS {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.last_ms.store(now, Ordering::Relaxed);
+        Some(self.dropped.swap(0, Ordering::Relaxed))
     }
-    DEBUG.load(Ordering::Relaxed)
+}
+
+/// `log!(Debug, Drm, "format", args..)`
+macro_rules! log {
+    ($level:ident, $subsystem:ident, $($arg:tt)*) => {
+        if log_enabled(LogLevel::$level, LogSubsystem::$subsystem) {
+            write_log(LogLevel::$level, LogSubsystem::$subsystem, &format!($($arg)*));
+        }
+    };
+}
+
+/// `log!` for hot paths (every commit, every frame): one message a second
+/// from each call site, the next one that gets through says how many didn't
+macro_rules! log_limited {
+    ($level:ident, $subsystem:ident, $($arg:tt)*) => {
+        if log_enabled(LogLevel::$level, LogSubsystem::$subsystem) {
+            static LIMIT: LogRateLimit = LogRateLimit::new();
+            if let Some(dropped) = LIMIT.allow() {
+                let mut message = format!($($arg)*);
+                if dropped > 0 {
+                    message.push_str(&format!(" ({} more like this suppressed)", dropped));
+                }
+                write_log(LogLevel::$level, LogSubsystem::$subsystem, &message);
+            }
+        }
+    };
 }
 
 // The Constellation cursor has a configuration in .config
@@ -150,8 +423,7 @@ fn debug_enabled() -> bool {
 ///   fade_enabled=true
 ///   fade_speed=30
 ///   frost_intensity=100
-///   hotspot_smoothing=true
-///   hotspot_threshold=5
+///   idle_hide_ms=3000
 fn load_config() {
     if CONFIG_LOADED.load(Ordering::Relaxed) {
         return;
@@ -181,26 +453,53 @@ cursor_scale=1.5
 # outline_thickness=0
 
 # Enable fade-out effect when cursor hides (runs in background thread)
-# (Buggy)
 fade_enabled=false
 
 # Enable fade-in effect when cursor appears
-# (Buggy)
 fade_in_enabled=false
 
-# Fade speed (1-255, higher = faster fade)
+# Fade speed (1-255, higher = faster fade, 30 is about 140ms)
 fade_speed=30
 
+# Fade curve: linear, ease-in, ease-out or ease-in-out
+fade_easing=ease-out
+
+# Hide the cursor after this many ms without moving (0 = never)
+# Fades out when fade_enabled is set, the next move brings it back
+idle_hide_ms=0
+
 # Frosted glass intensity (0-100)
-# (Doesn't look great at the moment)
+# Blurs the screen under passthrough layers when the primary plane is a
+# linear buffer we can map, falls back to a noise pattern otherwise
 frost_intensity=0
 
-# Smooth hotspot transitions between cursor types
-# For positional syncing, is likely to cause issues if not needed
-hotspot_smoothing=false
+# Filter used for layer blur: gaussian (soft) or box (cheaper, harder falloff)
+blur_filter=gaussian
 
-# Threshold for hotspot change detection (pixels)
-hotspot_threshold=0
+# Keep the cursor visible on backgrounds of the same color
+# off, variant (invert the cursor) or outline (add a contrasting outline)
+adaptive_mode=off
+
+# Background lightness (0-100) where the adaptive mode flips
+adaptive_threshold=50
+
+# Take cursors (animated ones included) from an XCursor theme
+# Empty = draw our own, auto = follow $XCURSOR_THEME
+xcursor_theme=
+
+# Which cursor types come from the theme, comma separated or all
+xcursor_types=wait
+
+# Where touch /tmp/constellation_cursor_export writes its PNG
+export_dir=/tmp
+
+# What to log: error, warn, info, debug, trace or off, optionally per
+# subsystem (drm, render, config, fade), e.g. warn,drm=debug
+log_level=warn
+
+# Log to ~/.local/state/constellation_cursor/cursor.log ($XDG_STATE_HOME),
+# stderr then only gets warnings and errors
+log_file=false
 
 # --- Config Hot-Reload Settings ---
 # The cursor library can automatically detect when this file changes.
@@ -246,18 +545,18 @@ config_poll_interval=50
                         CONFIG_FADE_SPEED.store(speed.clamp(1, 255), Ordering::Relaxed);
                     }
                 }
-                "frost_intensity" => {
-                    if let Ok(intensity) = value.parse::<u32>() {
-                        CONFIG_FROST_INTENSITY.store(intensity.clamp(0, 100), Ordering::Relaxed);
+                "idle_hide_ms" => {
+                    if let Ok(ms) = value.parse::<u32>() {
+                        CONFIG_IDLE_HIDE_MS.store(ms, Ordering::Relaxed);
                     }
                 }
-                "hotspot_smoothing" => {
-                    let enabled = value == "true" || value == "1";
-                    CONFIG_HOTSPOT_SMOOTHING.store(enabled, Ordering::Relaxed);
+                "fade_easing" => {
+                    let easing = FadeEasing::from_name(value);
+                    CONFIG_FADE_EASING.store(easing as u32, Ordering::Relaxed);
                 }
-                "hotspot_threshold" => {
-                    if let Ok(threshold) = value.parse::<i32>() {
-                        CONFIG_HOTSPOT_THRESHOLD.store(threshold.clamp(0, 50), Ordering::Relaxed);
+                "frost_intensity" => {
+                    if let Ok(intensity) = value.parse::<u32>() {
+                        CONFIG_FROST_INTENSITY.store(intensity.clamp(0, 100), Ordering::Relaxed);
                     }
                 }
                 "cursor_scale" => {
@@ -274,6 +573,45 @@ config_poll_interval=50
                         CONFIG_OUTLINE_THICKNESS.store(thickness_int, Ordering::Relaxed);
                     }
                 }
+                "blur_filter" => {
+                    let filter = match value {
+                        "box" => BlurFilter::Box,
+                        _ => BlurFilter::Gaussian,
+                    };
+                    CONFIG_BLUR_FILTER.store(filter as u32, Ordering::Relaxed);
+                }
+                "adaptive_mode" => {
+                    let mode = AdaptiveMode::from_name(value);
+                    CONFIG_ADAPTIVE_MODE.store(mode as u32, Ordering::Relaxed);
+                }
+                "xcursor_theme" => {
+                    *CONFIG_XCURSOR_THEME
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner()) = value.to_string();
+                }
+                "export_dir" => {
+                    *CONFIG_EXPORT_DIR.lock().unwrap_or_else(|e| e.into_inner()) =
+                        value.to_string();
+                }
+                "xcursor_types" => {
+                    let mask = value.split(',').fold(0u32, |mask, name| match name.trim() {
+                        "all" => u32::MAX,
+                        name => {
+                            CursorType::from_name(name).map_or(mask, |t| mask | (1 << t.as_u32()))
+                        }
+                    });
+                    CONFIG_XCURSOR_TYPES.store(mask, Ordering::Relaxed);
+                }
+                "adaptive_threshold" => {
+                    if let Ok(threshold) = value.parse::<u32>() {
+                        CONFIG_ADAPTIVE_THRESHOLD.store(threshold.clamp(0, 100), Ordering::Relaxed);
+                    }
+                }
+                "log_level" => apply_config_log_level(value),
+                "log_file" => {
+                    let enabled = value == "true" || value == "1";
+                    LOG_FILE_ENABLED.store(enabled, Ordering::Relaxed);
+                }
                 "config_polling" => {
                     let enabled = value == "true" || value == "1";
                     CONFIG_POLLING_ENABLED.store(enabled, Ordering::Relaxed);
@@ -308,7 +646,7 @@ unsafe fn check_config_changed() -> bool {
 
     let interval = CONFIG_POLL_INTERVAL.load(Ordering::Relaxed).max(1);
     let counter = CONFIG_CHECK_COUNTER.fetch_add(1, Ordering::Relaxed);
-    if counter % interval != 0 {
+    if !counter.is_multiple_of(interval) {
         return false;
     }
 
@@ -334,9 +672,8 @@ unsafe fn check_config_changed() -> bool {
 
     let last_mtime = CONFIG_LAST_MTIME.load(Ordering::Relaxed);
     if current_mtime > last_mtime && last_mtime > 0 {
-        if DEBUG.load(Ordering::Relaxed) {
-            eprintln!("[constellation-cursor] Config file changed, reloading...");
-        }
+        log!(Info, Config, "Config file changed, reloading");
+        STAT_CONFIG_RELOADS.fetch_add(1, Ordering::Relaxed);
         CONFIG_LOADED.store(false, Ordering::Relaxed);
         CURSOR_FADE_CHECKED.store(false, Ordering::Relaxed);
         load_config();
@@ -366,22 +703,34 @@ fn cursor_fade_enabled() -> bool {
     CURSOR_FADE_ENABLED.load(Ordering::Relaxed)
 }
 
-macro_rules! debug_print {
-    ($($arg:tt)*) => {
-        if debug_enabled() {
-            eprintln!("[constellation-cursor] {}", format!($($arg)*));
-        }
-    };
-}
-
 // DRM ioctl codes
 const DRM_IOCTL_MODE_CURSOR: libc::c_ulong = 0xC01C64A3;
 const DRM_IOCTL_MODE_CURSOR2: libc::c_ulong = 0xC03064BB;
 const DRM_IOCTL_MODE_CREATE_DUMB: libc::c_ulong = 0xC02064B2;
 const DRM_IOCTL_MODE_MAP_DUMB: libc::c_ulong = 0xC01064B3;
 const DRM_IOCTL_MODE_DESTROY_DUMB: libc::c_ulong = 0xC00464B4;
+const DRM_IOCTL_MODE_ADDFB: libc::c_ulong = 0xC01C64AE;
 const DRM_IOCTL_MODE_ADDFB2: libc::c_ulong = 0xC04064B8;
-
+const DRM_IOCTL_MODE_RMFB: libc::c_ulong = 0xC00464AF;
+const DRM_IOCTL_MODE_DIRTYFB: libc::c_ulong = 0xC01864B1;
+
+const DRM_IOCTL_MODE_GETCRTC: libc::c_ulong = 0xC06864A1;
+const DRM_IOCTL_MODE_GETFB2: libc::c_ulong = 0xC06864CE;
+const DRM_IOCTL_GEM_CLOSE: libc::c_ulong = 0x40086409;
+const DRM_IOCTL_MODE_ATOMIC: libc::c_ulong = 0xC03864BC;
+
+// Only the doctor asks these
+const DRM_IOCTL_VERSION: libc::c_ulong = 0xC0406400;
+const DRM_IOCTL_GET_CAP: libc::c_ulong = 0xC010640C;
+const DRM_IOCTL_MODE_GETRESOURCES: libc::c_ulong = 0xC04064A0;
+const DRM_IOCTL_MODE_GETPLANERESOURCES: libc::c_ulong = 0xC01064B5;
+const DRM_IOCTL_MODE_GETPLANE: libc::c_ulong = 0xC02064B6;
+
+const DRM_CAP_DUMB_BUFFER: u64 = 0x1;
+const DRM_CAP_CURSOR_WIDTH: u64 = 0x8;
+const DRM_CAP_CURSOR_HEIGHT: u64 = 0x9;
+
+const DRM_PLANE_TYPE_PRIMARY: u64 = 1;
 const DRM_PLANE_TYPE_CURSOR: u64 = 2;
 
 // global state fort the cursor buffer
@@ -402,37 +751,73 @@ static CURRENT_CURSOR_TYPE: AtomicU32 = AtomicU32::new(0);
 static CURSOR_HOTSPOT_X: AtomicI32 = AtomicI32::new(0);
 static CURSOR_HOTSPOT_Y: AtomicI32 = AtomicI32::new(0);
 
-// applied hotspot, what we actually sent to DRM (for silky smoothing)
-static APPLIED_HOTSPOT_X: AtomicI32 = AtomicI32::new(0);
-static APPLIED_HOTSPOT_Y: AtomicI32 = AtomicI32::new(0);
-static HOTSPOT_INITIALIZED: AtomicBool = AtomicBool::new(false);
-
-// Cursor fade state (Still looks... well, it escapes mothers love)
-static CURSOR_FADING_OUT: AtomicBool = AtomicBool::new(false);
-static CURSOR_FADING_IN: AtomicBool = AtomicBool::new(false);
-static CURSOR_FADE_ALPHA: AtomicU32 = AtomicU32::new(255);
-static CURSOR_HIDDEN: AtomicBool = AtomicBool::new(false);
-static CURSOR_VISIBLE: AtomicBool = AtomicBool::new(true);
+// Hotspot of the compositor's own cursor image, the one its positions assume.
+// Only trusted once known, until then positions pass through as is.
+static COMPOSITOR_HOTSPOT_X: AtomicI32 = AtomicI32::new(0);
+static COMPOSITOR_HOTSPOT_Y: AtomicI32 = AtomicI32::new(0);
+static COMPOSITOR_HOTSPOT_KNOWN: AtomicBool = AtomicBool::new(false);
+
+// Position the compositor last gave the cursor, before our hotspot correction
+static COMPOSITOR_CURSOR_X: AtomicI32 = AtomicI32::new(0);
+static COMPOSITOR_CURSOR_Y: AtomicI32 = AtomicI32::new(0);
+static COMPOSITOR_CURSOR_SEEN: AtomicBool = AtomicBool::new(false);
+static COMPOSITOR_MOVE_MS: AtomicU64 = AtomicU64::new(0);
+
+// Cursor visibility, see the "Visibility and fades" section
+static CURSOR_VISIBILITY: AtomicU32 = AtomicU32::new(Visibility::Shown as u32);
+// When the running fade started and the opacity it started from (f32 bits)
+static FADE_START_MS: AtomicU64 = AtomicU64::new(0);
+static FADE_FROM_OPACITY: AtomicU32 = AtomicU32::new(0);
+// Set when we hid the cursor for being idle rather than the compositor
+static IDLE_HIDDEN: AtomicBool = AtomicBool::new(false);
+static IDLE_THREAD_RUNNING: AtomicBool = AtomicBool::new(false);
+static LAST_MOVE_MS: AtomicU64 = AtomicU64::new(0);
 static CURSOR_FADE_ENABLED: AtomicBool = AtomicBool::new(false);
 static CURSOR_FADE_CHECKED: AtomicBool = AtomicBool::new(false);
-static FADE_THREAD_RUNNING: AtomicBool = AtomicBool::new(false);
+
+// Animation scheduler, one thread drives fades and animated designs
+static ANIMATION_THREAD_RUNNING: AtomicBool = AtomicBool::new(false);
+// An animated design is on screen and still has frames to play
+static ANIMATION_PLAYING: AtomicBool = AtomicBool::new(false);
+// Last render was of an animated design, the clock restarts when this flips on
+static ANIMATION_ACTIVE: AtomicBool = AtomicBool::new(false);
+static ANIMATION_CLOCK_START: AtomicU64 = AtomicU64::new(0);
+// Set while rendering, by designs that animate / have finished animating
+static FRAME_ANIMATED: AtomicBool = AtomicBool::new(false);
+static FRAME_ANIMATION_DONE: AtomicBool = AtomicBool::new(false);
+
+// CRTC of the legacy cursor, 0 for atomic compositors or while hidden
+static LEGACY_CURSOR_CRTC: AtomicU32 = AtomicU32::new(0);
+
+// Renders come from the hooks and the animation thread
+static RENDER_LOCK: Mutex<()> = Mutex::new(());
 
 // config loaded from ~/.config/constellation_cursor/cursor.conf
 static CONFIG_LOADED: AtomicBool = AtomicBool::new(false);
 static CONFIG_FADE_ENABLED: AtomicBool = AtomicBool::new(false);
 static CONFIG_FADE_IN_ENABLED: AtomicBool = AtomicBool::new(false);
 static CONFIG_FADE_SPEED: AtomicU32 = AtomicU32::new(30);
+// Hide the cursor after this long without moving, 0 = never
+static CONFIG_IDLE_HIDE_MS: AtomicU32 = AtomicU32::new(0);
+static CONFIG_FADE_EASING: AtomicU32 = AtomicU32::new(FadeEasing::EaseOut as u32);
 static CONFIG_FROST_INTENSITY: AtomicU32 = AtomicU32::new(100);
-static CONFIG_HOTSPOT_SMOOTHING: AtomicBool = AtomicBool::new(true);
-static CONFIG_HOTSPOT_THRESHOLD: AtomicI32 = AtomicI32::new(5);
 static CONFIG_CURSOR_SCALE: AtomicU32 = AtomicU32::new(150);
 static CONFIG_OUTLINE_THICKNESS: AtomicU32 = AtomicU32::new(0);
+static CONFIG_BLUR_FILTER: AtomicU32 = AtomicU32::new(0);
+static CONFIG_ADAPTIVE_MODE: AtomicU32 = AtomicU32::new(0);
+static CONFIG_ADAPTIVE_THRESHOLD: AtomicU32 = AtomicU32::new(50);
+// XCursor theme to take cursors from, empty = draw our own
+static CONFIG_XCURSOR_THEME: Mutex<String> = Mutex::new(String::new());
+static CONFIG_EXPORT_DIR: Mutex<String> = Mutex::new(String::new());
+// Bit per CursorType that comes from the XCursor theme
+static CONFIG_XCURSOR_TYPES: AtomicU32 = AtomicU32::new(1 << CursorType::Wait as u32);
 static CONFIG_LAST_MTIME: AtomicU64 = AtomicU64::new(0);
 static CONFIG_CHECK_COUNTER: AtomicU32 = AtomicU32::new(0);
 static CONFIG_POLLING_ENABLED: AtomicBool = AtomicBool::new(true);
 static CONFIG_POLL_INTERVAL: AtomicU32 = AtomicU32::new(50);
 
-// Cursor screen position
+// Cursor position on its CRTC (top left of the cursor image), from legacy
+// moves or the atomic CRTC_X/CRTC_Y of whichever cursor plane moved last
 static CURSOR_SCREEN_X: AtomicI32 = AtomicI32::new(0);
 static CURSOR_SCREEN_Y: AtomicI32 = AtomicI32::new(0);
 
@@ -443,18 +828,41 @@ static PRIMARY_FB_HEIGHT: AtomicU32 = AtomicU32::new(0);
 static PRIMARY_FB_STRIDE: AtomicU32 = AtomicU32::new(0);
 static mut PRIMARY_FB_BUFFER: *mut u32 = std::ptr::null_mut();
 
+// Set when the last render sampled the screen, so moves need a re-render
+static CURSOR_SAMPLES_BACKDROP: AtomicBool = AtomicBool::new(false);
+// Lightness class of the screen under the cursor (see Backdrop)
+static BACKDROP_CLASS: AtomicU32 = AtomicU32::new(0);
+
 // mmap'd
 static mut CURSOR_BUFFER: *mut u32 = std::ptr::null_mut();
 
+// Premultiplied linear-light working copy, encoded into CURSOR_BUFFER on present
+static RENDER_BUFFER: Mutex<Vec<LinearPixel>> = Mutex::new(Vec::new());
+
+// Cursor plane "pixel blend mode" (PixelBlendMode), decides how we encode pixels
+static CURSOR_BLEND_MODE: AtomicU32 = AtomicU32::new(0);
+
 // Property IDs for cursor planes, tracking these sneaky bastards
 static mut CURSOR_FB_PROP_IDS: [u32; 8] = [0; 8];
 static mut CURSOR_SRC_W_PROP_IDS: [u32; 8] = [0; 8];
 static mut CURSOR_SRC_H_PROP_IDS: [u32; 8] = [0; 8];
 static mut CURSOR_CRTC_W_PROP_IDS: [u32; 8] = [0; 8];
 static mut CURSOR_CRTC_H_PROP_IDS: [u32; 8] = [0; 8];
+static mut CURSOR_CRTC_X_PROP_IDS: [u32; 8] = [0; 8];
+static mut CURSOR_CRTC_Y_PROP_IDS: [u32; 8] = [0; 8];
+// HOTSPOT_X/HOTSPOT_Y, only there for drivers that need it (virtual GPUs)
+static mut CURSOR_HOTSPOT_X_PROP_IDS: [u32; 8] = [0; 8];
+static mut CURSOR_HOTSPOT_Y_PROP_IDS: [u32; 8] = [0; 8];
+static mut CURSOR_BLEND_PROP_IDS: [u32; 8] = [0; 8];
+
+// Driver enum values for "pixel blend mode", indexed by PixelBlendMode
+static mut BLEND_MODE_ENUM_VALUES: [Option<u64>; 3] = [None; 3];
 
-// The actual display size for our cursor (content is ~32x48, use 64x64 for compatibility)
-const CURSOR_DISPLAY_SIZE: u32 = 64;
+// Size of the dumb buffer we render into, the plane only shows part of it
+const CURSOR_BUFFER_SIZE: u32 = 256;
+
+/// The actual display size for our cursor (content is ~32x48, use 64x64 for compatibility)
+pub const CURSOR_DISPLAY_SIZE: u32 = 64;
 
 static mut REAL_IOCTL: Option<unsafe extern "C" fn(i32, libc::c_ulong, ...) -> i32> = None;
 
@@ -505,24 +913,161 @@ struct DrmModeFB2 {
     modifier: [u64; 4],
 }
 
+// Legacy ADDFB, no format or modifiers
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeFbCmd {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    bpp: u32,
+    depth: u32,
+    handle: u32,
+}
+
+#[repr(C)]
+struct DrmModeCrtc {
+    set_connectors_ptr: u64,
+    count_connectors: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    x: u32,
+    y: u32,
+    gamma_size: u32,
+    mode_valid: u32,
+    // struct drm_mode_modeinfo, not needed here
+    mode: [u8; 68],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeFbDirtyCmd {
+    fb_id: u32,
+    flags: u32,
+    color: u32,
+    num_clips: u32,
+    clips_ptr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmGemClose {
+    handle: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeDestroyDumb {
+    handle: u32,
+}
+
+// The strings are user pointers the driver copies into
+#[repr(C)]
+#[derive(Default)]
+struct DrmVersion {
+    major: i32,
+    minor: i32,
+    patchlevel: i32,
+    name_len: usize,
+    name: usize,
+    date_len: usize,
+    date: usize,
+    desc_len: usize,
+    desc: usize,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmGetCap {
+    capability: u64,
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCardRes {
+    fb_id_ptr: u64,
+    crtc_id_ptr: u64,
+    connector_id_ptr: u64,
+    encoder_id_ptr: u64,
+    count_fbs: u32,
+    count_crtcs: u32,
+    count_connectors: u32,
+    count_encoders: u32,
+    min_width: u32,
+    max_width: u32,
+    min_height: u32,
+    max_height: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeGetPlaneRes {
+    plane_id_ptr: u64,
+    count_planes: u32,
+}
+
+/// struct drm_mode_get_plane, the ioctl behind drmModeGetPlane
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeGetPlaneCmd {
+    plane_id: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    possible_crtcs: u32,
+    gamma_size: u32,
+    count_format_types: u32,
+    format_type_ptr: u64,
+}
+
 // DRM format codes
 // 'A' 'R' '2' '4' in little-endian
 const DRM_FORMAT_ARGB8888: u32 = 0x34325241;
+const DRM_FORMAT_XRGB8888: u32 = 0x34325258;
+
+const DRM_MODE_FB_MODIFIERS: u32 = 0x02;
+const DRM_FORMAT_MOD_LINEAR: u64 = 0;
 
 // flags
 const DRM_MODE_CURSOR_BO: u32 = 0x01;
 const DRM_MODE_CURSOR_MOVE: u32 = 0x02;
 
+/// The definition of `name` after ours, the one we're hooking. `F` is the
+/// function pointer type it has, the caller makes sure that matches.
+unsafe fn next_symbol<F: Copy>(name: &std::ffi::CStr) -> Option<F> {
+    let sym = libc::dlsym(libc::RTLD_NEXT, name.as_ptr());
+    (!sym.is_null()).then(|| std::mem::transmute_copy::<*mut c_void, F>(&sym))
+}
+
 unsafe fn init_real_functions() {
-    if REAL_IOCTL.is_none() {
-        let sym = libc::dlsym(libc::RTLD_NEXT, b"ioctl\0".as_ptr() as *const i8);
-        if !sym.is_null() {
-            REAL_IOCTL = Some(std::mem::transmute(sym));
-        }
-    }
+    REAL_IOCTL = REAL_IOCTL.or_else(|| next_symbol(c"ioctl"));
 }
 
+/// An ioctl of ours, failures go into the statistics
 unsafe fn real_ioctl(fd: i32, request: libc::c_ulong, arg: *mut c_void) -> i32 {
+    checked_ioctl(fd, request, arg).unwrap_or(-1)
+}
+
+/// `real_ioctl` with the errno when it fails
+unsafe fn checked_ioctl(fd: i32, request: libc::c_ulong, arg: *mut c_void) -> Result<i32, i32> {
+    let ret = forward_ioctl(fd, request, arg);
+    if ret < 0 {
+        let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+        note_failed_ioctl(request, errno);
+        return Err(errno);
+    }
+    Ok(ret)
+}
+
+unsafe fn forward_ioctl(fd: i32, request: libc::c_ulong, arg: *mut c_void) -> i32 {
+    // REAL_IOCTL is variadic, so tests can't swap it out like the plane functions
+    #[cfg(test)]
+    if let Some(ret) = fake_drm::ioctl(fd, request, arg) {
+        return ret;
+    }
+
     init_real_functions();
     if let Some(func) = REAL_IOCTL {
         func(fd, request, arg)
@@ -531,36 +1076,92 @@ unsafe fn real_ioctl(fd: i32, request: libc::c_ulong, arg: *mut c_void) -> i32 {
     }
 }
 
-/// Create the poor excuse for a constellation cursor buffer on the DRM device
-unsafe fn create_cursor_buffer(fd: i32, width: u32, height: u32) -> bool {
+/// Why we have no cursor buffer, with the errno of the step that failed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CursorError {
+    CreateDumb(i32),
+    MapDumb(i32),
+    Mmap(i32),
+    AddFb(i32),
+}
+
+impl CursorError {
+    fn errno(self) -> i32 {
+        match self {
+            CursorError::CreateDumb(errno)
+            | CursorError::MapDumb(errno)
+            | CursorError::Mmap(errno)
+            | CursorError::AddFb(errno) => errno,
+        }
+    }
+}
+
+impl std::fmt::Display for CursorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let step = match self {
+            CursorError::CreateDumb(_) => "CREATE_DUMB",
+            CursorError::MapDumb(_) => "MAP_DUMB",
+            CursorError::Mmap(_) => "mmap",
+            CursorError::AddFb(_) => "ADDFB2",
+        };
+        write!(
+            f,
+            "{} failed: {}",
+            step,
+            std::io::Error::from_raw_os_error(self.errno())
+        )
+    }
+}
+
+// After a failed attempt at the buffer we wait before the next one, twice as
+// long each time, and stop after a few. touch /tmp/constellation_cursor_refresh
+// starts over. Meanwhile the compositor's own cursor goes through untouched.
+const BUFFER_RETRY_MIN_MS: u64 = 250;
+const BUFFER_RETRY_MAX_MS: u64 = 30_000;
+const BUFFER_MAX_ATTEMPTS: u32 = 8;
+
+static BUFFER_FAILURES: AtomicU32 = AtomicU32::new(0);
+static BUFFER_RETRY_MS: AtomicU64 = AtomicU64::new(0);
+static BUFFER_ERROR: Mutex<Option<CursorError>> = Mutex::new(None);
+
+/// Create the poor excuse for a constellation cursor buffer on the DRM device.
+/// Whatever got made before a failing step is released again.
+unsafe fn create_cursor_buffer(fd: i32, width: u32, height: u32) -> Result<(), CursorError> {
     let mut create = DrmModeCreateDumb {
         width,
         height,
         bpp: 32,
         ..Default::default()
     };
-
-    let ret = real_ioctl(
+    checked_ioctl(
         fd,
         DRM_IOCTL_MODE_CREATE_DUMB,
         &mut create as *mut _ as *mut c_void,
-    );
-    if ret < 0 {
-        return false;
-    }
+    )
+    .map_err(CursorError::CreateDumb)?;
+
+    let destroy_dumb = || {
+        let mut destroy = DrmModeDestroyDumb {
+            handle: create.handle,
+        };
+        real_ioctl(
+            fd,
+            DRM_IOCTL_MODE_DESTROY_DUMB,
+            &mut destroy as *mut _ as *mut c_void,
+        );
+    };
 
     let mut map = DrmModeMapDumb {
         handle: create.handle,
         ..Default::default()
     };
-
-    let ret = real_ioctl(
+    if let Err(errno) = checked_ioctl(
         fd,
         DRM_IOCTL_MODE_MAP_DUMB,
         &mut map as *mut _ as *mut c_void,
-    );
-    if ret < 0 {
-        return false;
+    ) {
+        destroy_dumb();
+        return Err(CursorError::MapDumb(errno));
     }
 
     // mmap it
@@ -574,7 +1175,9 @@ unsafe fn create_cursor_buffer(fd: i32, width: u32, height: u32) -> bool {
     );
 
     if ptr == libc::MAP_FAILED {
-        return false;
+        let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+        destroy_dumb();
+        return Err(CursorError::Mmap(errno));
     }
 
     let mut fb = DrmModeFB2 {
@@ -587,9 +1190,10 @@ unsafe fn create_cursor_buffer(fd: i32, width: u32, height: u32) -> bool {
         ..Default::default()
     };
 
-    let ret = real_ioctl(fd, DRM_IOCTL_MODE_ADDFB2, &mut fb as *mut _ as *mut c_void);
-    if ret < 0 {
-        return false;
+    if let Err(errno) = checked_ioctl(fd, DRM_IOCTL_MODE_ADDFB2, &mut fb as *mut _ as *mut c_void) {
+        libc::munmap(ptr, create.size as usize);
+        destroy_dumb();
+        return Err(CursorError::AddFb(errno));
     }
     CURSOR_FB_ID.store(fb.fb_id, Ordering::SeqCst);
 
@@ -602,7 +1206,67 @@ unsafe fn create_cursor_buffer(fd: i32, width: u32, height: u32) -> bool {
 
     render_cursor();
 
-    true
+    Ok(())
+}
+
+/// Make sure there's a cursor buffer, trying to create one when the backoff
+/// allows. False means there's none and the compositor's cursor has to go
+/// through as it is.
+unsafe fn ensure_cursor_buffer(fd: i32) -> bool {
+    if INITIALIZED.load(Ordering::SeqCst) {
+        return true;
+    }
+    let failures = BUFFER_FAILURES.load(Ordering::SeqCst);
+    if failures >= BUFFER_MAX_ATTEMPTS || monotonic_ms() < BUFFER_RETRY_MS.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    match create_cursor_buffer(fd, CURSOR_BUFFER_SIZE, CURSOR_BUFFER_SIZE) {
+        Ok(()) => {
+            log!(
+                Info,
+                Drm,
+                "Created cursor buffer, FB_ID={}",
+                CURSOR_FB_ID.load(Ordering::SeqCst)
+            );
+            BUFFER_FAILURES.store(0, Ordering::SeqCst);
+            *BUFFER_ERROR.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            true
+        }
+        Err(e) => {
+            let failures = failures + 1;
+            BUFFER_FAILURES.store(failures, Ordering::SeqCst);
+            *BUFFER_ERROR.lock().unwrap_or_else(|e| e.into_inner()) = Some(e);
+            if failures >= BUFFER_MAX_ATTEMPTS {
+                log!(
+                    Error,
+                    Drm,
+                    "Can't create the cursor buffer ({}), giving up after {} tries. The compositor's cursor is shown instead, touch {} to try again",
+                    e,
+                    failures,
+                    control_path("refresh")
+                );
+            } else {
+                let delay =
+                    (BUFFER_RETRY_MIN_MS << (failures - 1).min(16)).min(BUFFER_RETRY_MAX_MS);
+                BUFFER_RETRY_MS.store(monotonic_ms() + delay, Ordering::SeqCst);
+                log!(
+                    Error,
+                    Drm,
+                    "Can't create the cursor buffer ({}), showing the compositor's cursor, trying again in {}ms",
+                    e,
+                    delay
+                );
+            }
+            false
+        }
+    }
+}
+
+/// Start over with the buffer attempts, after a refresh
+fn reset_buffer_retries() {
+    BUFFER_FAILURES.store(0, Ordering::SeqCst);
+    BUFFER_RETRY_MS.store(0, Ordering::SeqCst);
 }
 
 // =============================================================================
@@ -619,23 +1283,26 @@ unsafe fn render_cursor() {
     let width = CURSOR_WIDTH.load(Ordering::SeqCst) as usize;
     let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as usize;
 
-    for i in 0..(width * height) {
-        *CURSOR_BUFFER.add(i) = 0x00000000;
-    }
+    let _render = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let started = Instant::now();
+    CURSOR_SAMPLES_BACKDROP.store(false, Ordering::SeqCst);
+    FRAME_ANIMATED.store(false, Ordering::SeqCst);
+    FRAME_ANIMATION_DONE.store(false, Ordering::SeqCst);
 
     // Use Constellation's vector rendering
     // TODO: When Constellation is integrated, use VectorGlyph/VectorPath here
     // For now, use cursor type detection with standard polygon rendering
-    match get_cursor_type() {
-        CursorType::Default => render_arrow_cursor(width),
-        CursorType::Pointer => render_pointer_cursor(width),
-        CursorType::Text => render_text_cursor(width),
-        CursorType::Crosshair => render_crosshair_cursor(width),
-        CursorType::Wait => render_wait_cursor(width),
-        CursorType::Grab => render_grab_cursor(width),
-        CursorType::NotAllowed => render_not_allowed_cursor(width),
-        CursorType::Custom => render_custom_cursor(width),
+    // Cursors taken from an XCursor theme replace our own drawing
+    let mut canvas = Canvas::live(width, height);
+    let cursor_type = current_cursor_type();
+    if !render_theme_cursor(&mut canvas, cursor_type) {
+        render_cursor_type(&mut canvas, cursor_type);
     }
+
+    apply_adaptive_contrast(&mut canvas);
+    present_render(canvas, cursor_opacity());
+    finish_animation_frame();
+    note_render(started);
 }
 
 // =============================================================================
@@ -644,9 +1311,9 @@ unsafe fn render_cursor() {
 
 /// Cursor types that can be selected via CONSTELLATION_CURSOR_TYPE env var
 /// or /tmp/constellation_cursor_type file
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(u32)]
-enum CursorType {
+pub enum CursorType {
     Default = 0,
     Pointer = 1,
     Text = 2,
@@ -658,49 +1325,114 @@ enum CursorType {
 }
 
 impl CursorType {
+    /// Every cursor we draw ourselves, Custom is whatever the user designed
+    pub const BUILTIN: [CursorType; 7] = [
+        CursorType::Default,
+        CursorType::Pointer,
+        CursorType::Text,
+        CursorType::Crosshair,
+        CursorType::Wait,
+        CursorType::Grab,
+        CursorType::NotAllowed,
+    ];
+
     fn as_u32(self) -> u32 {
         self as u32
     }
-}
 
-/// Get the current cursor type from environment or file
-/// Applications can change cursor by:
-/// 1. Setting CONSTELLATION_CURSOR_TYPE=pointer (etc)
-/// 2. Writing to /tmp/constellation_cursor_type
-fn get_cursor_type() -> CursorType {
-    if std::path::Path::new("/tmp/constellation_cursor_custom").exists() {
-        return CursorType::Custom;
+    fn from_u32(value: u32) -> Self {
+        match value {
+            7 => CursorType::Custom,
+            _ => Self::BUILTIN
+                .into_iter()
+                .find(|t| t.as_u32() == value)
+                .unwrap_or(CursorType::Default),
+        }
     }
 
-    if let Ok(cursor_type) = std::env::var("CONSTELLATION_CURSOR_TYPE") {
-        return match cursor_type.to_lowercase().as_str() {
-            "pointer" | "hand" => CursorType::Pointer,
-            "text" | "ibeam" | "i-beam" => CursorType::Text,
-            "crosshair" | "cross" => CursorType::Crosshair,
-            "wait" | "loading" | "busy" => CursorType::Wait,
-            "grab" | "grabbing" => CursorType::Grab,
-            "not-allowed" | "no" | "forbidden" => CursorType::NotAllowed,
-            "custom" => CursorType::Custom,
-            _ => CursorType::Default,
-        };
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "default" | "arrow" => Some(CursorType::Default),
+            "pointer" | "hand" => Some(CursorType::Pointer),
+            "text" | "ibeam" | "i-beam" => Some(CursorType::Text),
+            "crosshair" | "cross" => Some(CursorType::Crosshair),
+            "wait" | "loading" | "busy" => Some(CursorType::Wait),
+            "grab" | "grabbing" => Some(CursorType::Grab),
+            "not-allowed" | "no" | "forbidden" => Some(CursorType::NotAllowed),
+            "custom" => Some(CursorType::Custom),
+            _ => None,
+        }
     }
+}
 
-    if let Ok(contents) = std::fs::read_to_string("/tmp/constellation_cursor_type") {
-        return match contents.trim().to_lowercase().as_str() {
-            "pointer" | "hand" => CursorType::Pointer,
-            "text" | "ibeam" | "i-beam" => CursorType::Text,
-            "crosshair" | "cross" => CursorType::Crosshair,
-            "wait" | "loading" | "busy" => CursorType::Wait,
-            "grab" | "grabbing" => CursorType::Grab,
-            "not-allowed" | "no" | "forbidden" => CursorType::NotAllowed,
-            "custom" => CursorType::Custom,
-            _ => CursorType::Default,
-        };
+// Control files are looked for at most this often, they're checked from the
+// cursor move path
+const CONTROL_CHECK_INTERVAL_MS: u64 = 500;
+
+/// A control file, /tmp/constellation_cursor_<name> unless
+/// CONSTELLATION_CURSOR_CONTROL_DIR moves them
+fn control_path(name: &str) -> String {
+    let dir = std::env::var("CONSTELLATION_CURSOR_CONTROL_DIR").unwrap_or_else(|_| "/tmp".into());
+    format!("{}/constellation_cursor_{}", dir, name)
+}
+
+/// Write a report next to the control files. The dir may be /tmp, so a
+/// symlink someone else put there isn't followed.
+fn write_control_file(path: &str, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+/// Whether a control file is due a look, `last` is when it had one
+/// (u64::MAX for never)
+fn control_check_due(last: &AtomicU64) -> bool {
+    let now = monotonic_ms();
+    let previous = last.load(Ordering::Relaxed);
+    if previous != u64::MAX && now.saturating_sub(previous) < CONTROL_CHECK_INTERVAL_MS {
+        return false;
+    }
+    last.store(now, Ordering::Relaxed);
+    true
+}
+
+/// Get the current cursor type from environment or file
+/// Applications can change cursor by:
+/// 1. Setting CONSTELLATION_CURSOR_TYPE=pointer (etc)
+/// 2. Writing to /tmp/constellation_cursor_type
+fn get_cursor_type() -> CursorType {
+    if std::path::Path::new(&control_path("custom")).exists() {
+        return CursorType::Custom;
+    }
+
+    if let Ok(cursor_type) = std::env::var("CONSTELLATION_CURSOR_TYPE") {
+        return CursorType::from_name(&cursor_type).unwrap_or(CursorType::Default);
+    }
+
+    if let Ok(contents) = std::fs::read_to_string(control_path("type")) {
+        return CursorType::from_name(&contents).unwrap_or(CursorType::Default);
     }
 
     CursorType::Default
 }
 
+static CURSOR_TYPE_CHECK_MS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// `get_cursor_type` looked up at most every CONTROL_CHECK_INTERVAL_MS,
+/// animation frames come far more often than the type changes
+fn current_cursor_type() -> CursorType {
+    if control_check_due(&CURSOR_TYPE_CHECK_MS) {
+        CURRENT_CURSOR_TYPE.store(get_cursor_type().as_u32(), Ordering::SeqCst);
+    }
+    CursorType::from_u32(CURRENT_CURSOR_TYPE.load(Ordering::SeqCst))
+}
+
 /// Get cursor scale from environment or file
 /// Default is 1.5, can be overridden via:
 /// - CONSTELLATION_CURSOR_SCALE=2.0
@@ -716,7 +1448,7 @@ fn get_cursor_scale() -> f32 {
         }
     }
 
-    if let Ok(contents) = std::fs::read_to_string("/tmp/constellation_cursor_scale") {
+    if let Ok(contents) = std::fs::read_to_string(control_path("scale")) {
         if let Ok(scale) = contents.trim().parse::<f32>() {
             if scale > 0.0 && scale <= 10.0 {
                 return scale;
@@ -725,35 +1457,50 @@ fn get_cursor_scale() -> f32 {
     }
 
     let config_scale = CONFIG_CURSOR_SCALE.load(Ordering::Relaxed) as f32 / 100.0;
-    if config_scale >= 0.5 && config_scale <= 10.0 {
+    if (0.5..=10.0).contains(&config_scale) {
         return config_scale;
     }
 
     1.5 // Default scale
 }
 
+static REFRESH_CHECK_MS: AtomicU64 = AtomicU64::new(u64::MAX);
+
 /// Check if a refresh has been requested via /tmp/constellation_cursor_refresh
 /// Apps can trigger a cursor refresh by:
 ///   touch /tmp/constellation_cursor_refresh
 /// Or set the type and refresh in one command:
 ///   echo "pointer" > /tmp/constellation_cursor_type && touch /tmp/constellation_cursor_refresh
 unsafe fn check_cursor_refresh() {
-    const REFRESH_PATH: &str = "/tmp/constellation_cursor_refresh";
+    // Called on every pointer move, a stat each time would add up
+    if !control_check_due(&REFRESH_CHECK_MS) {
+        return;
+    }
+    let refresh_path = control_path("refresh");
 
-    if std::path::Path::new(REFRESH_PATH).exists() {
-        let _ = std::fs::remove_file(REFRESH_PATH);
+    if std::path::Path::new(&refresh_path).exists() {
+        let _ = std::fs::remove_file(&refresh_path);
+        reset_buffer_retries();
 
         if !INITIALIZED.load(Ordering::SeqCst) || CURSOR_BUFFER.is_null() {
             return;
         }
 
+        STAT_CONFIG_RELOADS.fetch_add(1, Ordering::Relaxed);
         CONFIG_LOADED.store(false, Ordering::Relaxed);
         CURSOR_FADE_CHECKED.store(false, Ordering::Relaxed);
         load_config();
 
         let new_type = get_cursor_type();
-        debug_print!("Cursor refresh requested, type: {:?}", new_type.as_u32());
+        log!(
+            Info,
+            Config,
+            "Cursor refresh requested, type: {:?}",
+            new_type.as_u32()
+        );
         CURRENT_CURSOR_TYPE.store(new_type.as_u32(), Ordering::SeqCst);
+        CURSOR_TYPE_CHECK_MS.store(monotonic_ms(), Ordering::Relaxed);
+        forget_custom_design();
         render_cursor();
     }
 }
@@ -767,20 +1514,97 @@ unsafe fn render_cursor() {
     let width = CURSOR_WIDTH.load(Ordering::SeqCst) as usize;
     let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as usize;
 
-    for i in 0..(width * height) {
-        *CURSOR_BUFFER.add(i) = 0x00000000;
+    let _render = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let started = Instant::now();
+    CURSOR_SAMPLES_BACKDROP.store(false, Ordering::SeqCst);
+    FRAME_ANIMATED.store(false, Ordering::SeqCst);
+    FRAME_ANIMATION_DONE.store(false, Ordering::SeqCst);
+
+    // Cursors taken from an XCursor theme replace our own drawing
+    let mut canvas = Canvas::live(width, height);
+    let cursor_type = current_cursor_type();
+    if !render_theme_cursor(&mut canvas, cursor_type) {
+        render_cursor_type(&mut canvas, cursor_type);
+    }
+
+    apply_adaptive_contrast(&mut canvas);
+    present_render(canvas, cursor_opacity());
+    finish_animation_frame();
+    note_render(started);
+}
+
+/// Draw one of our own cursors
+unsafe fn render_cursor_type(canvas: &mut Canvas, cursor_type: CursorType) {
+    match cursor_type {
+        CursorType::Default => render_arrow_cursor(canvas),
+        CursorType::Pointer => render_pointer_cursor(canvas),
+        CursorType::Text => render_text_cursor(canvas),
+        CursorType::Crosshair => render_crosshair_cursor(canvas),
+        CursorType::Wait => render_wait_cursor(canvas),
+        CursorType::Grab => render_grab_cursor(canvas),
+        CursorType::NotAllowed => render_not_allowed_cursor(canvas),
+        CursorType::Custom => render_custom_cursor(canvas),
+    }
+}
+
+/// Something to render offline
+pub enum Design<'a> {
+    /// One of the built-in cursors (Custom reads /tmp/constellation_cursor_custom)
+    Builtin(CursorType),
+    /// A designer JSON, the same format as /tmp/constellation_cursor_custom
+    Json(&'a str),
+    /// An SVG of plain shapes, see "SVG import"
+    Svg(&'a str),
+}
+
+impl<'a> Design<'a> {
+    /// A design file's content, SVG going by the extension and designer JSON
+    /// otherwise
+    pub fn from_file(path: &std::path::Path, content: &'a str) -> Design<'a> {
+        let is_svg = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+        if is_svg {
+            Design::Svg(content)
+        } else {
+            Design::Json(content)
+        }
     }
+}
+
+/// Render a design without a display, through the same rasterizer the cursor
+/// plane gets. `scale` works like the cursor scale (1.5 = default size),
+/// `rotation` is in degrees and added to the design's own. The canvas is the
+/// size of the cursor buffer, the plane shows its top left CURSOR_DISPLAY_SIZE.
+pub fn render_to_canvas(design: &Design, scale: f32, rotation: f32) -> Canvas {
+    render_frame(design, scale, rotation, 0.0)
+}
 
-    match get_cursor_type() {
-        CursorType::Default => render_arrow_cursor(width),
-        CursorType::Pointer => render_pointer_cursor(width),
-        CursorType::Text => render_text_cursor(width),
-        CursorType::Crosshair => render_crosshair_cursor(width),
-        CursorType::Wait => render_wait_cursor(width),
-        CursorType::Grab => render_grab_cursor(width),
-        CursorType::NotAllowed => render_not_allowed_cursor(width),
-        CursorType::Custom => render_custom_cursor(width),
+/// `render_to_canvas` at `time` ms into the design's animation
+fn render_frame(design: &Design, scale: f32, rotation: f32, time: f32) -> Canvas {
+    let size = CURSOR_BUFFER_SIZE as usize;
+    let mut canvas = Canvas::new(size, size);
+    canvas.scale = scale;
+    canvas.rotation = rotation;
+    canvas.clock = Some(time);
+    unsafe {
+        match design {
+            Design::Builtin(cursor_type) => render_cursor_type(&mut canvas, *cursor_type),
+            Design::Json(content) => render_design(&mut canvas, content),
+            Design::Svg(svg) => match svg_to_design(svg) {
+                Some(content) => render_design(&mut canvas, &content),
+                None => {
+                    log!(
+                        Warn,
+                        Render,
+                        "No shapes in SVG, rendering the default arrow"
+                    );
+                    render_arrow_cursor(&mut canvas);
+                }
+            },
+        }
     }
+    canvas
 }
 
 // =============================================================================
@@ -805,25 +1629,7 @@ fn transform_points(
         return (Vec::new(), (0, 0));
     }
 
-    // First point is the hotspot
-    let (hx, hy) = points[0];
-    let rotation_rad = rotation_deg * std::f32::consts::PI / 180.0;
-    let cos_r = rotation_rad.cos();
-    let sin_r = rotation_rad.sin();
-
-    // transform all points: offset to hotspot origin, scale, rotate
-    let transformed: Vec<(f32, f32)> = points
-        .iter()
-        .map(|(x, y)| {
-            // Offset so hotspot is at origin
-            let dx = (x - hx) * scale;
-            let dy = (y - hy) * scale;
-            // Apply rotation around origin (which is, you guessed it, the hotspot)
-            let rx = dx * cos_r - dy * sin_r;
-            let ry = dx * sin_r + dy * cos_r;
-            (rx, ry)
-        })
-        .collect();
+    let transformed = scale_rotate_around_hotspot(points, scale, rotation_deg);
 
     // find bounding box
     let min_x = transformed.iter().map(|p| p.0).fold(f32::MAX, f32::min);
@@ -844,11 +1650,43 @@ fn transform_points(
     (adjusted, (hotspot_x, hotspot_y))
 }
 
-/// Simple scale without rotation (hopeful legacy compatibility)
-fn scale_points_around_hotspot(points: &[(f32, f32)], scale: f32) -> Vec<(f32, f32)> {
-    let (adjusted, (hx, hy)) = transform_points(points, scale, 0.0);
-    CURSOR_HOTSPOT_X.store(hx, Ordering::SeqCst);
-    CURSOR_HOTSPOT_Y.store(hy, Ordering::SeqCst);
+/// Offset points so the hotspot (first point) is the origin, then scale and
+/// rotate them around it
+fn scale_rotate_around_hotspot(
+    points: &[(f32, f32)],
+    scale: f32,
+    rotation_deg: f32,
+) -> Vec<(f32, f32)> {
+    let Some(&(hx, hy)) = points.first() else {
+        return Vec::new();
+    };
+    let rotation_rad = rotation_deg * std::f32::consts::PI / 180.0;
+    let cos_r = rotation_rad.cos();
+    let sin_r = rotation_rad.sin();
+
+    points
+        .iter()
+        .map(|(x, y)| {
+            // Offset so hotspot is at origin
+            let dx = (x - hx) * scale;
+            let dy = (y - hy) * scale;
+            // Apply rotation around origin (which is, you guessed it, the hotspot)
+            let rx = dx * cos_r - dy * sin_r;
+            let ry = dx * sin_r + dy * cos_r;
+            (rx, ry)
+        })
+        .collect()
+}
+
+/// Place a built-in shape on the canvas at `scale`, turned by the canvas
+/// rotation, and make its first point the canvas hotspot
+fn scale_points_around_hotspot(
+    canvas: &mut Canvas,
+    points: &[(f32, f32)],
+    scale: f32,
+) -> Vec<(f32, f32)> {
+    let (adjusted, hotspot) = transform_points(points, scale, canvas.rotation);
+    canvas.hotspot = hotspot;
     adjusted
 }
 
@@ -857,13 +1695,13 @@ fn scale_points_around_hotspot(points: &[(f32, f32)], scale: f32) -> Vec<(f32, f
 const EMBEDDED_CURSOR_JSON: &str = r##"{"type":"default","layers":[{"points":[{"x":0,"y":0},{"x":3,"y":18},{"x":10,"y":17.5},{"x":12.5,"y":15.5},{"x":14.5,"y":10}],"fill":"#011023","fillAlpha":91,"outline":"#948f8f","outlineWidth":2,"outlineAlpha":100,"shadow":"#000000","shadowAlpha":33,"shadowOffset":3,"blur":0,"blurOutline":false,"passthroughTo":-1}],"settings":{"scale":1.5}}"##;
 
 /// Default arrow cursor, renders the embedded multi-layer design
-unsafe fn render_arrow_cursor(stride: usize) {
-    render_custom_cursor_v2(stride, EMBEDDED_CURSOR_JSON);
+unsafe fn render_arrow_cursor(canvas: &mut Canvas) {
+    render_custom_cursor_v2(canvas, EMBEDDED_CURSOR_JSON);
 }
 
 /// Pointer/hand cursor (the result of a copy/paste)
-unsafe fn render_pointer_cursor(stride: usize) {
-    let scale = get_cursor_scale();
+unsafe fn render_pointer_cursor(canvas: &mut Canvas) {
+    let scale = canvas.scale;
     let points: [(f32, f32); 7] = [
         (0.0, 0.0),
         (0.0, 16.0),
@@ -873,15 +1711,15 @@ unsafe fn render_pointer_cursor(stride: usize) {
         (7.0, 11.0),
         (12.0, 11.0),
     ];
-    let scaled = scale_points_around_hotspot(&points, scale);
-    draw_filled_polygon(stride, &scaled, 1.0, 1.0, 0x80000000);
-    draw_filled_polygon(stride, &scaled, 0.0, 0.0, 0xFFFFFFFF);
-    draw_polygon_outline(stride, &scaled, 0.0, 0.0, 0xFF000000);
+    let scaled = scale_points_around_hotspot(canvas, &points, scale);
+    draw_drop_shadow(canvas, &scaled, &BUILTIN_SHADOW);
+    draw_filled_polygon(canvas, &scaled, 0.0, 0.0, 0xFFFFFFFF);
+    draw_polygon_outline(canvas, &scaled, 0.0, 0.0, 0xFF000000);
 }
 
 /// Text/I-beam cursor (if we squint)
-unsafe fn render_text_cursor(stride: usize) {
-    let scale = get_cursor_scale();
+unsafe fn render_text_cursor(canvas: &mut Canvas) {
+    let scale = canvas.scale;
     let points: [(f32, f32); 14] = [
         (2.0, 0.0),
         (5.0, 0.0),
@@ -898,15 +1736,15 @@ unsafe fn render_text_cursor(stride: usize) {
         (5.0, 1.0),
         (2.0, 1.0),
     ];
-    let scaled = scale_points_around_hotspot(&points, scale);
-    draw_filled_polygon(stride, &scaled, 1.0, 1.0, 0x80000000);
-    draw_filled_polygon(stride, &scaled, 0.0, 0.0, 0xFFFFFFFF);
-    draw_polygon_outline(stride, &scaled, 0.0, 0.0, 0xFF000000);
+    let scaled = scale_points_around_hotspot(canvas, &points, scale);
+    draw_drop_shadow(canvas, &scaled, &BUILTIN_SHADOW);
+    draw_filled_polygon(canvas, &scaled, 0.0, 0.0, 0xFFFFFFFF);
+    draw_polygon_outline(canvas, &scaled, 0.0, 0.0, 0xFF000000);
 }
 
 /// Crosshair cursor (for precision selection, off-center, for that extra precision)
-unsafe fn render_crosshair_cursor(stride: usize) {
-    let scale = get_cursor_scale() * 0.8; // smaller for crosshair
+unsafe fn render_crosshair_cursor(canvas: &mut Canvas) {
+    let scale = canvas.scale * 0.8; // smaller for crosshair
     let points: [(f32, f32); 20] = [
         (8.0, 0.0),
         (8.0, 6.0),
@@ -929,34 +1767,84 @@ unsafe fn render_crosshair_cursor(stride: usize) {
         (10.0, 6.0),
         (10.0, 0.0),
     ];
-    let scaled = scale_points_around_hotspot(&points, scale);
-    draw_filled_polygon(stride, &scaled, 1.0, 1.0, 0x80000000);
-    draw_filled_polygon(stride, &scaled, 0.0, 0.0, 0xFFFFFFFF);
-    draw_polygon_outline(stride, &scaled, 0.0, 0.0, 0xFF000000);
+    let scaled = scale_points_around_hotspot(canvas, &points, scale);
+    draw_drop_shadow(canvas, &scaled, &BUILTIN_SHADOW);
+    draw_filled_polygon(canvas, &scaled, 0.0, 0.0, 0xFFFFFFFF);
+    draw_polygon_outline(canvas, &scaled, 0.0, 0.0, 0xFF000000);
 }
 
 /// Wait/loading cursor (pretend hourglass shape)
-unsafe fn render_wait_cursor(stride: usize) {
-    let scale = get_cursor_scale();
-    let points: [(f32, f32); 8] = [
+unsafe fn render_wait_cursor(canvas: &mut Canvas) {
+    let scale = canvas.scale;
+    let points: [(f32, f32); 10] = [
         (0.0, 0.0),
         (12.0, 0.0),
         (12.0, 3.0),
-        (6.0, 9.0),
+        (7.0, 9.0),
         (12.0, 15.0),
         (12.0, 18.0),
         (0.0, 18.0),
         (0.0, 15.0),
+        (5.0, 9.0),
+        (0.0, 3.0),
     ];
-    let scaled = scale_points_around_hotspot(&points, scale);
-    draw_filled_polygon(stride, &scaled, 1.0, 1.0, 0x80000000);
-    draw_filled_polygon(stride, &scaled, 0.0, 0.0, 0xFFFFFFFF);
-    draw_polygon_outline(stride, &scaled, 0.0, 0.0, 0xFF000000);
+    let scaled = scale_points_around_hotspot(canvas, &points, scale);
+    draw_drop_shadow(canvas, &scaled, &BUILTIN_SHADOW);
+    draw_filled_polygon(canvas, &scaled, 0.0, 0.0, 0xFFFFFFFF);
+
+    // Sand runs from the top bulb into the bottom one, then starts over.
+    // The hotspot is the top left corner, so sand is placed the same way
+    // as the glass: scaled and turned around it.
+    let progress = (canvas.animation_time() % WAIT_CYCLE_MS) / WAIT_CYCLE_MS;
+    let (sin_r, cos_r) = canvas.rotation.to_radians().sin_cos();
+    let (hx, hy) = (canvas.hotspot.0 as f32, canvas.hotspot.1 as f32);
+    let sand = |shape: &[(f32, f32)]| -> Vec<(f32, f32)> {
+        shape
+            .iter()
+            .map(|(x, y)| {
+                let (x, y) = (x * scale, y * scale);
+                (x * cos_r - y * sin_r + hx, x * sin_r + y * cos_r + hy)
+            })
+            .collect()
+    };
+    let top = 5.0 * (1.0 - progress);
+    let bottom = 4.0 * progress;
+    let bottom_inset = (bottom - 2.0).max(0.0) * 0.85;
+    if top > 0.2 {
+        let top_sand = [
+            (6.0 - 0.8 * top, 8.5 - top),
+            (6.0 + 0.8 * top, 8.5 - top),
+            (6.0, 8.5),
+        ];
+        draw_filled_polygon(canvas, &sand(&top_sand), 0.0, 0.0, WAIT_SAND_COLOR);
+    }
+    if bottom > 0.2 {
+        let bottom_sand = [
+            (1.0, 17.0),
+            (11.0, 17.0),
+            (11.0 - bottom_inset, 17.0 - bottom),
+            (1.0 + bottom_inset, 17.0 - bottom),
+        ];
+        draw_filled_polygon(canvas, &sand(&bottom_sand), 0.0, 0.0, WAIT_SAND_COLOR);
+        let trickle = [
+            (5.7, 9.0),
+            (6.3, 9.0),
+            (6.3, 17.0 - bottom),
+            (5.7, 17.0 - bottom),
+        ];
+        draw_filled_polygon(canvas, &sand(&trickle), 0.0, 0.0, WAIT_SAND_COLOR);
+    }
+
+    draw_polygon_outline(canvas, &scaled, 0.0, 0.0, 0xFF000000);
 }
 
+/// How long the wait cursor's sand takes to run through, in ms
+const WAIT_CYCLE_MS: f32 = 1600.0;
+const WAIT_SAND_COLOR: u32 = 0xFFD9A441;
+
 /// Grab/hand cursor (the result of my unending potential for graphical design)
-unsafe fn render_grab_cursor(stride: usize) {
-    let scale = get_cursor_scale() * 0.87;
+unsafe fn render_grab_cursor(canvas: &mut Canvas) {
+    let scale = canvas.scale * 0.87;
     let points: [(f32, f32); 20] = [
         (6.0, 0.0),
         (6.0, 8.0),
@@ -979,15 +1867,15 @@ unsafe fn render_grab_cursor(stride: usize) {
         (4.0, 12.0),
         (4.0, 0.0),
     ];
-    let scaled = scale_points_around_hotspot(&points, scale);
-    draw_filled_polygon(stride, &scaled, 1.0, 1.0, 0x80000000);
-    draw_filled_polygon(stride, &scaled, 0.0, 0.0, 0xFFFFFFFF);
-    draw_polygon_outline(stride, &scaled, 0.0, 0.0, 0xFF000000);
+    let scaled = scale_points_around_hotspot(canvas, &points, scale);
+    draw_drop_shadow(canvas, &scaled, &BUILTIN_SHADOW);
+    draw_filled_polygon(canvas, &scaled, 0.0, 0.0, 0xFFFFFFFF);
+    draw_polygon_outline(canvas, &scaled, 0.0, 0.0, 0xFF000000);
 }
 
 /// Not-allowed cursor (circle with slash)
-unsafe fn render_not_allowed_cursor(stride: usize) {
-    let scale = get_cursor_scale();
+unsafe fn render_not_allowed_cursor(canvas: &mut Canvas) {
+    let scale = canvas.scale;
     let radius = 9.0;
 
     let mut points: Vec<(f32, f32)> = Vec::new();
@@ -1000,11 +1888,11 @@ unsafe fn render_not_allowed_cursor(stride: usize) {
 
     points.insert(0, (offset, offset));
 
-    let scaled = scale_points_around_hotspot(&points, scale);
+    let scaled = scale_points_around_hotspot(canvas, &points, scale);
 
     let circle_points: Vec<(f32, f32)> = scaled[1..].to_vec();
-    draw_polygon_outline(stride, &circle_points, 1.0, 1.0, 0x80000000);
-    draw_polygon_outline(stride, &circle_points, 0.0, 0.0, 0xFFFF0000);
+    draw_polygon_outline(canvas, &circle_points, 1.0, 1.0, 0x80000000);
+    draw_polygon_outline(canvas, &circle_points, 0.0, 0.0, 0xFFFF0000);
 
     let slash: [(f32, f32); 5] = [
         (offset, offset),
@@ -1013,12 +1901,13 @@ unsafe fn render_not_allowed_cursor(stride: usize) {
         (offset + 7.0, offset + 5.0),
         (offset + 6.0, offset + 6.0),
     ];
-    let scaled_slash = scale_points_around_hotspot(&slash, scale);
+    let scaled_slash = scale_points_around_hotspot(canvas, &slash, scale);
     let slash_points: Vec<(f32, f32)> = scaled_slash[1..].to_vec();
-    draw_filled_polygon(stride, &slash_points, 0.0, 0.0, 0xFFFF0000);
+    draw_filled_polygon(canvas, &slash_points, 0.0, 0.0, 0xFFFF0000);
 }
 
-/// Custom cursor loaded from /tmp/constellation_cursor_custom
+/// Custom cursor loaded from /tmp/constellation_cursor_custom, parsed once
+/// and read again when the file changes or on refresh
 /// File format is simple JSON:
 ///
 /// Custom cursor format v2 (multi-layer):
@@ -1034,57 +1923,168 @@ unsafe fn render_not_allowed_cursor(stride: usize) {
 ///       "outline": "#AARRGGBB",
 ///       "outlineWidth": 1.0,
 ///       "shadow": "#AARRGGBB",
-///       "shadowOffset": 1.0
+///       "shadowAlpha": 50,
+///       "shadowOffset": 1.0,
+///       "shadowOffsetX": 1.0,
+///       "shadowOffsetY": 2.0,
+///       "shadowBlur": 1.5,
+///       "shadowSpread": 0.0,
+///       "blendMode": "normal",
+///       "clipTo": 0,
+///       "maskBy": 1,
+///       "maskMode": "subtract",
+///       "passthroughTo": -1
 ///     }
 ///   ]
 /// }
-unsafe fn render_custom_cursor(stride: usize) {
-    const CUSTOM_PATH: &str = "/tmp/constellation_cursor_custom";
+///
+/// Shadows are a blurred copy of the layer's shape: `shadowOffset` moves both
+/// axes, `shadowOffsetX`/`shadowOffsetY` override either one, `shadowBlur` is
+/// the Gaussian radius (standard deviation) and `shadowSpread` grows the shape
+/// before blurring. All of them are in design units and follow the scale.
+///
+/// `blendMode` decides how a layer (shadow included) combines with the layers
+/// below it: `normal`, `multiply`, `screen`, `overlay`, `add`, `difference`,
+/// `xor` or `erase` (punches the layer's shape out of what is below).
+///
+/// `clipTo` keeps only the part of a layer inside another layer's shape,
+/// `maskBy` cuts it with another layer's shape using `maskMode` (`subtract`,
+/// `intersect` or `exclude`). Layers are referenced by index.
+/// `passthroughTo` makes the layer a window: inside its shape the layers from
+/// the target index upwards are removed, then the fill is drawn as a tint.
+///
+/// Designs can animate with an optional `animation` block:
+/// {
+///   "animation": {
+///     "loop": true,
+///     "frames": [{"duration": 120, "layers": [...]}, ...],
+///     "keyframes": [{"duration": 500, "rotation": 90, "scale": 1.2, "tint": "#80FF0000", "hidden": [1]}, ...]
+///   }
+/// }
+/// `frames` replace the design's layers one after the other. `keyframes`
+/// animate the whole design: rotation (degrees, added to the design's), scale
+/// (multiplier), tint (mixed into fill and outline by its alpha) ease linearly
+/// into the next keyframe over `duration` ms, `hidden` lists layer indices
+/// that are not drawn during that keyframe. Both timelines run side by side,
+/// without `loop` the last state is held. The hotspot stays put for the
+/// whole animation, spinning designs get room to rotate in every direction.
+unsafe fn render_custom_cursor(canvas: &mut Canvas) {
+    let mut cache = CUSTOM_DESIGN.lock().unwrap_or_else(|e| e.into_inner());
+    if control_check_due(&CUSTOM_DESIGN_CHECK_MS) {
+        reload_custom_design(&mut cache);
+    }
 
-    let content = match std::fs::read_to_string(CUSTOM_PATH) {
-        Ok(c) => c,
-        Err(_) => {
-            render_arrow_cursor(stride);
+    match &*cache {
+        Some(custom) => render_parsed_design(canvas, &custom.design),
+        None => render_arrow_cursor(canvas),
+    }
+}
+
+/// The custom design as last read, animation frames only sample its timeline
+struct CustomDesign {
+    modified: Option<std::time::SystemTime>,
+    len: u64,
+    design: ParsedDesign,
+}
+
+static CUSTOM_DESIGN: Mutex<Option<CustomDesign>> = Mutex::new(None);
+static CUSTOM_DESIGN_CHECK_MS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Read and parse the custom design again if the file changed since
+fn reload_custom_design(cache: &mut Option<CustomDesign>) {
+    let path = control_path("custom");
+    let Ok(metadata) = std::fs::metadata(&path) else {
+        *cache = None;
+        return;
+    };
+    let modified = metadata.modified().ok();
+    if let Some(custom) = cache {
+        if custom.modified == modified && custom.len == metadata.len() {
             return;
         }
-    };
+    }
+
+    *cache = std::fs::read_to_string(&path)
+        .ok()
+        .map(|content| CustomDesign {
+            modified,
+            len: metadata.len(),
+            design: ParsedDesign::parse(&content),
+        });
+    log!(Debug, Render, "Custom design (re)loaded from {}", path);
+}
 
-    // Check for version 2 (multi-layer) format
-    let version = parse_float(&content, "version").unwrap_or(1.0) as i32;
+/// Make the next render read the custom design file again
+fn forget_custom_design() {
+    *CUSTOM_DESIGN.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    CUSTOM_DESIGN_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+}
 
-    if version >= 2 {
-        render_custom_cursor_v2(stride, &content);
-    } else {
-        render_custom_cursor_v1(stride, &content);
+/// A designer JSON of either version, parsed
+enum ParsedDesign {
+    // v1 is a single shape, cheap enough to parse as it's drawn
+    V1(String),
+    V2(DesignV2),
+}
+
+impl ParsedDesign {
+    fn parse(content: &str) -> Self {
+        // Check for version 2 (multi-layer) format
+        let version = parse_float(content, "version").unwrap_or(1.0) as i32;
+
+        if version >= 2 {
+            ParsedDesign::V2(DesignV2::parse(content))
+        } else {
+            ParsedDesign::V1(content.to_string())
+        }
     }
 }
 
+unsafe fn render_parsed_design(canvas: &mut Canvas, design: &ParsedDesign) {
+    match design {
+        ParsedDesign::V1(content) => render_custom_cursor_v1(canvas, content),
+        ParsedDesign::V2(design) => render_design_v2(canvas, design),
+    }
+}
+
+/// Render a designer JSON of either version
+unsafe fn render_design(canvas: &mut Canvas, content: &str) {
+    render_parsed_design(canvas, &ParsedDesign::parse(content));
+}
+
 /// Render v1 format (single layer, backwards compatible for my own work, will be removed later)
-unsafe fn render_custom_cursor_v1(stride: usize, content: &str) {
+unsafe fn render_custom_cursor_v1(canvas: &mut Canvas, content: &str) {
     let points = parse_custom_points(content);
     let fill_color = parse_color(content, "fill").unwrap_or(0xFFFFFFFF);
     let outline_color = parse_color(content, "outline").unwrap_or(0xFF000000);
     let shadow_color = parse_color(content, "shadow").unwrap_or(0x80000000);
     let custom_scale = parse_float(content, "scale").unwrap_or(1.5);
-    let rotation = parse_float(content, "rotation").unwrap_or(0.0);
+    let rotation = parse_float(content, "rotation").unwrap_or(0.0) + canvas.rotation;
     let shadow_offset = parse_float(content, "shadowOffset").unwrap_or(1.0);
+    let shadow_blur = parse_float(content, "shadowBlur").unwrap_or(0.0);
 
     if points.is_empty() {
-        render_arrow_cursor(stride);
+        render_arrow_cursor(canvas);
         return;
     }
 
     let (scaled, (hx, hy)) = transform_points(&points, custom_scale, rotation);
-    CURSOR_HOTSPOT_X.store(hx, Ordering::SeqCst);
-    CURSOR_HOTSPOT_Y.store(hy, Ordering::SeqCst);
-
-    if shadow_offset > 0.0 {
-        draw_filled_polygon(stride, &scaled, shadow_offset, shadow_offset, shadow_color);
-    }
-    draw_filled_polygon(stride, &scaled, 0.0, 0.0, fill_color);
-    draw_polygon_outline(stride, &scaled, 0.0, 0.0, outline_color);
+    canvas.hotspot = (hx, hy);
+
+    let shadow = DropShadow {
+        offset_x: shadow_offset,
+        offset_y: shadow_offset,
+        blur: shadow_blur,
+        spread: 0.0,
+        color: shadow_color,
+    };
+    draw_drop_shadow(canvas, &scaled, &shadow);
+    draw_filled_polygon(canvas, &scaled, 0.0, 0.0, fill_color);
+    draw_polygon_outline(canvas, &scaled, 0.0, 0.0, outline_color);
 
-    debug_print!(
+    log_limited!(
+        Debug,
+        Render,
         "Rendered custom cursor v1 with {} points, rotation: {}°, hotspot: ({}, {})",
         points.len(),
         rotation,
@@ -1093,17 +2093,85 @@ unsafe fn render_custom_cursor_v1(stride: usize, content: &str) {
     );
 }
 
+/// A v2 (multi-layer) design
+struct DesignV2 {
+    scale: f32,
+    rotation: f32,
+    layers: Vec<CursorLayer>,
+    animation: Option<DesignAnimation>,
+}
+
+impl DesignV2 {
+    fn parse(content: &str) -> Self {
+        // The animation block repeats keys like scale and layers, keep it out of
+        // the way of the design's own
+        let (content, animation) = split_animation(content);
+        let content = content.as_str();
+
+        DesignV2 {
+            scale: parse_float(content, "scale").unwrap_or(1.5),
+            rotation: parse_float(content, "rotation").unwrap_or(0.0),
+            layers: parse_layers(content),
+            animation: animation.and_then(|block| DesignAnimation::parse(&block)),
+        }
+    }
+}
+
 /// Render v2 format (multi-layer)
-unsafe fn render_custom_cursor_v2(stride: usize, content: &str) {
-    let json_scale = parse_float(content, "scale").unwrap_or(1.5);
-    let runtime_scale = get_cursor_scale();
-    let custom_scale = json_scale * runtime_scale / 1.5;
-    let rotation = parse_float(content, "rotation").unwrap_or(0.0);
+unsafe fn render_custom_cursor_v2(canvas: &mut Canvas, content: &str) {
+    render_design_v2(canvas, &DesignV2::parse(content));
+}
+
+unsafe fn render_design_v2(canvas: &mut Canvas, design: &DesignV2) {
+    let runtime_scale = canvas.scale;
+    let mut custom_scale = design.scale * runtime_scale / 1.5;
+    let mut rotation = design.rotation + canvas.rotation;
+
+    let mut layers = design.layers.clone();
+    let mut hidden = Vec::new();
+    let mut pinned_hotspot = None;
+
+    if let Some(animation) = &design.animation {
+        let state = animation.sample(canvas.animation_time());
+        if state.finished && canvas.is_live() {
+            FRAME_ANIMATION_DONE.store(true, Ordering::SeqCst);
+        }
 
-    let layers = parse_layers(content);
+        // Pin the hotspot for the whole animation, measured before this
+        // frame's transform so frames and keyframes can't move it around
+        let mut extent: Vec<(f32, f32)> = Vec::new();
+        for frame_layers in
+            std::iter::once(&layers).chain(animation.frames.iter().map(|f| &f.layers))
+        {
+            let frame_points: Vec<(f32, f32)> = frame_layers
+                .iter()
+                .flat_map(|l| l.points.iter().cloned())
+                .collect();
+            extent.extend(scale_rotate_around_hotspot(&frame_points, 1.0, 0.0));
+        }
+        pinned_hotspot = Some(animation_hotspot(
+            &extent,
+            custom_scale * animation.max_scale(),
+            rotation,
+            animation.spins(),
+        ));
+
+        if let Some(frame) = state.frame {
+            layers = animation.frames[frame].layers.clone();
+        }
+        custom_scale *= state.scale;
+        rotation += state.rotation;
+        if (state.tint >> 24) > 0 {
+            for layer in layers.iter_mut() {
+                layer.fill_color = tint_color(layer.fill_color, state.tint);
+                layer.outline_color = tint_color(layer.outline_color, state.tint);
+            }
+        }
+        hidden = state.hidden;
+    }
 
     if layers.is_empty() {
-        let scale = get_cursor_scale();
+        let scale = canvas.scale;
         let points: [(f32, f32); 7] = [
             (0.0, 0.0),
             (0.0, 18.0),
@@ -1113,10 +2181,10 @@ unsafe fn render_custom_cursor_v2(stride: usize, content: &str) {
             (7.5, 12.0),
             (13.0, 12.0),
         ];
-        let scaled = scale_points_around_hotspot(&points, scale);
-        draw_filled_polygon(stride, &scaled, 1.0, 1.0, 0x80000000);
-        draw_filled_polygon(stride, &scaled, 0.0, 0.0, 0xFFFFFFFF);
-        draw_polygon_outline(stride, &scaled, 0.0, 0.0, 0xFF000000);
+        let scaled = scale_points_around_hotspot(canvas, &points, scale);
+        draw_drop_shadow(canvas, &scaled, &BUILTIN_SHADOW);
+        draw_filled_polygon(canvas, &scaled, 0.0, 0.0, 0xFFFFFFFF);
+        draw_polygon_outline(canvas, &scaled, 0.0, 0.0, 0xFF000000);
         return;
     }
 
@@ -1125,132 +2193,221 @@ unsafe fn render_custom_cursor_v2(stride: usize, content: &str) {
         all_points.extend(layer.points.iter().cloned());
     }
 
-    let (_, (hx, hy)) = transform_points(&all_points, custom_scale, rotation);
-    CURSOR_HOTSPOT_X.store(hx, Ordering::SeqCst);
-    CURSOR_HOTSPOT_Y.store(hy, Ordering::SeqCst);
+    // Transform all layers together so they keep their relative placement
+    let (all_scaled, (hx, hy)) = match pinned_hotspot {
+        Some((px, py)) => {
+            let placed = scale_rotate_around_hotspot(&all_points, custom_scale, rotation)
+                .into_iter()
+                .map(|(x, y)| (x + px, y + py))
+                .collect();
+            (placed, (px as i32, py as i32))
+        }
+        None => transform_points(&all_points, custom_scale, rotation),
+    };
+    canvas.hotspot = (hx, hy);
+
+    let mut ranges = Vec::with_capacity(layers.len());
+    let mut start = 0;
+    for layer in &layers {
+        ranges.push(start..start + layer.points.len());
+        start += layer.points.len();
+    }
+    let layer_points = |idx: usize| &all_scaled[ranges[idx].clone()];
+    let (width, height) = (canvas.width, canvas.height);
+    let shape_mask = |points: &[(f32, f32)]| {
+        let mut mask = CoverageMask::new(width, height);
+        mask.fill_polygon(points, 0.0, 0.0);
+        mask
+    };
+
+    // Passthrough layers reveal the composite as it was before their target
+    // layer was drawn, keep copies of just those states
+    let mut snapshots: Vec<Option<Vec<LinearPixel>>> = vec![None; layers.len()];
+    let needs_snapshot = |idx: usize| {
+        layers
+            .iter()
+            .enumerate()
+            .any(|(j, l)| l.passthrough_to >= 0 && (l.passthrough_to as usize).min(j) == idx)
+    };
 
     for (i, layer) in layers.iter().enumerate() {
-        if layer.points.len() < 3 {
+        if needs_snapshot(i) {
+            snapshots[i] = Some(canvas.pixels.clone());
+        }
+        // Hidden layers still work as clip paths and masks
+        if layer.points.len() < 3 || hidden.contains(&i) {
             continue;
         }
+        let scaled = layer_points(i);
 
-        let (scaled, _) = transform_points(&layer.points, custom_scale, rotation);
-
-        let is_passthrough = layer.passthrough_to >= 0;
-        if is_passthrough {
-            debug_print!(
-                "Layer {} is passthrough (target: {}) with blur: {}",
-                i,
-                layer.passthrough_to,
-                layer.blur
-            );
-
-            if layer.blur != 0.0 {
-                let frost_mult = CONFIG_FROST_INTENSITY.load(Ordering::Relaxed) as f32 / 100.0;
-                let adjusted_blur = layer.blur * frost_mult;
-                draw_frosted_glass(stride, &scaled, 0.0, 0.0, layer.fill_color, adjusted_blur);
-            } else {
-                let alpha = ((layer.fill_color >> 24) & 0xFF) as f32 / 255.0;
-                let reduced_alpha = (alpha * 0.5 * 255.0) as u32;
-                let tint_color = (reduced_alpha << 24) | (layer.fill_color & 0x00FFFFFF);
-                draw_filled_polygon(stride, &scaled, 0.0, 0.0, tint_color);
-            }
-
-            if layer.outline_width > 0.0 && (layer.outline_color >> 24) > 0 {
-                if layer.blur != 0.0 && layer.blur_outline {
-                    draw_polygon_outline_spiral_blur(
-                        stride,
-                        &scaled,
-                        0.0,
-                        0.0,
-                        layer.outline_color,
-                        layer.blur,
-                    );
-                } else {
-                    draw_polygon_outline(stride, &scaled, 0.0, 0.0, layer.outline_color);
-                }
+        if layer.passthrough_to >= 0 {
+            let target = (layer.passthrough_to as usize).min(i);
+            if let Some(snapshot) = &snapshots[target] {
+                reveal_snapshot(canvas, &shape_mask(scaled), snapshot);
             }
-            continue;
         }
 
-        if layer.shadow_offset > 0.0 && (layer.shadow_color >> 24) > 0 {
-            if layer.blur != 0.0 {
-                draw_filled_polygon_spiral_blur(
-                    stride,
-                    &scaled,
-                    layer.shadow_offset,
-                    layer.shadow_offset,
-                    layer.shadow_color,
-                    layer.blur,
-                );
-            } else {
-                draw_filled_polygon(
-                    stride,
-                    &scaled,
-                    layer.shadow_offset,
-                    layer.shadow_offset,
-                    layer.shadow_color,
-                );
+        // Clipped, masked or non-normal layers are drawn on their own first
+        // so they can be cut and then combined with everything below
+        let masks: Vec<(CoverageMask, MaskMode)> = layer
+            .masks
+            .iter()
+            .filter(|m| m.target != i && m.target < layers.len())
+            .filter(|m| layers[m.target].points.len() >= 3)
+            .map(|m| (shape_mask(layer_points(m.target)), m.mode))
+            .collect();
+
+        if layer.blend_mode == LayerBlendMode::Normal && masks.is_empty() {
+            draw_layer(canvas, i, layer, scaled, custom_scale);
+        } else {
+            let below = begin_layer(canvas);
+            draw_layer(canvas, i, layer, scaled, custom_scale);
+            if !masks.is_empty() {
+                let own = shape_mask(scaled);
+                for (coverage, mode) in &masks {
+                    apply_layer_mask(canvas, coverage, &own, *mode, layer.fill_color);
+                }
             }
+            end_layer(canvas, below, layer.blend_mode);
         }
+    }
 
-        if (layer.fill_color >> 24) > 0 {
-            if layer.blur != 0.0 {
-                draw_filled_polygon_spiral_blur(
-                    stride,
-                    &scaled,
-                    0.0,
-                    0.0,
-                    layer.fill_color,
-                    layer.blur,
-                );
-            } else {
-                draw_filled_polygon(stride, &scaled, 0.0, 0.0, layer.fill_color);
+    log_limited!(
+        Debug,
+        Render,
+        "Rendered custom cursor v2 with {} layers, rotation: {}°, hotspot: ({}, {})",
+        layers.len(),
+        rotation,
+        hx,
+        hy
+    );
+}
+
+/// Draw one v2 layer from its transformed points: shadow, fill and outline
+/// (or the passthrough variant)
+unsafe fn draw_layer(
+    canvas: &mut Canvas,
+    i: usize,
+    layer: &CursorLayer,
+    scaled: &[(f32, f32)],
+    custom_scale: f32,
+) {
+    // Blur radius is in design units like the designer preview, sign is ignored
+    let blur_radius = layer.blur.abs() * custom_scale;
+
+    // Passthrough layers were already punched through to their target,
+    // all that's left is the tint (or frost) and the outline on top
+    if layer.passthrough_to >= 0 {
+        log_limited!(
+            Trace,
+            Render,
+            "Layer {} is passthrough (target: {}) with blur: {}",
+            i,
+            layer.passthrough_to,
+            layer.blur
+        );
+
+        if layer.blur != 0.0 {
+            let frost_mult = CONFIG_FROST_INTENSITY.load(Ordering::Relaxed) as f32 / 100.0;
+            let adjusted_blur = layer.blur * frost_mult;
+            // Blur the real screen when we can read it, fake it with noise otherwise
+            if !draw_backdrop_blur(canvas, scaled, layer.fill_color, blur_radius * frost_mult) {
+                draw_frosted_glass(canvas, scaled, 0.0, 0.0, layer.fill_color, adjusted_blur);
             }
+        } else if (layer.fill_color >> 24) > 0 {
+            draw_filled_polygon(canvas, scaled, 0.0, 0.0, layer.fill_color);
         }
-        // Blur did not work as I wanted, So a lot of this will be refactored
+
         if layer.outline_width > 0.0 && (layer.outline_color >> 24) > 0 {
-            if layer.blur != 0.0 && layer.blur_outline {
-                draw_polygon_outline_spiral_blur(
-                    stride,
-                    &scaled,
-                    0.0,
-                    0.0,
-                    layer.outline_color,
-                    layer.blur,
-                );
+            if blur_radius > 0.0 && layer.blur_outline {
+                draw_blurred_outline(canvas, scaled, layer.outline_color, blur_radius);
             } else {
-                draw_polygon_outline(stride, &scaled, 0.0, 0.0, layer.outline_color);
+                draw_polygon_outline(canvas, scaled, 0.0, 0.0, layer.outline_color);
             }
         }
+        return;
+    }
+
+    // Shadow distances are in design units, so they follow the cursor scale
+    let shadow = DropShadow {
+        offset_x: layer.shadow_offset_x * custom_scale,
+        offset_y: layer.shadow_offset_y * custom_scale,
+        blur: layer.shadow_blur * custom_scale,
+        spread: layer.shadow_spread * custom_scale,
+        color: layer.shadow_color,
+    };
+    draw_drop_shadow(canvas, scaled, &shadow);
+
+    if (layer.fill_color >> 24) > 0 {
+        if blur_radius > 0.0 {
+            draw_blurred_polygon(canvas, scaled, layer.fill_color, blur_radius);
+        } else {
+            draw_filled_polygon(canvas, scaled, 0.0, 0.0, layer.fill_color);
+        }
+    }
 
-        debug_print!(
-            "Rendered layer {} with {} points, blur: {}",
-            i,
-            layer.points.len(),
-            layer.blur
-        );
+    if layer.outline_width > 0.0 && (layer.outline_color >> 24) > 0 {
+        if blur_radius > 0.0 && layer.blur_outline {
+            draw_blurred_outline(canvas, scaled, layer.outline_color, blur_radius);
+        } else {
+            draw_polygon_outline(canvas, scaled, 0.0, 0.0, layer.outline_color);
+        }
     }
 
-    debug_print!(
-        "Rendered custom cursor v2 with {} layers, rotation: {}°, hotspot: ({}, {})",
-        layers.len(),
-        rotation,
-        hx,
-        hy
+    log_limited!(
+        Trace,
+        Render,
+        "Rendered layer {} with {} points, blur: {}",
+        i,
+        layer.points.len(),
+        layer.blur
     );
 }
 
+#[derive(Clone)]
 struct CursorLayer {
     points: Vec<(f32, f32)>,
     fill_color: u32,
     outline_color: u32,
     outline_width: f32,
     shadow_color: u32,
-    shadow_offset: f32,
+    shadow_offset_x: f32,
+    shadow_offset_y: f32,
+    shadow_blur: f32,
+    shadow_spread: f32,
     blur: f32,
     blur_outline: bool,
     passthrough_to: i32,
+    blend_mode: LayerBlendMode,
+    masks: Vec<LayerMask>,
+}
+
+/// How a mask layer's shape cuts the layer it's applied to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum MaskMode {
+    /// Keep only what is inside the mask shape (clipTo)
+    Intersect,
+    /// Cut the mask shape out of the layer
+    Subtract,
+    /// Keep either shape but not their overlap, filled with the layer's fill
+    Exclude,
+}
+
+impl MaskMode {
+    fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "intersect" => MaskMode::Intersect,
+            "exclude" | "xor" => MaskMode::Exclude,
+            _ => MaskMode::Subtract,
+        }
+    }
+}
+
+/// Another layer's shape used as a clip path or mask
+#[derive(Clone)]
+struct LayerMask {
+    target: usize,
+    mode: MaskMode,
 }
 
 fn parse_layers(content: &str) -> Vec<CursorLayer> {
@@ -1264,7 +2421,7 @@ fn parse_layers(content: &str) -> Vec<CursorLayer> {
             let mut in_layer = false;
             let mut layer_start = 0;
 
-            for (i, c) in arr_content.chars().enumerate() {
+            for (i, c) in arr_content.char_indices() {
                 match c {
                     '[' if depth == 0 => depth = 1,
                     ']' if depth == 1 => break,
@@ -1303,7 +2460,7 @@ fn parse_single_layer(layer_str: &str) -> Option<CursorLayer> {
 
     let fill_color_base = parse_color(layer_str, "fill").unwrap_or(0xFFFFFFFF);
     let outline_color_base = parse_color(layer_str, "outline").unwrap_or(0xFF000000);
-    let shadow_color = parse_color(layer_str, "shadow").unwrap_or(0x80000000);
+    let shadow_color_base = parse_color(layer_str, "shadow").unwrap_or(0x80000000);
 
     let fill_alpha = parse_float(layer_str, "fillAlpha").unwrap_or(100.0);
     let outline_alpha = parse_float(layer_str, "outlineAlpha").unwrap_or(100.0);
@@ -1313,11 +2470,47 @@ fn parse_single_layer(layer_str: &str) -> Option<CursorLayer> {
     let fill_color = (fill_alpha_byte << 24) | (fill_color_base & 0x00FFFFFF);
     let outline_color = (outline_alpha_byte << 24) | (outline_color_base & 0x00FFFFFF);
 
+    // shadowAlpha is optional, without it the shadow color keeps its own alpha
+    let shadow_color = match parse_float(layer_str, "shadowAlpha") {
+        Some(alpha) => {
+            let alpha_byte = ((alpha / 100.0 * 255.0) as u32).min(255);
+            (alpha_byte << 24) | (shadow_color_base & 0x00FFFFFF)
+        }
+        None => shadow_color_base,
+    };
+
     let outline_width = parse_float(layer_str, "outlineWidth").unwrap_or(1.0);
+    // shadowOffset sets both axes, shadowOffsetX/Y override them individually
     let shadow_offset = parse_float(layer_str, "shadowOffset").unwrap_or(1.0);
+    let shadow_offset_x = parse_float(layer_str, "shadowOffsetX").unwrap_or(shadow_offset);
+    let shadow_offset_y = parse_float(layer_str, "shadowOffsetY").unwrap_or(shadow_offset);
+    let shadow_blur = parse_float(layer_str, "shadowBlur").unwrap_or(0.0).max(0.0);
+    let shadow_spread = parse_float(layer_str, "shadowSpread").unwrap_or(0.0);
     let blur = parse_float(layer_str, "blur").unwrap_or(0.0);
     let blur_outline = parse_bool(layer_str, "blurOutline").unwrap_or(false);
 
+    let blend_mode = parse_string(layer_str, "blendMode")
+        .map(|name| LayerBlendMode::from_name(&name))
+        .unwrap_or(LayerBlendMode::Normal);
+
+    // clipTo always intersects, maskBy defaults to cutting the shape out
+    let mut masks = Vec::new();
+    if let Some(target) = parse_int(layer_str, "clipTo").filter(|t| *t >= 0) {
+        masks.push(LayerMask {
+            target: target as usize,
+            mode: MaskMode::Intersect,
+        });
+    }
+    if let Some(target) = parse_int(layer_str, "maskBy").filter(|t| *t >= 0) {
+        let mode = parse_string(layer_str, "maskMode")
+            .map(|name| MaskMode::from_name(&name))
+            .unwrap_or(MaskMode::Subtract);
+        masks.push(LayerMask {
+            target: target as usize,
+            mode,
+        });
+    }
+
     let passthrough_to = if let Some(pt) = parse_int(layer_str, "passthroughTo") {
         pt
     } else if parse_bool(layer_str, "passthrough").unwrap_or(false) {
@@ -1332,10 +2525,15 @@ fn parse_single_layer(layer_str: &str) -> Option<CursorLayer> {
         outline_color,
         outline_width,
         shadow_color,
-        shadow_offset,
+        shadow_offset_x,
+        shadow_offset_y,
+        shadow_blur,
+        shadow_spread,
         blur,
         blur_outline,
         passthrough_to,
+        blend_mode,
+        masks,
     })
 }
 
@@ -1353,6 +2551,20 @@ fn parse_bool(content: &str, key: &str) -> Option<bool> {
     None
 }
 
+/// Parse a string value like "blendMode": "multiply" (no escape handling)
+fn parse_string(content: &str, key: &str) -> Option<String> {
+    let search_key = format!("\"{}\"", key);
+    let key_pos = content.find(&search_key)?;
+    let after_key = &content[key_pos + search_key.len()..];
+    let value = after_key
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start()
+        .strip_prefix('"')?;
+    let end = value.find('"')?;
+    Some(value[..end].to_string())
+}
+
 fn parse_int(content: &str, key: &str) -> Option<i32> {
     let search_key = format!("\"{}\"", key);
     if let Some(key_pos) = content.find(&search_key) {
@@ -1385,7 +2597,7 @@ fn parse_layer_points(layer_str: &str) -> Vec<(f32, f32)> {
 
             let mut depth = 0;
             let mut arr_end = 0;
-            for (i, c) in arr_content.chars().enumerate() {
+            for (i, c) in arr_content.char_indices() {
                 match c {
                     '[' => depth += 1,
                     ']' => {
@@ -1406,7 +2618,7 @@ fn parse_layer_points(layer_str: &str) -> Vec<(f32, f32)> {
                 let mut obj_depth = 0;
                 let mut obj_start = 0;
 
-                for (i, c) in points_arr.chars().enumerate() {
+                for (i, c) in points_arr.char_indices() {
                     match c {
                         '{' => {
                             if obj_depth == 0 {
@@ -1474,7 +2686,7 @@ fn parse_custom_points(content: &str) -> Vec<(f32, f32)> {
             let arr_content = &content[start + arr_start..];
             let mut depth = 0;
             let mut arr_end = 0;
-            for (i, c) in arr_content.chars().enumerate() {
+            for (i, c) in arr_content.char_indices() {
                 match c {
                     '[' => depth += 1,
                     ']' => {
@@ -1568,12 +2780,18 @@ fn parse_float(content: &str, key: &str) -> Option<f32> {
 }
 
 /// Scanline fill'n
-unsafe fn draw_filled_polygon(stride: usize, points: &[(f32, f32)], ox: f32, oy: f32, color: u32) {
+unsafe fn draw_filled_polygon(
+    canvas: &mut Canvas,
+    points: &[(f32, f32)],
+    ox: f32,
+    oy: f32,
+    color: u32,
+) {
     if points.is_empty() {
         return;
     }
 
-    let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as i32;
+    let height = canvas.height as i32;
 
     let min_y = points.iter().map(|(_, y)| *y + oy).fold(f32::MAX, f32::min) as i32;
     let max_y = points.iter().map(|(_, y)| *y + oy).fold(f32::MIN, f32::max) as i32;
@@ -1604,11 +2822,11 @@ unsafe fn draw_filled_polygon(stride: usize, points: &[(f32, f32)], ox: f32, oy:
         for chunk in intersections.chunks(2) {
             if chunk.len() == 2 {
                 let x_start = chunk[0].max(0.0) as i32;
-                let x_end = chunk[1].min(stride as f32 - 1.0) as i32;
+                let x_end = chunk[1].min(canvas.width as f32 - 1.0) as i32;
                 for x in x_start..=x_end {
-                    if x >= 0 && (x as usize) < stride {
-                        let idx = y as usize * stride + x as usize;
-                        *CURSOR_BUFFER.add(idx) = blend_pixel(*CURSOR_BUFFER.add(idx), color);
+                    if x >= 0 && (x as usize) < canvas.width {
+                        let idx = y as usize * canvas.width + x as usize;
+                        canvas.blend(idx, color, 1.0);
                     }
                 }
             }
@@ -1616,31 +2834,41 @@ unsafe fn draw_filled_polygon(stride: usize, points: &[(f32, f32)], ox: f32, oy:
     }
 }
 
-unsafe fn draw_polygon_outline(stride: usize, points: &[(f32, f32)], ox: f32, oy: f32, color: u32) {
-    draw_polygon_outline_thickness(stride, points, ox, oy, color, 0.0);
-}
-
-/// Draw polygon outline with configurable thickness
-/// thickness parameter: 0.0 = use config or default (1.0), else use specified value
-unsafe fn draw_polygon_outline_thickness(
-    stride: usize,
+unsafe fn draw_polygon_outline(
+    canvas: &mut Canvas,
     points: &[(f32, f32)],
     ox: f32,
     oy: f32,
     color: u32,
-    thickness: f32,
 ) {
-    // Get thickness from config if not specified
+    draw_polygon_outline_thickness(canvas, points, ox, oy, color, 0.0);
+}
+
+/// Outline thickness to use: explicit value, else config override, else 1.0
+fn resolve_outline_thickness(thickness: f32) -> f32 {
     let config_thickness = CONFIG_OUTLINE_THICKNESS.load(Ordering::Relaxed) as f32 / 10.0;
-    let actual_thickness = if thickness > 0.0 {
+    if thickness > 0.0 {
         thickness
     } else if config_thickness > 0.0 {
         config_thickness
     } else {
         1.0 // default
-    };
+    }
+}
+
+/// Draw polygon outline with configurable thickness
+/// thickness parameter: 0.0 = use config or default (1.0), else use specified value
+unsafe fn draw_polygon_outline_thickness(
+    canvas: &mut Canvas,
+    points: &[(f32, f32)],
+    ox: f32,
+    oy: f32,
+    color: u32,
+    thickness: f32,
+) {
+    let actual_thickness = resolve_outline_thickness(thickness);
 
-    let base_alpha = ((color >> 24) & 0xFF) as u32;
+    let base_alpha = (color >> 24) & 0xFF;
     let rgb = color & 0x00FFFFFF;
 
     // For thickness > 1, draw multiple concentric outlines
@@ -1668,9 +2896,9 @@ unsafe fn draw_polygon_outline_thickness(
                 let len = (dx * dx + dy * dy).sqrt().max(0.001);
                 let nx = -dy / len * offset;
                 let ny = dx / len * offset;
-                draw_line_aa(stride, x1 + nx, y1 + ny, x2 + nx, y2 + ny, pass_color);
+                draw_line_aa(canvas, x1 + nx, y1 + ny, x2 + nx, y2 + ny, pass_color);
             } else {
-                draw_line_aa(stride, x1, y1, x2, y2, pass_color);
+                draw_line_aa(canvas, x1, y1, x2, y2, pass_color);
             }
         }
     }
@@ -1689,108 +2917,19 @@ unsafe fn draw_polygon_outline_thickness(
         let len = (dx * dx + dy * dy).sqrt().max(0.001);
         let nx = -dy / len * (actual_thickness * 0.5 + glow_offset);
         let ny = dx / len * (actual_thickness * 0.5 + glow_offset);
-        draw_line_aa(stride, x1 + nx, y1 + ny, x2 + nx, y2 + ny, glow_color);
+        draw_line_aa(canvas, x1 + nx, y1 + ny, x2 + nx, y2 + ny, glow_color);
     }
 }
 
-/// Frosted outline, draws outline with noise-varied alpha
-/// for what was supposed to be a textured look...
-unsafe fn draw_polygon_outline_spiral_blur(
-    stride: usize,
+unsafe fn draw_frosted_glass(
+    canvas: &mut Canvas,
     points: &[(f32, f32)],
     ox: f32,
     oy: f32,
-    color: u32,
+    tint_color: u32,
     blur_intensity: f32,
 ) {
-    if points.is_empty() || blur_intensity == 0.0 {
-        draw_polygon_outline(stride, points, ox, oy, color);
-        return;
-    }
-
-    let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as usize;
-
-    let base_alpha = ((color >> 24) & 0xFF) as f32;
-    let base_r = ((color >> 16) & 0xFF) as u32;
-    let base_g = ((color >> 8) & 0xFF) as u32;
-    let base_b = (color & 0xFF) as u32;
-    let noise_strength = (blur_intensity * 6.0).min(60.0);
-
-    for i in 0..points.len() {
-        let (x1, y1) = (points[i].0 + ox, points[i].1 + oy);
-        let (x2, y2) = (
-            points[(i + 1) % points.len()].0 + ox,
-            points[(i + 1) % points.len()].1 + oy,
-        );
-
-        let dx = (x2 - x1).abs();
-        let dy = (y2 - y1).abs();
-        let steps = (dx.max(dy) as i32).max(1);
-
-        for step in 0..=steps {
-            let t = step as f32 / steps as f32;
-            let x = (x1 + t * (x2 - x1)) as i32;
-            let y = (y1 + t * (y2 - y1)) as i32;
-
-            if x >= 0 && y >= 0 && (x as usize) < stride && (y as usize) < height {
-                let hash = ((x as u32)
-                    .wrapping_mul(374761393)
-                    .wrapping_add((y as u32).wrapping_mul(668265263)))
-                    ^ ((x as u32).wrapping_add(y as u32).wrapping_mul(1274126177));
-                let noise = ((hash % 1000) as f32 / 500.0) - 1.0;
-                let alpha_var = noise * noise_strength;
-                let final_alpha = (base_alpha + alpha_var).clamp(30.0, 255.0) as u32;
-
-                let color_noise = ((hash >> 10) % 16) as i32 - 8;
-                let final_r = (base_r as i32 + color_noise).clamp(0, 255) as u32;
-                let final_g = (base_g as i32 + color_noise).clamp(0, 255) as u32;
-                let final_b = (base_b as i32 + color_noise).clamp(0, 255) as u32;
-
-                let frosted_color =
-                    (final_alpha << 24) | (final_r << 16) | (final_g << 8) | final_b;
-
-                let idx = y as usize * stride + x as usize;
-                let existing = *CURSOR_BUFFER.add(idx);
-                *CURSOR_BUFFER.add(idx) = blend_pixel(existing, frosted_color);
-            }
-        }
-    }
-}
-
-/// I'm Sorry
-unsafe fn draw_filled_polygon_spiral_blur(
-    stride: usize,
-    points: &[(f32, f32)],
-    ox: f32,
-    oy: f32,
-    color: u32,
-    blur_intensity: f32,
-) {
-    if points.is_empty() || blur_intensity == 0.0 {
-        draw_filled_polygon(stride, points, ox, oy, color);
-        return;
-    }
-
-    let frost_mult = CONFIG_FROST_INTENSITY.load(Ordering::Relaxed) as f32 / 100.0;
-    let adjusted_blur = blur_intensity * frost_mult;
-
-    if adjusted_blur == 0.0 {
-        draw_filled_polygon(stride, points, ox, oy, color);
-        return;
-    }
-
-    draw_frosted_glass(stride, points, ox, oy, color, adjusted_blur);
-}
-
-unsafe fn draw_frosted_glass(
-    stride: usize,
-    points: &[(f32, f32)],
-    ox: f32,
-    oy: f32,
-    tint_color: u32,
-    blur_intensity: f32,
-) {
-    if points.is_empty() {
+    if points.is_empty() {
         return;
     }
 
@@ -1804,7 +2943,7 @@ unsafe fn draw_frosted_glass(
     let alpha_variation_max = (blur_intensity * 25.0).min(100.0);
     let color_variation_max = (blur_intensity * 10.0).min(50.0);
 
-    let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as i32;
+    let height = canvas.height as i32;
 
     let min_y = points.iter().map(|(_, y)| *y + oy).fold(f32::MAX, f32::min) as i32;
     let max_y = points.iter().map(|(_, y)| *y + oy).fold(f32::MIN, f32::max) as i32;
@@ -1834,11 +2973,11 @@ unsafe fn draw_frosted_glass(
         for chunk in intersections.chunks(2) {
             if chunk.len() == 2 {
                 let x_start = chunk[0].max(0.0) as i32;
-                let x_end = chunk[1].min(stride as f32 - 1.0) as i32;
+                let x_end = chunk[1].min(canvas.width as f32 - 1.0) as i32;
 
                 for x in x_start..=x_end {
-                    if x >= 0 && (x as usize) < stride {
-                        let idx = y as usize * stride + x as usize;
+                    if x >= 0 && (x as usize) < canvas.width {
+                        let idx = y as usize * canvas.width + x as usize;
 
                         let cell_x = (x as f32 / cell_size) as i32;
                         let cell_y = (y as f32 / cell_size) as i32;
@@ -1866,30 +3005,14 @@ unsafe fn draw_frosted_glass(
                         let frosted_color =
                             (final_alpha << 24) | (final_r << 16) | (final_g << 8) | final_b;
 
-                        let existing = *CURSOR_BUFFER.add(idx);
-                        let existing_alpha = (existing >> 24) & 0xFF;
-
-                        if existing_alpha > 0 {
-                            let blend = 0.5;
-                            let ex_r = ((existing >> 16) & 0xFF) as f32;
-                            let ex_g = ((existing >> 8) & 0xFF) as f32;
-                            let ex_b = (existing & 0xFF) as f32;
-
-                            let blended_r =
-                                ((ex_r * (1.0 - blend) + final_r as f32 * blend) as u32).min(255);
-                            let blended_g =
-                                ((ex_g * (1.0 - blend) + final_g as f32 * blend) as u32).min(255);
-                            let blended_b =
-                                ((ex_b * (1.0 - blend) + final_b as f32 * blend) as u32).min(255);
-                            let blended_a =
-                                ((existing_alpha as f32 + final_alpha as f32) / 2.0) as u32;
-
-                            *CURSOR_BUFFER.add(idx) = (blended_a << 24)
-                                | (blended_r << 16)
-                                | (blended_g << 8)
-                                | blended_b;
-                        } else {
-                            *CURSOR_BUFFER.add(idx) = frosted_color;
+                        // Frost is mixed half way into whatever is already there
+                        let frosted = LinearPixel::from_argb(frosted_color, 1.0);
+                        if let Some(existing) = canvas.pixels.get_mut(idx) {
+                            *existing = if existing.a > 0.0 {
+                                existing.lerp(frosted, 0.5)
+                            } else {
+                                frosted
+                            };
                         }
                     }
                 }
@@ -1898,18 +3021,10 @@ unsafe fn draw_frosted_glass(
     }
 }
 
-/// Some smart dude math (Bresenham's line algorithm)
-unsafe fn draw_line(_stride: usize, _x0: i32, _y0: i32, _x1: i32, _y1: i32, _color: u32) {
-    // Deprecated: will refactor, use draw_line_aa for anti-aliased lines
-    draw_line_aa(
-        _stride, _x0 as f32, _y0 as f32, _x1 as f32, _y1 as f32, _color,
-    );
-}
-
 /// Anti-aliased line drawing using more smart dude math (Xiaolin Wu's algorithm)
 /// Produces smooth lines by blending pixels at fractional positions
 unsafe fn draw_line_aa(
-    stride: usize,
+    canvas: &mut Canvas,
     mut x0: f32,
     mut y0: f32,
     mut x1: f32,
@@ -1938,11 +3053,11 @@ unsafe fn draw_line_aa(
     let ypxl1 = yend.floor() as i32;
 
     if steep {
-        plot_aa(stride, ypxl1, xpxl1, color, (1.0 - yend.fract()) * xgap);
-        plot_aa(stride, ypxl1 + 1, xpxl1, color, yend.fract() * xgap);
+        plot_aa(canvas, ypxl1, xpxl1, color, (1.0 - yend.fract()) * xgap);
+        plot_aa(canvas, ypxl1 + 1, xpxl1, color, yend.fract() * xgap);
     } else {
-        plot_aa(stride, xpxl1, ypxl1, color, (1.0 - yend.fract()) * xgap);
-        plot_aa(stride, xpxl1, ypxl1 + 1, color, yend.fract() * xgap);
+        plot_aa(canvas, xpxl1, ypxl1, color, (1.0 - yend.fract()) * xgap);
+        plot_aa(canvas, xpxl1, ypxl1 + 1, color, yend.fract() * xgap);
     }
 
     let mut intery = yend + gradient;
@@ -1954,486 +3069,4817 @@ unsafe fn draw_line_aa(
     let ypxl2 = yend.floor() as i32;
 
     if steep {
-        plot_aa(stride, ypxl2, xpxl2, color, (1.0 - yend.fract()) * xgap);
-        plot_aa(stride, ypxl2 + 1, xpxl2, color, yend.fract() * xgap);
+        plot_aa(canvas, ypxl2, xpxl2, color, (1.0 - yend.fract()) * xgap);
+        plot_aa(canvas, ypxl2 + 1, xpxl2, color, yend.fract() * xgap);
     } else {
-        plot_aa(stride, xpxl2, ypxl2, color, (1.0 - yend.fract()) * xgap);
-        plot_aa(stride, xpxl2, ypxl2 + 1, color, yend.fract() * xgap);
+        plot_aa(canvas, xpxl2, ypxl2, color, (1.0 - yend.fract()) * xgap);
+        plot_aa(canvas, xpxl2, ypxl2 + 1, color, yend.fract() * xgap);
     }
 
     for x in (xpxl1 + 1)..xpxl2 {
         if steep {
             plot_aa(
-                stride,
+                canvas,
                 intery.floor() as i32,
                 x,
                 color,
                 1.0 - intery.fract(),
             );
-            plot_aa(stride, intery.floor() as i32 + 1, x, color, intery.fract());
+            plot_aa(canvas, intery.floor() as i32 + 1, x, color, intery.fract());
         } else {
             plot_aa(
-                stride,
+                canvas,
                 x,
                 intery.floor() as i32,
                 color,
                 1.0 - intery.fract(),
             );
-            plot_aa(stride, x, intery.floor() as i32 + 1, color, intery.fract());
+            plot_aa(canvas, x, intery.floor() as i32 + 1, color, intery.fract());
         }
         intery += gradient;
     }
 }
 
 #[inline]
-unsafe fn plot_aa(stride: usize, x: i32, y: i32, color: u32, brightness: f32) {
-    let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as usize;
-    if x < 0 || y < 0 || (x as usize) >= stride || (y as usize) >= height || brightness <= 0.0 {
+unsafe fn plot_aa(canvas: &mut Canvas, x: i32, y: i32, color: u32, brightness: f32) {
+    let height = canvas.height;
+    if x < 0 || y < 0 || (x as usize) >= canvas.width || (y as usize) >= height || brightness <= 0.0
+    {
         return;
     }
 
-    let idx = y as usize * stride + x as usize;
-    let base_alpha = ((color >> 24) & 0xFF) as f32;
-    let aa_alpha = (base_alpha * brightness.clamp(0.0, 1.0)) as u32;
+    let idx = y as usize * canvas.width + x as usize;
+    canvas.blend(idx, color, brightness);
+}
 
-    if aa_alpha == 0 {
-        return;
-    }
+// =============================================================================
+// Compositing (premultiplied alpha, linear light)
+// =============================================================================
 
-    let aa_color = (aa_alpha << 24) | (color & 0x00FFFFFF);
-    let existing = *CURSOR_BUFFER.add(idx);
-    *CURSOR_BUFFER.add(idx) = blend_pixel(existing, aa_color);
+/// Working pixel: premultiplied RGBA in linear light, 0.0-1.0
+#[derive(Clone, Copy, Default, PartialEq)]
+struct LinearPixel {
+    r: f32,
+    g: f32,
+    b: f32,
+    a: f32,
 }
 
-fn blend_pixel(dst: u32, src: u32) -> u32 {
-    let sa = (src >> 24) & 0xFF;
-    if sa == 0 {
-        return dst;
-    }
-    if sa == 255 {
-        return src;
+impl LinearPixel {
+    const TRANSPARENT: LinearPixel = LinearPixel {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+        a: 0.0,
+    };
+
+    /// Convert a straight-alpha sRGB 0xAARRGGBB color, scaled by `coverage`
+    fn from_argb(color: u32, coverage: f32) -> Self {
+        let table = srgb_decode_table();
+        let a = ((color >> 24) & 0xFF) as f32 / 255.0 * coverage.clamp(0.0, 1.0);
+        LinearPixel {
+            r: table[((color >> 16) & 0xFF) as usize] * a,
+            g: table[((color >> 8) & 0xFF) as usize] * a,
+            b: table[(color & 0xFF) as usize] * a,
+            a,
+        }
     }
 
-    let da = (dst >> 24) & 0xFF;
-    let sr = (src >> 16) & 0xFF;
-    let sg = (src >> 8) & 0xFF;
-    let sb = src & 0xFF;
-    let dr = (dst >> 16) & 0xFF;
-    let dg = (dst >> 8) & 0xFF;
-    let db = dst & 0xFF;
+    fn scale(self, factor: f32) -> Self {
+        LinearPixel {
+            r: self.r * factor,
+            g: self.g * factor,
+            b: self.b * factor,
+            a: self.a * factor,
+        }
+    }
 
-    let inv_sa = 255 - sa;
-    let out_a = sa + (da * inv_sa) / 255;
-    let out_r = (sr * sa + dr * inv_sa) / 255;
-    let out_g = (sg * sa + dg * inv_sa) / 255;
-    let out_b = (sb * sa + db * inv_sa) / 255;
+    fn lerp(self, other: LinearPixel, t: f32) -> Self {
+        LinearPixel {
+            r: self.r + (other.r - self.r) * t,
+            g: self.g + (other.g - self.g) * t,
+            b: self.b + (other.b - self.b) * t,
+            a: self.a + (other.a - self.a) * t,
+        }
+    }
+}
 
-    (out_a << 24) | (out_r << 16) | (out_g << 8) | out_b
+/// How the cursor plane composites our pixels, from its "pixel blend mode" property
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+enum PixelBlendMode {
+    /// Color channels already multiplied by alpha (kernel default)
+    Premultiplied = 0,
+    /// Straight alpha, the plane multiplies color by alpha itself
+    Coverage = 1,
+    /// Alpha ignored, pixels are shown as opaque
+    None = 2,
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn ioctl(fd: i32, request: libc::c_ulong, arg: *mut c_void) -> i32 {
-    init_real_functions();
+impl PixelBlendMode {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => PixelBlendMode::Coverage,
+            2 => PixelBlendMode::None,
+            _ => PixelBlendMode::Premultiplied,
+        }
+    }
 
-    // Capture DRM fd from any DRM ioctl (they all start with 0x64 = 'd') //noice
-    if (request >> 8) & 0xFF == 0x64 {
-        if CURSOR_FD.load(Ordering::SeqCst) < 0 {
-            CURSOR_FD.store(fd, Ordering::SeqCst);
-            debug_print!("Captured DRM fd: {}", fd);
+    fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"Pre-multiplied" => Some(PixelBlendMode::Premultiplied),
+            b"Coverage" => Some(PixelBlendMode::Coverage),
+            b"None" => Some(PixelBlendMode::None),
+            _ => None,
         }
     }
+}
 
-    // Handle legacy cursor operations (hopefully)
-    if request == DRM_IOCTL_MODE_CURSOR || request == DRM_IOCTL_MODE_CURSOR2 {
-        debug_print!("Legacy cursor ioctl: 0x{:x}", request);
-        if !INITIALIZED.load(Ordering::SeqCst) {
-            if !create_cursor_buffer(fd, 256, 256) {
-                debug_print!("Failed to create cursor buffer!");
-                return 0;
-            }
-            debug_print!(
-                "Created cursor buffer, FB_ID={}",
-                CURSOR_FB_ID.load(Ordering::SeqCst)
-            );
+fn srgb_decode_table() -> &'static [f32; 256] {
+    static TABLE: std::sync::OnceLock<[f32; 256]> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0f32; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            let c = i as f32 / 255.0;
+            *v = if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            };
         }
+        table
+    })
+}
 
-        // For cursor operations, use OUR proper buffer instead
-        let cursor = arg as *mut DrmModeCursor2;
-        if !cursor.is_null() {
-            let flags = (*cursor).flags;
+/// Linear 0.0-1.0 to 8-bit sRGB, 16-bit table so dark tones keep their steps
+fn srgb_encode(value: f32) -> u32 {
+    static TABLE: std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        (0..=u16::MAX)
+            .map(|i| {
+                let l = i as f32 / u16::MAX as f32;
+                let c = if l <= 0.0031308 {
+                    l * 12.92
+                } else {
+                    1.055 * l.powf(1.0 / 2.4) - 0.055
+                };
+                (c * 255.0).round().clamp(0.0, 255.0) as u8
+            })
+            .collect()
+    });
+    table[(value.clamp(0.0, 1.0) * u16::MAX as f32).round() as usize] as u32
+}
 
-            if flags & DRM_MODE_CURSOR_BO != 0 {
-                // If compositor wants to hide cursor (handle = 0), allow it through
-                if (*cursor).handle == 0 {
-                    debug_print!("Compositor hiding cursor (handle=0), passing through");
-                    return real_ioctl(fd, request, arg);
-                }
+/// Porter-Duff source-over on premultiplied pixels
+fn blend_pixel(dst: LinearPixel, src: LinearPixel) -> LinearPixel {
+    if src.a <= 0.0 {
+        return dst;
+    }
+    let inv = 1.0 - src.a;
+    LinearPixel {
+        r: src.r + dst.r * inv,
+        g: src.g + dst.g * inv,
+        b: src.b + dst.b * inv,
+        a: src.a + dst.a * inv,
+    }
+}
 
-                (*cursor).handle = CURSOR_HANDLE.load(Ordering::SeqCst);
-                // Use display size, not buffer size (hardware may not support large cursors)
-                (*cursor).width = CURSOR_DISPLAY_SIZE;
-                (*cursor).height = CURSOR_DISPLAY_SIZE;
-            }
+/// Encode a working pixel for the plane, `opacity` is applied last
+fn encode_pixel(pixel: LinearPixel, opacity: f32, mode: PixelBlendMode) -> u32 {
+    let alpha = (pixel.a * opacity).clamp(0.0, 1.0);
+    if alpha <= 0.0 || pixel.a <= 0.0 {
+        return 0;
+    }
 
-            return real_ioctl(fd, request, arg);
+    // Un-premultiply to get the straight linear color, then encode it
+    let r = srgb_encode(pixel.r / pixel.a);
+    let g = srgb_encode(pixel.g / pixel.a);
+    let b = srgb_encode(pixel.b / pixel.a);
+    let a = (alpha * 255.0).round() as u32;
+
+    match mode {
+        PixelBlendMode::Coverage => (a << 24) | (r << 16) | (g << 8) | b,
+        PixelBlendMode::Premultiplied | PixelBlendMode::None => {
+            let pm = |c: u32| (c * a + 127) / 255;
+            (a << 24) | (pm(r) << 16) | (pm(g) << 8) | pm(b)
         }
-        return 0;
     }
+}
 
-    real_ioctl(fd, request, arg)
+/// What the renderers draw into: premultiplied linear pixels, plus the
+/// hotspot the design ends up with and the scale and rotation to draw at.
+/// The cursor plane gets one per frame (see `render_cursor`), offline
+/// renders get their own from `render_to_canvas`.
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<LinearPixel>,
+    hotspot: (i32, i32),
+    scale: f32,
+    rotation: f32,
+    // Animation time in ms for offline renders, None = the live animation clock
+    clock: Option<f32>,
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn drmModeSetCursor(
-    fd: i32,
-    crtc_id: u32,
-    bo_handle: u32,
-    _width: u32,
-    _height: u32,
-) -> i32 {
-    // If compositor wants to hide cursor (handle = 0), allow it through
-    if bo_handle == 0 {
-        debug_print!("drmModeSetCursor: hiding cursor (handle=0)");
-        let cursor = DrmModeCursor2 {
-            flags: DRM_MODE_CURSOR_BO,
-            crtc_id,
-            x: 0,
-            y: 0,
-            width: 0,
-            height: 0,
-            handle: 0,
-            hot_x: 0,
-            hot_y: 0,
-        };
-        return real_ioctl(
-            fd,
-            DRM_IOCTL_MODE_CURSOR2,
-            &cursor as *const _ as *mut c_void,
-        );
+impl Canvas {
+    /// Transparent canvas for an offline render, animations frozen at their start
+    pub fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: vec![LinearPixel::TRANSPARENT; width * height],
+            hotspot: (0, 0),
+            scale: 1.5,
+            rotation: 0.0,
+            clock: Some(0.0),
+        }
     }
 
-    if !INITIALIZED.load(Ordering::SeqCst) {
-        if !create_cursor_buffer(fd, 256, 256) {
-            return 0;
+    /// Canvas for the next frame on the cursor plane, reusing the working
+    /// buffer of the last one
+    unsafe fn live(width: usize, height: usize) -> Canvas {
+        let mut pixels =
+            std::mem::take(&mut *RENDER_BUFFER.lock().unwrap_or_else(|e| e.into_inner()));
+        pixels.clear();
+        pixels.resize(width * height, LinearPixel::TRANSPARENT);
+        Canvas {
+            width,
+            height,
+            pixels,
+            hotspot: (0, 0),
+            scale: get_cursor_scale(),
+            rotation: 0.0,
+            clock: None,
         }
     }
 
-    let cursor = DrmModeCursor2 {
-        flags: DRM_MODE_CURSOR_BO,
-        crtc_id,
-        x: 0,
-        y: 0,
-        // Use display size, not buffer size (hardware may not support large cursors)
-        width: CURSOR_DISPLAY_SIZE,
-        height: CURSOR_DISPLAY_SIZE,
-        handle: CURSOR_HANDLE.load(Ordering::SeqCst),
-        hot_x: CURSOR_HOTSPOT_X.load(Ordering::SeqCst),
-        hot_y: CURSOR_HOTSPOT_Y.load(Ordering::SeqCst),
-    };
+    pub fn width(&self) -> usize {
+        self.width
+    }
 
-    real_ioctl(
-        fd,
-        DRM_IOCTL_MODE_CURSOR2,
-        &cursor as *const _ as *mut c_void,
-    )
-}
+    pub fn height(&self) -> usize {
+        self.height
+    }
 
-/// FaceSmack drmModeSetCursor2
-#[no_mangle]
-pub unsafe extern "C" fn drmModeSetCursor2(
-    fd: i32,
-    crtc_id: u32,
-    bo_handle: u32,
-    _width: u32,
-    _height: u32,
-    hot_x: i32,
-    hot_y: i32,
-) -> i32 {
-    // If compositor wants to hide cursor (handle = 0)
-    if bo_handle == 0 {
-        debug_print!("drmModeSetCursor2: hiding cursor (handle=0)");
-        CURSOR_VISIBLE.store(false, Ordering::SeqCst);
+    /// Where the pointer is in the image, in pixels from the top left
+    pub fn hotspot(&self) -> (i32, i32) {
+        self.hotspot
+    }
 
-        // If fade is enabled, start the poor excuse of fading instead of instant hide
-        if cursor_fade_enabled() {
-            CURSOR_FADING_OUT.store(true, Ordering::SeqCst);
-            // But suprise, Don't actually hide yet, the fade will happen in drmModeMoveCursor
-            return 0;
+    /// Pixels as straight alpha sRGB 0xAARRGGBB, row by row
+    pub fn to_argb(&self) -> Vec<u32> {
+        self.pixels
+            .iter()
+            .map(|p| encode_pixel(*p, 1.0, PixelBlendMode::Coverage))
+            .collect()
+    }
+
+    /// The top left `width` x `height` of the canvas, hotspot included
+    pub fn crop(&self, width: usize, height: usize) -> Canvas {
+        let (width, height) = (width.min(self.width), height.min(self.height));
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in self.pixels.chunks(self.width).take(height) {
+            pixels.extend_from_slice(&row[..width]);
         }
+        Canvas {
+            width,
+            height,
+            pixels,
+            ..*self
+        }
+    }
 
-        let cursor = DrmModeCursor2 {
-            flags: DRM_MODE_CURSOR_BO,
-            crtc_id,
-            x: 0,
-            y: 0,
-            width: 0,
-            height: 0,
-            handle: 0,
-            hot_x: 0,
-            hot_y: 0,
-        };
-        return real_ioctl(
-            fd,
-            DRM_IOCTL_MODE_CURSOR2,
-            &cursor as *const _ as *mut c_void,
-        );
+    /// Live canvases belong to the cursor on screen and may sample the
+    /// screen under it, offline ones only see the design
+    fn is_live(&self) -> bool {
+        self.clock.is_none()
     }
 
-    CURSOR_VISIBLE.store(true, Ordering::SeqCst);
-    CURSOR_FADING_OUT.store(false, Ordering::SeqCst);
-    CURSOR_FADE_ALPHA.store(255, Ordering::SeqCst);
+    /// Time into the design's animation in ms, see `animation_clock`
+    unsafe fn animation_time(&self) -> f32 {
+        match self.clock {
+            Some(time) => time,
+            None => animation_clock(),
+        }
+    }
 
-    if !INITIALIZED.load(Ordering::SeqCst) {
-        if !create_cursor_buffer(fd, 256, 256) {
-            return 0;
+    /// Blend a straight-alpha sRGB color into the pixel at `idx`
+    #[inline]
+    fn blend(&mut self, idx: usize, color: u32, coverage: f32) {
+        if let Some(dst) = self.pixels.get_mut(idx) {
+            *dst = blend_pixel(*dst, LinearPixel::from_argb(color, coverage));
         }
     }
+}
 
-    let new_hot_x = hot_x + CURSOR_HOTSPOT_X.load(Ordering::SeqCst);
-    let new_hot_y = hot_y + CURSOR_HOTSPOT_Y.load(Ordering::SeqCst);
+/// Write a finished frame to the mmap'd cursor buffer in the plane's format
+/// and make its hotspot ours
+unsafe fn present_render(canvas: Canvas, opacity: f32) {
+    CURSOR_HOTSPOT_X.store(canvas.hotspot.0, Ordering::SeqCst);
+    CURSOR_HOTSPOT_Y.store(canvas.hotspot.1, Ordering::SeqCst);
+
+    if !CURSOR_BUFFER.is_null() {
+        let mode = PixelBlendMode::from_u32(CURSOR_BLEND_MODE.load(Ordering::Relaxed));
+        let width = CURSOR_WIDTH.load(Ordering::SeqCst) as usize;
+        let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as usize;
+
+        for i in 0..(width * height) {
+            let pixel = canvas
+                .pixels
+                .get(i)
+                .copied()
+                .unwrap_or(LinearPixel::TRANSPARENT);
+            *CURSOR_BUFFER.add(i) = encode_pixel(pixel, opacity, mode);
+        }
+    }
+    *RENDER_BUFFER.lock().unwrap_or_else(|e| e.into_inner()) = canvas.pixels;
+}
 
-    let final_hot_x;
-    let final_hot_y;
+/// How a design layer combines with the layers below it (`blendMode`)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LayerBlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+    Difference,
+    Xor,
+    Erase,
+}
 
-    if !HOTSPOT_INITIALIZED.load(Ordering::SeqCst) {
-        final_hot_x = new_hot_x;
-        final_hot_y = new_hot_y;
-        APPLIED_HOTSPOT_X.store(final_hot_x, Ordering::SeqCst);
-        APPLIED_HOTSPOT_Y.store(final_hot_y, Ordering::SeqCst);
-        HOTSPOT_INITIALIZED.store(true, Ordering::SeqCst);
-        debug_print!("Hotspot initialized: ({},{})", final_hot_x, final_hot_y);
-    } else {
-        load_config();
+impl LayerBlendMode {
+    fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "multiply" => LayerBlendMode::Multiply,
+            "screen" => LayerBlendMode::Screen,
+            "overlay" => LayerBlendMode::Overlay,
+            "add" | "plus" | "lighter" => LayerBlendMode::Add,
+            "difference" => LayerBlendMode::Difference,
+            "xor" => LayerBlendMode::Xor,
+            "erase" | "destination-out" => LayerBlendMode::Erase,
+            _ => LayerBlendMode::Normal,
+        }
+    }
 
-        let current_hot_x = APPLIED_HOTSPOT_X.load(Ordering::SeqCst);
-        let current_hot_y = APPLIED_HOTSPOT_Y.load(Ordering::SeqCst);
-
-        if CONFIG_HOTSPOT_SMOOTHING.load(Ordering::Relaxed) {
-            let threshold = CONFIG_HOTSPOT_THRESHOLD.load(Ordering::Relaxed);
-            let dx = (new_hot_x - current_hot_x).abs();
-            let dy = (new_hot_y - current_hot_y).abs();
-
-            if dx > threshold || dy > threshold {
-                final_hot_x = current_hot_x + (new_hot_x - current_hot_x) / 3;
-                final_hot_y = current_hot_y + (new_hot_y - current_hot_y) / 3;
-                APPLIED_HOTSPOT_X.store(final_hot_x, Ordering::SeqCst);
-                APPLIED_HOTSPOT_Y.store(final_hot_y, Ordering::SeqCst);
-                debug_print!(
-                    "Hotspot smoothed: ({},{}) -> ({},{})",
-                    current_hot_x,
-                    current_hot_y,
-                    final_hot_x,
-                    final_hot_y
-                );
-            } else {
-                final_hot_x = current_hot_x;
-                final_hot_y = current_hot_y;
+    /// Separable blend function B(Cb, Cs) on straight (non-premultiplied) channels
+    fn mix(self, backdrop: f32, source: f32) -> f32 {
+        match self {
+            LayerBlendMode::Multiply => backdrop * source,
+            LayerBlendMode::Screen => backdrop + source - backdrop * source,
+            LayerBlendMode::Overlay => {
+                if backdrop <= 0.5 {
+                    2.0 * backdrop * source
+                } else {
+                    let b = 2.0 * backdrop - 1.0;
+                    b + source - b * source
+                }
             }
-        } else {
-            final_hot_x = new_hot_x;
-            final_hot_y = new_hot_y;
-            APPLIED_HOTSPOT_X.store(final_hot_x, Ordering::SeqCst);
-            APPLIED_HOTSPOT_Y.store(final_hot_y, Ordering::SeqCst);
+            LayerBlendMode::Difference => (backdrop - source).abs(),
+            _ => source,
         }
     }
+}
 
-    let cursor = DrmModeCursor2 {
-        flags: DRM_MODE_CURSOR_BO,
-        crtc_id,
-        x: 0,
-        y: 0,
-        // Use display size, not buffer size (again, hardware may not support large cursors)
-        width: CURSOR_DISPLAY_SIZE,
-        height: CURSOR_DISPLAY_SIZE,
-        handle: CURSOR_HANDLE.load(Ordering::SeqCst),
-        hot_x: final_hot_x,
-        hot_y: final_hot_y,
-    };
+/// Combine a premultiplied layer pixel `src` onto `dst` with the given mode
+fn blend_layer_pixel(dst: LinearPixel, src: LinearPixel, mode: LayerBlendMode) -> LinearPixel {
+    let (sa, da) = (src.a, dst.a);
+    match mode {
+        LayerBlendMode::Normal => blend_pixel(dst, src),
+        LayerBlendMode::Add => LinearPixel {
+            r: src.r + dst.r,
+            g: src.g + dst.g,
+            b: src.b + dst.b,
+            a: (sa + da).min(1.0),
+        },
+        LayerBlendMode::Xor => LinearPixel {
+            r: src.r * (1.0 - da) + dst.r * (1.0 - sa),
+            g: src.g * (1.0 - da) + dst.g * (1.0 - sa),
+            b: src.b * (1.0 - da) + dst.b * (1.0 - sa),
+            a: sa * (1.0 - da) + da * (1.0 - sa),
+        },
+        LayerBlendMode::Erase => LinearPixel {
+            r: dst.r * (1.0 - sa),
+            g: dst.g * (1.0 - sa),
+            b: dst.b * (1.0 - sa),
+            a: da * (1.0 - sa),
+        },
+        _ => {
+            if sa <= 0.0 {
+                return dst;
+            }
+            if da <= 0.0 {
+                return src;
+            }
+            // W3C compositing: co = cs(1 - ab) + cb(1 - as) + as*ab*B(Cb, Cs)
+            let channel = |s: f32, d: f32| {
+                let mixed = mode.mix(d / da, s / sa);
+                s * (1.0 - da) + d * (1.0 - sa) + sa * da * mixed
+            };
+            LinearPixel {
+                r: channel(src.r, dst.r),
+                g: channel(src.g, dst.g),
+                b: channel(src.b, dst.b),
+                a: sa + da - sa * da,
+            }
+        }
+    }
+}
 
-    real_ioctl(
-        fd,
-        DRM_IOCTL_MODE_CURSOR2,
-        &cursor as *const _ as *mut c_void,
-    )
+/// Start drawing a layer on its own, returns what was rendered so far
+fn begin_layer(canvas: &mut Canvas) -> Vec<LinearPixel> {
+    let len = canvas.pixels.len();
+    std::mem::replace(&mut canvas.pixels, vec![LinearPixel::TRANSPARENT; len])
 }
 
-/// Apply uniform alpha fade to cursor buffer
-/// This does not work as intended yet.
-/// All non-zero pixels should get scaled to target_alpha proportionally
-/// This should ensures outline and fill fade together perceptually
-/// Outline still does its own thing, It will be fixed later
-unsafe fn apply_cursor_fade(target_alpha: f32) {
-    if CURSOR_BUFFER.is_null() {
-        return;
+/// Put `below` back as the render buffer and combine the finished layer onto it
+fn end_layer(canvas: &mut Canvas, below: Vec<LinearPixel>, mode: LayerBlendMode) {
+    let layer = std::mem::replace(&mut canvas.pixels, below);
+    for (dst, src) in canvas.pixels.iter_mut().zip(layer.iter()) {
+        *dst = blend_layer_pixel(*dst, *src, mode);
     }
+}
 
-    let width = CURSOR_WIDTH.load(Ordering::SeqCst) as usize;
-    let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as usize;
-    let target = target_alpha.clamp(0.0, 255.0);
-
-    for i in 0..(width * height) {
-        let pixel = *CURSOR_BUFFER.add(i);
-        let orig_a = ((pixel >> 24) & 0xFF) as f32;
+/// Cut the layer being drawn with a mask shape. `own` is the layer's own
+/// shape, used by Exclude to fill the part of the mask outside the layer.
+fn apply_layer_mask(
+    canvas: &mut Canvas,
+    mask: &CoverageMask,
+    own: &CoverageMask,
+    mode: MaskMode,
+    fill: u32,
+) {
+    let fill = LinearPixel::from_argb(fill, 1.0);
+    for (i, pixel) in canvas.pixels.iter_mut().enumerate() {
+        let m = mask.data.get(i).copied().unwrap_or(0.0);
+        *pixel = match mode {
+            MaskMode::Intersect => pixel.scale(m),
+            MaskMode::Subtract => pixel.scale(1.0 - m),
+            MaskMode::Exclude => {
+                let outside_own = m * (1.0 - own.data.get(i).copied().unwrap_or(0.0));
+                blend_pixel(pixel.scale(1.0 - m), fill.scale(outside_own))
+            }
+        };
+    }
+}
 
-        if orig_a > 0.0 {
-            let new_a = ((orig_a / 255.0) * target) as u32;
-            *CURSOR_BUFFER.add(i) = (new_a << 24) | (pixel & 0x00FFFFFF);
+/// Inside `shape`, replace the render buffer with an earlier snapshot of it
+fn reveal_snapshot(canvas: &mut Canvas, shape: &CoverageMask, snapshot: &[LinearPixel]) {
+    for (i, pixel) in canvas.pixels.iter_mut().enumerate() {
+        let coverage = shape.data.get(i).copied().unwrap_or(0.0);
+        if coverage > 0.0 {
+            let earlier = snapshot.get(i).copied().unwrap_or(LinearPixel::TRANSPARENT);
+            *pixel = pixel.lerp(earlier, coverage);
         }
     }
 }
 
-/// Spawn a thread to handle our fade-out animation
-fn spawn_fade_out_thread() {
-    if FADE_THREAD_RUNNING.swap(true, Ordering::SeqCst) {
-        return;
+// =============================================================================
+// Coverage masks, blur and drop shadows
+// =============================================================================
+
+/// Single channel coverage buffer the size of the cursor buffer.
+/// Shapes get rasterized in here first so they can be spread and blurred
+/// before being composited into the render buffer with a flat color.
+/// Only the touched region (bounds) is processed, the rest stays at zero.
+struct CoverageMask {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+    // (min_x, min_y, max_x, max_y) inclusive, None while the mask is empty
+    bounds: Option<(usize, usize, usize, usize)>,
+}
+
+impl CoverageMask {
+    fn new(width: usize, height: usize) -> Self {
+        CoverageMask {
+            width,
+            height,
+            data: vec![0.0; width * height],
+            bounds: None,
+        }
     }
 
-    thread::spawn(move || {
-        let fade_speed = CONFIG_FADE_SPEED.load(Ordering::Relaxed) as f32;
-        let frame_time = Duration::from_millis(16); // ~60fps
-        let step = fade_speed.max(5.0);
+    fn include(&mut self, x: usize, y: usize) {
+        self.bounds = Some(match self.bounds {
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+            None => (x, y, x, y),
+        });
+    }
 
-        let mut alpha = 255.0_f32;
+    /// Grow the bounds by `radius` pixels, clamped to the buffer
+    fn expand_bounds(&mut self, radius: usize) {
+        if let Some((x0, y0, x1, y1)) = self.bounds {
+            self.bounds = Some((
+                x0.saturating_sub(radius),
+                y0.saturating_sub(radius),
+                (x1 + radius).min(self.width - 1),
+                (y1 + radius).min(self.height - 1),
+            ));
+        }
+    }
 
-        while alpha > 0.0 {
-            if !CURSOR_FADING_OUT.load(Ordering::SeqCst) {
-                break;
-            }
+    /// Scanline fill, samples pixel centers the same way draw_filled_polygon does
+    fn fill_polygon(&mut self, points: &[(f32, f32)], ox: f32, oy: f32) {
+        if points.is_empty() || self.width == 0 || self.height == 0 {
+            return;
+        }
 
-            alpha = (alpha - step).max(0.0);
+        let min_y = points.iter().map(|(_, y)| *y + oy).fold(f32::MAX, f32::min) as i32;
+        let max_y = points.iter().map(|(_, y)| *y + oy).fold(f32::MIN, f32::max) as i32;
+        let min_y = min_y.max(0);
+        let max_y = max_y.min(self.height as i32 - 1);
 
+        for y in min_y..=max_y {
+            let mut intersections = Vec::new();
+            let yf = y as f32 + 0.5;
+
+            for i in 0..points.len() {
+                let (x1, y1) = (points[i].0 + ox, points[i].1 + oy);
+                let (x2, y2) = (
+                    points[(i + 1) % points.len()].0 + ox,
+                    points[(i + 1) % points.len()].1 + oy,
+                );
+
+                if (y1 <= yf && y2 > yf) || (y2 <= yf && y1 > yf) {
+                    intersections.push(x1 + (yf - y1) / (y2 - y1) * (x2 - x1));
+                }
+            }
+
+            intersections.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            for chunk in intersections.chunks(2) {
+                if chunk.len() == 2 {
+                    let x_start = chunk[0].max(0.0) as i32;
+                    let x_end = chunk[1].min(self.width as f32 - 1.0) as i32;
+                    if x_end < x_start {
+                        continue;
+                    }
+                    for x in x_start..=x_end {
+                        self.data[y as usize * self.width + x as usize] = 1.0;
+                    }
+                    self.include(x_start as usize, y as usize);
+                    self.include(x_end as usize, y as usize);
+                }
+            }
+        }
+    }
+
+    /// Grow (positive) or shrink (negative) the covered area by whole pixels.
+    /// Separable max/min filter, so the structuring element is a square.
+    fn spread(&mut self, amount: f32) {
+        let radius = amount.abs().round() as usize;
+        if radius == 0 || self.bounds.is_none() {
+            return;
+        }
+        let grow = amount > 0.0;
+        if grow {
+            self.expand_bounds(radius);
+        }
+        let (x0, y0, x1, y1) = self.bounds.unwrap();
+        let (w, h) = (self.width, self.height);
+        let pick = |a: f32, b: f32| if grow { a.max(b) } else { a.min(b) };
+
+        let mut tmp = vec![0.0f32; w * h];
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let lo = x.saturating_sub(radius);
+                let hi = (x + radius).min(w - 1);
+                let mut v = self.data[y * w + x];
+                for sx in lo..=hi {
+                    v = pick(v, self.data[y * w + sx]);
+                }
+                // Erosion treats everything off-buffer as empty
+                if !grow && (x < radius || x + radius >= w) {
+                    v = 0.0;
+                }
+                tmp[y * w + x] = v;
+            }
+        }
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let lo = y.saturating_sub(radius);
+                let hi = (y + radius).min(h - 1);
+                let mut v = tmp[y * w + x];
+                for sy in lo..=hi {
+                    v = pick(v, tmp[sy * w + x]);
+                }
+                if !grow && (y < radius || y + radius >= h) {
+                    v = 0.0;
+                }
+                self.data[y * w + x] = v;
+            }
+        }
+    }
+
+    /// Separable Gaussian blur, `sigma` is the standard deviation in pixels
+    /// (the same unit the designer's canvas `blur()` filter uses)
+    fn gaussian_blur(&mut self, sigma: f32) {
+        if sigma < 0.1 || self.bounds.is_none() {
+            return;
+        }
+        let kernel = gaussian_kernel(sigma);
+        let radius = kernel.len() / 2;
+        self.expand_bounds(radius);
+        let (x0, y0, x1, y1) = self.bounds.unwrap();
+        let (w, h) = (self.width, self.height);
+
+        let mut tmp = vec![0.0f32; w * h];
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let mut sum = 0.0;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let sx = x as isize + k as isize - radius as isize;
+                    if sx >= 0 && (sx as usize) < w {
+                        sum += self.data[y * w + sx as usize] * weight;
+                    }
+                }
+                tmp[y * w + x] = sum;
+            }
+        }
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let mut sum = 0.0;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let sy = y as isize + k as isize - radius as isize;
+                    if sy >= 0 && (sy as usize) < h {
+                        sum += tmp[sy as usize * w + x] * weight;
+                    }
+                }
+                self.data[y * w + x] = sum.min(1.0);
+            }
+        }
+    }
+
+    /// Separable box blur, averages a (2 * radius + 1) wide window per axis
+    fn box_blur(&mut self, radius: usize) {
+        if radius == 0 || self.bounds.is_none() {
+            return;
+        }
+        self.expand_bounds(radius);
+        let (x0, y0, x1, y1) = self.bounds.unwrap();
+        let (w, h) = (self.width, self.height);
+        let norm = 1.0 / (2 * radius + 1) as f32;
+
+        let mut tmp = vec![0.0f32; w * h];
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let lo = x.saturating_sub(radius);
+                let hi = (x + radius).min(w - 1);
+                let sum: f32 = self.data[y * w + lo..=y * w + hi].iter().sum();
+                tmp[y * w + x] = sum * norm;
+            }
+        }
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let lo = y.saturating_sub(radius);
+                let hi = (y + radius).min(h - 1);
+                let sum: f32 = (lo..=hi).map(|sy| tmp[sy * w + x]).sum();
+                self.data[y * w + x] = (sum * norm).min(1.0);
+            }
+        }
+    }
+
+    /// Blur by a radius in pixels with the configured filter.
+    /// The Gaussian uses radius / 2 as sigma so both filters reach about as far.
+    fn blur(&mut self, radius: f32, filter: BlurFilter) {
+        match filter {
+            BlurFilter::Gaussian => self.gaussian_blur(radius / 2.0),
+            BlurFilter::Box => self.box_blur(radius.round() as usize),
+        }
+    }
+
+    /// Anti-aliased stroke along the closed polygon, coverage falls off over
+    /// one pixel at the edges of a `width` wide band centered on each edge
+    fn stroke_polygon(&mut self, points: &[(f32, f32)], ox: f32, oy: f32, width: f32) {
+        if points.len() < 2 || self.width == 0 || self.height == 0 {
+            return;
+        }
+
+        let half = width * 0.5 + 0.5;
+        let min_x = points.iter().map(|p| p.0 + ox).fold(f32::MAX, f32::min) - half;
+        let max_x = points.iter().map(|p| p.0 + ox).fold(f32::MIN, f32::max) + half;
+        let min_y = points.iter().map(|p| p.1 + oy).fold(f32::MAX, f32::min) - half;
+        let max_y = points.iter().map(|p| p.1 + oy).fold(f32::MIN, f32::max) + half;
+
+        let x_start = (min_x.floor() as i32).max(0);
+        let x_end = (max_x.ceil() as i32).min(self.width as i32 - 1);
+        let y_start = (min_y.floor() as i32).max(0);
+        let y_end = (max_y.ceil() as i32).min(self.height as i32 - 1);
+
+        for y in y_start..=y_end {
+            for x in x_start..=x_end {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let mut dist = f32::MAX;
+                for i in 0..points.len() {
+                    let (x1, y1) = (points[i].0 + ox, points[i].1 + oy);
+                    let (x2, y2) = (
+                        points[(i + 1) % points.len()].0 + ox,
+                        points[(i + 1) % points.len()].1 + oy,
+                    );
+                    dist = dist.min(distance_to_segment(px, py, x1, y1, x2, y2));
+                }
+                let coverage = (half - dist).clamp(0.0, 1.0);
+                if coverage > 0.0 {
+                    let idx = y as usize * self.width + x as usize;
+                    self.data[idx] = self.data[idx].max(coverage);
+                    self.include(x as usize, y as usize);
+                }
+            }
+        }
+    }
+}
+
+fn distance_to_segment(px: f32, py: f32, x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
+    let dx = x2 - x1;
+    let dy = y2 - y1;
+    let len_sq = dx * dx + dy * dy;
+    let t = if len_sq > 0.0 {
+        (((px - x1) * dx + (py - y1) * dy) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let cx = x1 + t * dx - px;
+    let cy = y1 + t * dy - py;
+    (cx * cx + cy * cy).sqrt()
+}
+
+/// Normalized 1D Gaussian kernel covering +-3 sigma
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (sigma * 3.0).ceil().max(1.0) as i32;
+    let denom = 2.0 * sigma * sigma;
+    let mut kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / denom).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+    for w in kernel.iter_mut() {
+        *w /= total;
+    }
+    kernel
+}
+
+/// Blend a flat color into the render buffer, modulated by mask coverage
+unsafe fn composite_mask(canvas: &mut Canvas, mask: &CoverageMask, color: u32) {
+    let Some((x0, y0, x1, y1)) = mask.bounds else {
+        return;
+    };
+    let height = canvas.height;
+
+    for y in y0..=y1.min(height.saturating_sub(1)) {
+        for x in x0..=x1.min(canvas.width.saturating_sub(1)) {
+            let coverage = mask.data[y * mask.width + x];
+            if coverage > 0.0 {
+                canvas.blend(y * canvas.width + x, color, coverage);
+            }
+        }
+    }
+}
+
+/// Filter used for layer blur, selected with `blur_filter` in cursor.conf
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+enum BlurFilter {
+    Gaussian = 0,
+    Box = 1,
+}
+
+impl BlurFilter {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => BlurFilter::Box,
+            _ => BlurFilter::Gaussian,
+        }
+    }
+}
+
+fn get_blur_filter() -> BlurFilter {
+    BlurFilter::from_u32(CONFIG_BLUR_FILTER.load(Ordering::Relaxed))
+}
+
+/// Fill a polygon through an offscreen mask so it can be blurred by `radius`
+/// pixels before it lands in the cursor buffer
+unsafe fn draw_blurred_polygon(
+    canvas: &mut Canvas,
+    points: &[(f32, f32)],
+    color: u32,
+    radius: f32,
+) {
+    let height = canvas.height;
+    let mut mask = CoverageMask::new(canvas.width, height);
+    mask.fill_polygon(points, 0.0, 0.0);
+    mask.blur(radius, get_blur_filter());
+    composite_mask(canvas, &mask, color);
+}
+
+/// Same as draw_blurred_polygon but for the outline, stroked at the same
+/// thickness the sharp outline would use
+unsafe fn draw_blurred_outline(
+    canvas: &mut Canvas,
+    points: &[(f32, f32)],
+    color: u32,
+    radius: f32,
+) {
+    let height = canvas.height;
+    let mut mask = CoverageMask::new(canvas.width, height);
+    mask.stroke_polygon(points, 0.0, 0.0, resolve_outline_thickness(0.0));
+    mask.blur(radius, get_blur_filter());
+    composite_mask(canvas, &mask, color);
+}
+
+/// Drop shadow parameters, all distances in cursor buffer pixels
+struct DropShadow {
+    offset_x: f32,
+    offset_y: f32,
+    /// Gaussian standard deviation, 0 = hard edged shadow
+    blur: f32,
+    /// Grow (or shrink when negative) the shadow shape before blurring
+    spread: f32,
+    color: u32,
+}
+
+/// Soft shadow used by the built-in cursor shapes
+const BUILTIN_SHADOW: DropShadow = DropShadow {
+    offset_x: 1.0,
+    offset_y: 1.0,
+    blur: 1.0,
+    spread: 0.0,
+    color: 0x80000000,
+};
+
+impl DropShadow {
+    fn is_visible(&self) -> bool {
+        (self.color >> 24) > 0
+            && (self.offset_x != 0.0
+                || self.offset_y != 0.0
+                || self.blur > 0.0
+                || self.spread > 0.0)
+    }
+}
+
+/// Render the shadow of a polygon: rasterize it into a mask at the shadow
+/// offset, spread it, blur it, then composite it with the shadow color
+unsafe fn draw_drop_shadow(canvas: &mut Canvas, points: &[(f32, f32)], shadow: &DropShadow) {
+    if points.is_empty() || !shadow.is_visible() {
+        return;
+    }
+
+    let height = canvas.height;
+    let mut mask = CoverageMask::new(canvas.width, height);
+    mask.fill_polygon(points, shadow.offset_x, shadow.offset_y);
+    mask.spread(shadow.spread);
+    mask.gaussian_blur(shadow.blur);
+    composite_mask(canvas, &mask, shadow.color);
+}
+
+// =============================================================================
+// Primary plane sampling (frosted glass)
+// =============================================================================
+
+/// A primary plane framebuffer mapped for reading
+struct PrimaryMapping {
+    fb_id: u32,
+    ptr: *mut c_void,
+    len: usize,
+    offset: usize,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+// Compositors flip between a few buffers, keep each of them mapped
+const PRIMARY_MAPPING_CACHE: usize = 4;
+static mut PRIMARY_MAPPINGS: Vec<PrimaryMapping> = Vec::new();
+
+/// The cached mappings, callers hold RENDER_LOCK
+unsafe fn primary_mappings() -> &'static mut Vec<PrimaryMapping> {
+    &mut *std::ptr::addr_of_mut!(PRIMARY_MAPPINGS)
+}
+// FBs that can't be read (tiled, compressed, not dumb), not worth retrying
+static mut UNMAPPABLE_FBS: [u32; PRIMARY_MAPPING_CACHE] = [0; PRIMARY_MAPPING_CACHE];
+static mut UNMAPPABLE_NEXT: usize = 0;
+// The compositor's dumb buffers and the FBs it put on them. Those are
+// linear even when ADDFB2 said nothing about modifiers.
+static COMPOSITOR_DUMB_HANDLES: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static COMPOSITOR_DUMB_FBS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Keep track of which FBs are on dumb buffers, from an ioctl of the
+/// compositor that went through
+unsafe fn note_dumb_buffers(request: libc::c_ulong, arg: *mut c_void) {
+    let mut handles = COMPOSITOR_DUMB_HANDLES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let mut fbs = COMPOSITOR_DUMB_FBS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    match request {
+        DRM_IOCTL_MODE_CREATE_DUMB => handles.push((*(arg as *const DrmModeCreateDumb)).handle),
+        DRM_IOCTL_MODE_DESTROY_DUMB => {
+            let handle = (*(arg as *const DrmModeDestroyDumb)).handle;
+            handles.retain(|h| *h != handle);
+        }
+        DRM_IOCTL_MODE_ADDFB => {
+            let fb = &*(arg as *const DrmModeFbCmd);
+            if handles.contains(&fb.handle) {
+                fbs.push(fb.fb_id);
+            }
+        }
+        DRM_IOCTL_MODE_ADDFB2 => {
+            let fb = &*(arg as *const DrmModeFB2);
+            if handles.contains(&fb.handles[0]) {
+                fbs.push(fb.fb_id);
+            }
+        }
+        DRM_IOCTL_MODE_RMFB => {
+            let fb_id = *(arg as *const u32);
+            fbs.retain(|f| *f != fb_id);
+        }
+        _ => {}
+    }
+}
+
+/// An FB is gone and the kernel may hand its id to the next one. Drop its
+/// mapping, which also lets go of the buffer, and whatever we knew about it.
+unsafe fn forget_framebuffer(fb_id: u32) {
+    let _render = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mappings = primary_mappings();
+    if let Some(idx) = mappings.iter().position(|m| m.fb_id == fb_id) {
+        let old = mappings.remove(idx);
+        let start = old.ptr as *mut u8;
+        if (start..start.add(old.len)).contains(&(PRIMARY_FB_BUFFER as *mut u8)) {
+            PRIMARY_FB_BUFFER = std::ptr::null_mut();
+        }
+        libc::munmap(old.ptr, old.len);
+    }
+
+    let mut unmappable = UNMAPPABLE_FBS;
+    for id in unmappable.iter_mut().filter(|id| **id == fb_id) {
+        *id = 0;
+    }
+    UNMAPPABLE_FBS = unmappable;
+}
+
+/// Make PRIMARY_FB_BUFFER point at the current primary FB.
+/// Only linear 32 bit RGB buffers that the driver lets us map are usable.
+/// Callers hold RENDER_LOCK, evicting a mapping unmaps it.
+unsafe fn map_primary_fb() -> bool {
+    let fb_id = PRIMARY_FB_ID.load(Ordering::SeqCst);
+    let fd = CURSOR_FD.load(Ordering::SeqCst);
+    let unmappable = UNMAPPABLE_FBS;
+    if fb_id == 0 || fd < 0 || unmappable.contains(&fb_id) {
+        return false;
+    }
+
+    let mappings = primary_mappings();
+    if let Some(mapping) = mappings.iter().find(|m| m.fb_id == fb_id) {
+        PRIMARY_FB_BUFFER = (mapping.ptr as *mut u8).add(mapping.offset) as *mut u32;
+        PRIMARY_FB_WIDTH.store(mapping.width, Ordering::SeqCst);
+        PRIMARY_FB_HEIGHT.store(mapping.height, Ordering::SeqCst);
+        PRIMARY_FB_STRIDE.store(mapping.stride, Ordering::SeqCst);
+        return true;
+    }
+
+    match map_framebuffer(fd, fb_id) {
+        Some(mapping) => {
+            log!(
+                Debug,
+                Drm,
+                "Mapped primary FB {} ({}x{}, stride {})",
+                fb_id,
+                mapping.width,
+                mapping.height,
+                mapping.stride
+            );
+            if mappings.len() >= PRIMARY_MAPPING_CACHE {
+                let old = mappings.remove(0);
+                libc::munmap(old.ptr, old.len);
+            }
+            mappings.push(mapping);
+            map_primary_fb()
+        }
+        None => {
+            log!(
+                Info,
+                Drm,
+                "Primary FB {} can't be sampled, using fake frost",
+                fb_id
+            );
+            UNMAPPABLE_FBS[UNMAPPABLE_NEXT] = fb_id;
+            UNMAPPABLE_NEXT = (UNMAPPABLE_NEXT + 1) % PRIMARY_MAPPING_CACHE;
+            PRIMARY_FB_BUFFER = std::ptr::null_mut();
+            false
+        }
+    }
+}
+
+/// Look up a framebuffer with GETFB2 and mmap it read only through MAP_DUMB
+unsafe fn map_framebuffer(fd: i32, fb_id: u32) -> Option<PrimaryMapping> {
+    let mut fb = DrmModeFB2 {
+        fb_id,
+        ..Default::default()
+    };
+    if real_ioctl(fd, DRM_IOCTL_MODE_GETFB2, &mut fb as *mut _ as *mut c_void) < 0 {
+        return None;
+    }
+
+    // Handles are only handed out to the DRM master
+    let handle = fb.handles[0];
+    if handle == 0 {
+        return None;
+    }
+
+    // Without explicit modifiers the layout is up to the driver, only
+    // dumb buffers are linear for sure
+    let linear = if fb.flags & DRM_MODE_FB_MODIFIERS != 0 {
+        fb.modifier[0] == DRM_FORMAT_MOD_LINEAR
+    } else {
+        COMPOSITOR_DUMB_FBS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(&fb_id)
+    };
+    let rgb32 = matches!(fb.pixel_format, DRM_FORMAT_XRGB8888 | DRM_FORMAT_ARGB8888);
+
+    let mut mapping = None;
+    if linear && rgb32 && fb.pitches[0] >= fb.width * 4 {
+        let mut map = DrmModeMapDumb {
+            handle,
+            ..Default::default()
+        };
+        if real_ioctl(
+            fd,
+            DRM_IOCTL_MODE_MAP_DUMB,
+            &mut map as *mut _ as *mut c_void,
+        ) == 0
+        {
+            let len = (fb.offsets[0] + fb.pitches[0] * fb.height) as usize;
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd,
+                map.offset as i64,
+            );
+            if ptr != libc::MAP_FAILED {
+                mapping = Some(PrimaryMapping {
+                    fb_id,
+                    ptr,
+                    len,
+                    offset: fb.offsets[0] as usize,
+                    width: fb.width,
+                    height: fb.height,
+                    stride: fb.pitches[0] / 4,
+                });
+            }
+        }
+    }
+
+    // The mapping keeps the buffer alive, our handle isn't needed anymore
+    let mut close = DrmGemClose {
+        handle,
+        ..Default::default()
+    };
+    real_ioctl(fd, DRM_IOCTL_GEM_CLOSE, &mut close as *mut _ as *mut c_void);
+
+    mapping
+}
+
+/// Read a primary plane pixel, coordinates are clamped to the screen
+unsafe fn sample_primary_pixel(x: i32, y: i32) -> u32 {
+    let width = PRIMARY_FB_WIDTH.load(Ordering::SeqCst) as i32;
+    let height = PRIMARY_FB_HEIGHT.load(Ordering::SeqCst) as i32;
+    let stride = PRIMARY_FB_STRIDE.load(Ordering::SeqCst) as usize;
+    if width <= 0 || height <= 0 {
+        return 0;
+    }
+    let x = x.clamp(0, width - 1) as usize;
+    let y = y.clamp(0, height - 1) as usize;
+    std::ptr::read_volatile(PRIMARY_FB_BUFFER.add(y * stride + x))
+}
+
+/// Frosted glass from the real screen: blur what the primary plane shows
+/// under the layer's shape, put whatever the layer revealed on top of it
+/// and tint it with the fill color.
+/// Returns false when the primary plane can't be read.
+unsafe fn draw_backdrop_blur(
+    canvas: &mut Canvas,
+    points: &[(f32, f32)],
+    tint: u32,
+    radius: f32,
+) -> bool {
+    // Offline renders have no screen behind them
+    if !canvas.is_live() || !map_primary_fb() {
+        return false;
+    }
+
+    let height = canvas.height;
+    let mut shape = CoverageMask::new(canvas.width, height);
+    shape.fill_polygon(points, 0.0, 0.0);
+    let Some(bounds) = shape.bounds else {
+        return true;
+    };
+
+    // Sample into a padded local buffer so the blur can pull from the screen
+    // around the shape, even past the edges of the cursor buffer
+    let (sx0, sy0, sx1, sy1) = bounds;
+    let margin = (radius * 1.5).ceil() as usize + 1;
+    let local_w = sx1 - sx0 + 1 + margin * 2;
+    let local_h = sy1 - sy0 + 1 + margin * 2;
+    let origin_x = CURSOR_SCREEN_X.load(Ordering::SeqCst) + sx0 as i32 - margin as i32;
+    let origin_y = CURSOR_SCREEN_Y.load(Ordering::SeqCst) + sy0 as i32 - margin as i32;
+
+    let table = srgb_decode_table();
+    let mut channels = [
+        CoverageMask::new(local_w, local_h),
+        CoverageMask::new(local_w, local_h),
+        CoverageMask::new(local_w, local_h),
+    ];
+    for ly in 0..local_h {
+        for lx in 0..local_w {
+            let pixel = sample_primary_pixel(origin_x + lx as i32, origin_y + ly as i32);
+            let idx = ly * local_w + lx;
+            channels[0].data[idx] = table[((pixel >> 16) & 0xFF) as usize];
+            channels[1].data[idx] = table[((pixel >> 8) & 0xFF) as usize];
+            channels[2].data[idx] = table[(pixel & 0xFF) as usize];
+        }
+    }
+    for channel in channels.iter_mut() {
+        channel.bounds = Some((0, 0, local_w - 1, local_h - 1));
+        channel.blur(radius, get_blur_filter());
+    }
+
+    let tint = LinearPixel::from_argb(tint, 1.0);
+    for y in sy0..=sy1 {
+        for x in sx0..=sx1 {
+            let idx = y * canvas.width + x;
+            let coverage = shape.data[idx];
+            if coverage <= 0.0 {
+                continue;
+            }
+            let local = (y - sy0 + margin) * local_w + (x - sx0 + margin);
+            let backdrop = LinearPixel {
+                r: channels[0].data[local],
+                g: channels[1].data[local],
+                b: channels[2].data[local],
+                a: 1.0,
+            };
+            if let Some(existing) = canvas.pixels.get_mut(idx) {
+                let frosted = blend_pixel(blend_pixel(backdrop, *existing), tint);
+                *existing = existing.lerp(frosted, coverage);
+            }
+        }
+    }
+
+    CURSOR_SAMPLES_BACKDROP.store(true, Ordering::SeqCst);
+    true
+}
+
+// =============================================================================
+// Visibility and fades
+// =============================================================================
+
+/// Whether the cursor is on screen. The compositor only ever asks to show or
+/// hide, fades are the states in between and can be turned around halfway.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+enum Visibility {
+    Shown = 0,
+    FadingOut = 1,
+    Hidden = 2,
+    FadingIn = 3,
+}
+
+impl Visibility {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => Visibility::FadingOut,
+            2 => Visibility::Hidden,
+            3 => Visibility::FadingIn,
+            _ => Visibility::Shown,
+        }
+    }
+}
+
+/// Easing curve of fades (`fade_easing`)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+enum FadeEasing {
+    Linear = 0,
+    EaseIn = 1,
+    EaseOut = 2,
+    EaseInOut = 3,
+}
+
+impl FadeEasing {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            0 => FadeEasing::Linear,
+            1 => FadeEasing::EaseIn,
+            3 => FadeEasing::EaseInOut,
+            _ => FadeEasing::EaseOut,
+        }
+    }
+
+    fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "linear" => FadeEasing::Linear,
+            "ease-in" | "in" => FadeEasing::EaseIn,
+            "ease-in-out" | "in-out" | "ease" => FadeEasing::EaseInOut,
+            _ => FadeEasing::EaseOut,
+        }
+    }
+
+    /// Cubic curves, `t` is 0.0 - 1.0
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            FadeEasing::Linear => t,
+            FadeEasing::EaseIn => t * t * t,
+            FadeEasing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            FadeEasing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (2.0 - 2.0 * t).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+fn visibility() -> Visibility {
+    Visibility::from_u32(CURSOR_VISIBILITY.load(Ordering::SeqCst))
+}
+
+/// How long a full fade takes. `fade_speed` used to be the alpha step per
+/// frame, keep what it felt like at 60fps.
+fn fade_duration_ms() -> u64 {
+    let speed = CONFIG_FADE_SPEED.load(Ordering::Relaxed).clamp(1, 255) as u64;
+    (255 * FRAME_INTERVAL.as_millis() as u64 / speed).max(1)
+}
+
+/// Eased progress of the running fade and whether it's done
+fn fade_progress() -> (f32, bool) {
+    let elapsed = monotonic_ms().saturating_sub(FADE_START_MS.load(Ordering::SeqCst));
+    let t = (elapsed as f32 / fade_duration_ms() as f32).min(1.0);
+    let easing = FadeEasing::from_u32(CONFIG_FADE_EASING.load(Ordering::Relaxed));
+    (easing.apply(t), t >= 1.0)
+}
+
+/// Opacity the cursor is presented with, applied to the finished render
+fn cursor_opacity() -> f32 {
+    let from = f32::from_bits(FADE_FROM_OPACITY.load(Ordering::SeqCst));
+    match visibility() {
+        Visibility::Shown => 1.0,
+        Visibility::Hidden => 0.0,
+        Visibility::FadingOut => from * (1.0 - fade_progress().0),
+        Visibility::FadingIn => from + (1.0 - from) * fade_progress().0,
+    }
+}
+
+/// Start a fade from wherever the cursor is now, so a fade that gets
+/// cancelled halfway turns around instead of jumping
+fn begin_fade(state: Visibility) {
+    FADE_FROM_OPACITY.store(cursor_opacity().to_bits(), Ordering::SeqCst);
+    FADE_START_MS.store(monotonic_ms(), Ordering::SeqCst);
+    CURSOR_VISIBILITY.store(state as u32, Ordering::SeqCst);
+    ensure_animation_thread();
+}
+
+/// The compositor hid the cursor. Returns true when we keep showing our
+/// buffer to fade it out, the hide is then done by `finish_fade_out`.
+unsafe fn cursor_hide_requested() -> bool {
+    // Hidden for real now, moving shouldn't bring it back
+    IDLE_HIDDEN.store(false, Ordering::SeqCst);
+    match visibility() {
+        Visibility::Hidden => false,
+        Visibility::FadingOut => true,
+        Visibility::Shown | Visibility::FadingIn => {
+            if cursor_fade_enabled() {
+                log!(Debug, Fade, "Cursor fading out");
+                begin_fade(Visibility::FadingOut);
+                true
+            } else {
+                CURSOR_VISIBILITY.store(Visibility::Hidden as u32, Ordering::SeqCst);
+                false
+            }
+        }
+    }
+}
+
+/// The compositor showed the cursor again, fade it in if configured
+unsafe fn cursor_show_requested() {
+    let state = visibility();
+    if matches!(state, Visibility::Shown | Visibility::FadingIn)
+        || IDLE_HIDDEN.load(Ordering::SeqCst)
+    {
+        return;
+    }
+
+    load_config();
+    if CONFIG_FADE_IN_ENABLED.load(Ordering::Relaxed) {
+        log!(Debug, Fade, "Cursor fading in");
+        begin_fade(Visibility::FadingIn);
+    } else {
+        CURSOR_VISIBILITY.store(Visibility::Shown as u32, Ordering::SeqCst);
+    }
+    // A finished fade out left the buffer empty
+    render_cursor();
+}
+
+/// Move a fade that has run its course on to its end state, returns the
+/// state when that happened
+fn step_visibility() -> Option<Visibility> {
+    let state = visibility();
+    let done = match state {
+        Visibility::FadingOut => Visibility::Hidden,
+        Visibility::FadingIn => Visibility::Shown,
+        _ => return None,
+    };
+    if !fade_progress().1 {
+        return None;
+    }
+    // The compositor may have turned the fade around meanwhile
+    CURSOR_VISIBILITY
+        .compare_exchange(
+            state as u32,
+            done as u32,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        )
+        .ok()
+        .map(|_| done)
+}
+
+/// Do the hide the compositor asked for once the fade out is over
+unsafe fn finish_fade_out() {
+    log!(Debug, Fade, "Cursor fade out finished");
+    let fd = CURSOR_FD.load(Ordering::SeqCst);
+    // Idle hides keep the cursor up with an empty buffer, the compositor
+    // still thinks it's shown and only moves it
+    let idle = IDLE_HIDDEN.load(Ordering::SeqCst);
+    let crtc_id = if idle {
+        0
+    } else {
+        LEGACY_CURSOR_CRTC.swap(0, Ordering::SeqCst)
+    };
+    if crtc_id != 0 && fd >= 0 {
+        let cursor = DrmModeCursor2 {
+            flags: DRM_MODE_CURSOR_BO,
+            crtc_id,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            handle: 0,
+            hot_x: 0,
+            hot_y: 0,
+        };
+        real_ioctl(
+            fd,
+            DRM_IOCTL_MODE_CURSOR2,
+            &cursor as *const _ as *mut c_void,
+        );
+        return;
+    }
+
+    // Atomic compositors still have our FB on the plane
+    if CURSOR_BUFFER.is_null() {
+        return;
+    }
+    clear_cursor_buffer();
+    flush_cursor_fb();
+}
+
+// =============================================================================
+// Compositor hotspot
+// =============================================================================
+
+// The compositor positions the cursor as pointer minus the hotspot of its own
+// image. To put our hotspot on the pointer we need that hotspot, per shape:
+// legacy SetCursor2 and the HOTSPOT_X/Y plane properties tell us, otherwise
+// we guess from the compositor's image and correct the guess from how far
+// the cursor jumps when the shape changes under a pointer that stands still.
+
+/// Where we know a compositor hotspot from, later ones are more reliable
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum HotspotSource {
+    Image,
+    Delta,
+    Explicit,
+}
+
+/// The hotspot of one of the compositor's cursor images
+#[derive(Clone, Copy)]
+struct CompositorShape {
+    /// Hash of the image, compositors redraw their buffers for new shapes
+    key: u64,
+    hotspot: (i32, i32),
+    size: (i32, i32),
+    source: HotspotSource,
+}
+
+/// How to get at the pixels of a compositor cursor image
+enum ShapeImage {
+    None,
+    Framebuffer(u32),
+    Handle {
+        handle: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+/// A shape change under a pointer that stood still, the position the
+/// compositor sends next shows how far its hotspot moved
+#[derive(Clone, Copy)]
+struct ShapeChange {
+    key: u64,
+    position: (i32, i32),
+    hotspot: (i32, i32),
+    at_ms: u64,
+}
+
+const COMPOSITOR_SHAPE_CACHE: usize = 32;
+// No moves for this long before a shape change = the pointer stood still
+const SHAPE_STILL_MS: u64 = 100;
+// Positions sent this soon after a shape change belong to it
+const SHAPE_DELTA_WINDOW_MS: u64 = 20;
+
+static COMPOSITOR_SHAPES: Mutex<Vec<CompositorShape>> = Mutex::new(Vec::new());
+// Buffer (legacy BO handle or atomic FB_ID) and image the compositor shows,
+// the key is 0 when we couldn't read the image
+static mut COMPOSITOR_BUFFER: u32 = 0;
+static mut COMPOSITOR_SHAPE_KEY: u64 = 0;
+static mut SHAPE_CHANGE: Option<ShapeChange> = None;
+
+/// How far to move the compositor's cursor position so our hotspot lands
+/// where its hotspot would have. Zero while we don't know its hotspot.
+fn hotspot_offset() -> (i32, i32) {
+    if !COMPOSITOR_HOTSPOT_KNOWN.load(Ordering::SeqCst) {
+        return (0, 0);
+    }
+    (
+        COMPOSITOR_HOTSPOT_X.load(Ordering::SeqCst) - CURSOR_HOTSPOT_X.load(Ordering::SeqCst),
+        COMPOSITOR_HOTSPOT_Y.load(Ordering::SeqCst) - CURSOR_HOTSPOT_Y.load(Ordering::SeqCst),
+    )
+}
+
+/// Use `hotspot` for the current compositor shape and remember it for when
+/// the shape comes back, unless we already know better
+unsafe fn remember_compositor_hotspot(hotspot: (i32, i32), source: HotspotSource) {
+    let key = COMPOSITOR_SHAPE_KEY;
+    let mut shapes = COMPOSITOR_SHAPES.lock().unwrap_or_else(|e| e.into_inner());
+    match shapes.iter_mut().find(|s| s.key == key) {
+        _ if key == 0 => {}
+        Some(shape) if shape.source > source => return,
+        Some(shape) => {
+            shape.hotspot = hotspot;
+            shape.source = source;
+        }
+        None => {
+            if shapes.len() >= COMPOSITOR_SHAPE_CACHE {
+                shapes.remove(0);
+            }
+            shapes.push(CompositorShape {
+                key,
+                hotspot,
+                size: (0, 0),
+                source,
+            });
+        }
+    }
+    COMPOSITOR_HOTSPOT_X.store(hotspot.0, Ordering::SeqCst);
+    COMPOSITOR_HOTSPOT_Y.store(hotspot.1, Ordering::SeqCst);
+    COMPOSITOR_HOTSPOT_KNOWN.store(true, Ordering::SeqCst);
+}
+
+/// The compositor put up a cursor image in `buffer`. Looks up or works out
+/// its hotspot, `hotspot` is the one the compositor gave if it did.
+unsafe fn compositor_shape_changed(buffer: u32, hotspot: Option<(i32, i32)>, image: ShapeImage) {
+    // Atomic commits resend the FB_ID every time, legacy calls only come
+    // with new images (possibly drawn into the same BO)
+    if buffer == COMPOSITOR_BUFFER && !matches!(image, ShapeImage::Handle { .. }) {
+        if let Some(hotspot) = hotspot {
+            remember_compositor_hotspot(hotspot, HotspotSource::Explicit);
+        }
+        return;
+    }
+    COMPOSITOR_BUFFER = buffer;
+
+    let still =
+        monotonic_ms().saturating_sub(COMPOSITOR_MOVE_MS.load(Ordering::SeqCst)) > SHAPE_STILL_MS;
+    let from = (
+        COMPOSITOR_HOTSPOT_X.load(Ordering::SeqCst),
+        COMPOSITOR_HOTSPOT_Y.load(Ordering::SeqCst),
+    );
+    let from_known = COMPOSITOR_HOTSPOT_KNOWN.load(Ordering::SeqCst);
+
+    if let Some(hotspot) = hotspot {
+        COMPOSITOR_SHAPE_KEY = 0;
+        SHAPE_CHANGE = None;
+        remember_compositor_hotspot(hotspot, HotspotSource::Explicit);
+        return;
+    }
+
+    let Some(shape) = read_compositor_image(image) else {
+        // Better not to correct at all than by a made up hotspot
+        COMPOSITOR_SHAPE_KEY = 0;
+        SHAPE_CHANGE = None;
+        COMPOSITOR_HOTSPOT_KNOWN.store(false, Ordering::SeqCst);
+        return;
+    };
+    COMPOSITOR_SHAPE_KEY = shape.key;
+    SHAPE_CHANGE = (still && from_known).then(|| ShapeChange {
+        key: shape.key,
+        position: (
+            COMPOSITOR_CURSOR_X.load(Ordering::SeqCst),
+            COMPOSITOR_CURSOR_Y.load(Ordering::SeqCst),
+        ),
+        hotspot: from,
+        at_ms: monotonic_ms(),
+    });
+
+    let mut shapes = COMPOSITOR_SHAPES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(known) = shapes.iter().find(|s| s.key == shape.key) {
+        COMPOSITOR_HOTSPOT_X.store(known.hotspot.0, Ordering::SeqCst);
+        COMPOSITOR_HOTSPOT_Y.store(known.hotspot.1, Ordering::SeqCst);
+        COMPOSITOR_HOTSPOT_KNOWN.store(true, Ordering::SeqCst);
+        return;
+    }
+
+    log!(
+        Debug,
+        Drm,
+        "Compositor cursor {:x} looks like its hotspot is at ({},{})",
+        shape.key,
+        shape.hotspot.0,
+        shape.hotspot.1
+    );
+    if shapes.len() >= COMPOSITOR_SHAPE_CACHE {
+        shapes.remove(0);
+    }
+    shapes.push(shape);
+    drop(shapes);
+    remember_compositor_hotspot(shape.hotspot, HotspotSource::Image);
+}
+
+/// A position right after a shape change under a still pointer: the jump
+/// is the hotspot difference between the two shapes
+unsafe fn refine_compositor_hotspot(x: i32, y: i32) {
+    let Some(change) = SHAPE_CHANGE else {
+        return;
+    };
+    if monotonic_ms().saturating_sub(change.at_ms) > SHAPE_DELTA_WINDOW_MS
+        || change.key != COMPOSITOR_SHAPE_KEY
+    {
+        SHAPE_CHANGE = None;
+        return;
+    }
+
+    let hotspot = (
+        change.hotspot.0 - (x - change.position.0),
+        change.hotspot.1 - (y - change.position.1),
+    );
+    // Only when it's inside the image, otherwise the pointer did move
+    let size = COMPOSITOR_SHAPES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|s| s.key == change.key)
+        .map_or((0, 0), |s| s.size);
+    let inside = (0..size.0.max(CURSOR_DISPLAY_SIZE as i32)).contains(&hotspot.0)
+        && (0..size.1.max(CURSOR_DISPLAY_SIZE as i32)).contains(&hotspot.1);
+    if inside {
+        remember_compositor_hotspot(hotspot, HotspotSource::Delta);
+    }
+}
+
+/// Map a compositor cursor image, hash it and guess its hotspot
+unsafe fn read_compositor_image(image: ShapeImage) -> Option<CompositorShape> {
+    let fd = CURSOR_FD.load(Ordering::SeqCst);
+    if fd < 0 {
+        return None;
+    }
+
+    let mapping = match image {
+        ShapeImage::None => return None,
+        ShapeImage::Framebuffer(fb_id) => map_framebuffer(fd, fb_id)?,
+        ShapeImage::Handle {
+            handle,
+            width,
+            height,
+        } => {
+            // Legacy cursor BOs are dumb buffers without padding
+            let mut map = DrmModeMapDumb {
+                handle,
+                ..Default::default()
+            };
+            if width == 0
+                || height == 0
+                || real_ioctl(
+                    fd,
+                    DRM_IOCTL_MODE_MAP_DUMB,
+                    &mut map as *mut _ as *mut c_void,
+                ) != 0
+            {
+                return None;
+            }
+            let len = (width * height * 4) as usize;
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd,
+                map.offset as i64,
+            );
+            if ptr == libc::MAP_FAILED {
+                return None;
+            }
+            PrimaryMapping {
+                fb_id: 0,
+                ptr,
+                len,
+                offset: 0,
+                width,
+                height,
+                stride: width,
+            }
+        }
+    };
+
+    let base = (mapping.ptr as *const u8).add(mapping.offset) as *const u32;
+    let (width, height) = (mapping.width as usize, mapping.height as usize);
+    let stride = mapping.stride as usize;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(std::ptr::read_volatile(base.add(y * stride + x)));
+        }
+    }
+    libc::munmap(mapping.ptr, mapping.len);
+
+    let hotspot = estimate_hotspot(width, height, |x, y| pixels[y * width + x] >> 24 > 127)?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    (width, height, &pixels).hash(&mut hasher);
+    Some(CompositorShape {
+        // 0 means unknown
+        key: hasher.finish().max(1),
+        hotspot,
+        size: (width as i32, height as i32),
+        source: HotspotSource::Image,
+    })
+}
+
+/// Guess the hotspot of a cursor image from which pixels are `opaque`:
+/// the middle of symmetric shapes (crosshairs, spinners, I-beams), the
+/// tip at the top for everything else (arrows, hands)
+fn estimate_hotspot(
+    width: usize,
+    height: usize,
+    opaque: impl Fn(usize, usize) -> bool,
+) -> Option<(i32, i32)> {
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for y in 0..height {
+        for x in 0..width {
+            if opaque(x, y) {
+                bounds = Some(match bounds {
+                    None => (x, y, x, y),
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                });
+            }
+        }
+    }
+    let (x0, y0, x1, y1) = bounds?;
+
+    let mut total = 0;
+    let mut mirrored = 0;
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let here = opaque(x, y);
+            total += 2;
+            mirrored += (here == opaque(x0 + x1 - x, y)) as usize;
+            mirrored += (here == opaque(x, y0 + y1 - y)) as usize;
+        }
+    }
+    if mirrored * 10 >= total * 9 {
+        return Some((((x0 + x1) / 2) as i32, ((y0 + y1) / 2) as i32));
+    }
+
+    // Middle of the first run of pixels on the top row
+    let start = (x0..=x1).find(|&x| opaque(x, y0))?;
+    let end = (start..=x1).take_while(|&x| opaque(x, y0)).last()?;
+    Some((((start + end) / 2) as i32, y0 as i32))
+}
+
+// =============================================================================
+// Idle hide
+// =============================================================================
+
+/// The pointer moved: restart the idle timer and bring back an idle cursor
+unsafe fn note_cursor_moved() {
+    LAST_MOVE_MS.store(monotonic_ms(), Ordering::SeqCst);
+
+    if IDLE_HIDDEN.swap(false, Ordering::SeqCst) {
+        log!(Debug, Fade, "Cursor moved, showing it again");
+        cursor_show_requested();
+        flush_cursor_fb();
+    }
+
+    if CONFIG_IDLE_HIDE_MS.load(Ordering::Relaxed) > 0 {
+        ensure_idle_thread();
+    }
+}
+
+/// Start the idle timer thread unless it is running. It sleeps until the
+/// cursor could have gone idle and exits once it has hidden the cursor,
+/// the next move starts it again.
+fn ensure_idle_thread() {
+    if IDLE_THREAD_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    thread::spawn(|| loop {
+        let timeout = CONFIG_IDLE_HIDE_MS.load(Ordering::Relaxed) as u64;
+        let idle = monotonic_ms().saturating_sub(LAST_MOVE_MS.load(Ordering::SeqCst));
+        if timeout > 0 && idle < timeout {
+            thread::sleep(Duration::from_millis(timeout - idle));
+            continue;
+        }
+
+        if timeout > 0 {
             unsafe {
-                if !CURSOR_BUFFER.is_null() {
-                    render_cursor();
-                    apply_cursor_fade(alpha);
+                idle_hide();
+            }
+        }
+        IDLE_THREAD_RUNNING.store(false, Ordering::SeqCst);
+
+        // A move may have brought the cursor back before we stopped
+        let shown = matches!(visibility(), Visibility::Shown | Visibility::FadingIn)
+            && !IDLE_HIDDEN.load(Ordering::SeqCst);
+        if timeout == 0 || !shown || IDLE_THREAD_RUNNING.swap(true, Ordering::SeqCst) {
+            return;
+        }
+    });
+}
+
+/// Hide a shown cursor for being idle, fading it out when fades are on
+unsafe fn idle_hide() {
+    if !matches!(visibility(), Visibility::Shown | Visibility::FadingIn) {
+        return;
+    }
+    log!(Debug, Fade, "Cursor idle, hiding it");
+    IDLE_HIDDEN.store(true, Ordering::SeqCst);
+
+    if cursor_fade_enabled() {
+        begin_fade(Visibility::FadingOut);
+    } else {
+        CURSOR_VISIBILITY.store(Visibility::Hidden as u32, Ordering::SeqCst);
+        finish_fade_out();
+    }
+}
+
+// =============================================================================
+// Animation
+// =============================================================================
+
+// ~60fps
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+fn monotonic_ms() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Time into the current animation in ms, for renderers of animated designs.
+/// Calling it marks the frame as animated so the scheduler keeps rendering,
+/// the clock starts over whenever an animation starts playing.
+unsafe fn animation_clock() -> f32 {
+    FRAME_ANIMATED.store(true, Ordering::SeqCst);
+    if !ANIMATION_ACTIVE.load(Ordering::SeqCst) {
+        ANIMATION_CLOCK_START.store(monotonic_ms(), Ordering::SeqCst);
+    }
+    monotonic_ms().saturating_sub(ANIMATION_CLOCK_START.load(Ordering::SeqCst)) as f32
+}
+
+/// Called at the end of every render to start or stop frame scheduling
+unsafe fn finish_animation_frame() {
+    let animated = FRAME_ANIMATED.load(Ordering::SeqCst);
+    ANIMATION_ACTIVE.store(animated, Ordering::SeqCst);
+    let playing = animated && !FRAME_ANIMATION_DONE.load(Ordering::SeqCst);
+    if playing && !ANIMATION_PLAYING.swap(true, Ordering::SeqCst) {
+        log!(Debug, Render, "Cursor animation started");
+    }
+    if playing {
+        ensure_animation_thread();
+    } else {
+        ANIMATION_PLAYING.store(false, Ordering::SeqCst);
+    }
+}
+
+fn animation_work_pending() -> bool {
+    match visibility() {
+        Visibility::FadingOut | Visibility::FadingIn => true,
+        Visibility::Hidden => false,
+        Visibility::Shown => ANIMATION_PLAYING.load(Ordering::SeqCst),
+    }
+}
+
+/// Start the animation thread unless it is already running. It renders
+/// fades and animated designs on a fixed frame interval and exits once
+/// there is nothing left to animate.
+fn ensure_animation_thread() {
+    if ANIMATION_THREAD_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    thread::spawn(|| loop {
+        if !animation_work_pending() {
+            ANIMATION_THREAD_RUNNING.store(false, Ordering::SeqCst);
+            // Work may have shown up right before we stopped
+            if animation_work_pending() && !ANIMATION_THREAD_RUNNING.swap(true, Ordering::SeqCst) {
+                continue;
+            }
+            return;
+        }
+
+        unsafe {
+            animation_tick();
+        }
+        thread::sleep(FRAME_INTERVAL);
+    });
+}
+
+/// One scheduler frame: step the fade, render and hand it to the display
+unsafe fn animation_tick() {
+    if step_visibility() == Some(Visibility::Hidden) {
+        finish_fade_out();
+        return;
+    }
+    if CURSOR_BUFFER.is_null() {
+        return;
+    }
+
+    if matches!(visibility(), Visibility::FadingOut | Visibility::FadingIn) {
+        STAT_FADE_FRAMES.fetch_add(1, Ordering::Relaxed);
+    }
+    render_cursor();
+    flush_cursor_fb();
+}
+
+unsafe fn clear_cursor_buffer() {
+    let _render = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let width = CURSOR_WIDTH.load(Ordering::SeqCst) as usize;
+    let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as usize;
+    std::ptr::write_bytes(CURSOR_BUFFER, 0, width * height);
+}
+
+/// Tell the driver the cursor buffer changed under it. Legacy cursors get
+/// their BO set again, atomic planes get a DIRTYFB (most drivers scan the
+/// dumb buffer directly and ignore it).
+unsafe fn flush_cursor_fb() {
+    let fd = CURSOR_FD.load(Ordering::SeqCst);
+    if fd < 0 {
+        return;
+    }
+
+    let crtc_id = LEGACY_CURSOR_CRTC.load(Ordering::SeqCst);
+    if crtc_id != 0 {
+        let cursor = DrmModeCursor2 {
+            flags: DRM_MODE_CURSOR_BO,
+            crtc_id,
+            x: 0,
+            y: 0,
+            width: CURSOR_DISPLAY_SIZE,
+            height: CURSOR_DISPLAY_SIZE,
+            handle: CURSOR_HANDLE.load(Ordering::SeqCst),
+            hot_x: CURSOR_HOTSPOT_X.load(Ordering::SeqCst),
+            hot_y: CURSOR_HOTSPOT_Y.load(Ordering::SeqCst),
+        };
+        real_ioctl(
+            fd,
+            DRM_IOCTL_MODE_CURSOR2,
+            &cursor as *const _ as *mut c_void,
+        );
+        return;
+    }
+
+    let mut dirty = DrmModeFbDirtyCmd {
+        fb_id: CURSOR_FB_ID.load(Ordering::SeqCst),
+        ..Default::default()
+    };
+    real_ioctl(
+        fd,
+        DRM_IOCTL_MODE_DIRTYFB,
+        &mut dirty as *mut _ as *mut c_void,
+    );
+}
+
+/// A frame of a frame based design animation
+struct AnimationFrame {
+    duration: f32,
+    layers: Vec<CursorLayer>,
+}
+
+/// A keyframe of a design animation, eases into the next one
+struct Keyframe {
+    duration: f32,
+    rotation: f32,
+    scale: f32,
+    tint: u32,
+    hidden: Vec<usize>,
+}
+
+/// The `animation` block of a v2 design
+struct DesignAnimation {
+    frames: Vec<AnimationFrame>,
+    keyframes: Vec<Keyframe>,
+    looping: bool,
+}
+
+/// Where an animation is at a point in time
+struct AnimationState {
+    frame: Option<usize>,
+    rotation: f32,
+    scale: f32,
+    tint: u32,
+    hidden: Vec<usize>,
+    finished: bool,
+}
+
+impl DesignAnimation {
+    fn parse(block: &str) -> Option<Self> {
+        let frames: Vec<AnimationFrame> = parse_object_array(block, "frames")
+            .into_iter()
+            .map(|frame| AnimationFrame {
+                duration: parse_float(frame, "duration").unwrap_or(100.0).max(1.0),
+                layers: parse_layers(frame),
+            })
+            .collect();
+        let keyframes: Vec<Keyframe> = parse_object_array(block, "keyframes")
+            .into_iter()
+            .map(|keyframe| Keyframe {
+                duration: parse_float(keyframe, "duration").unwrap_or(100.0).max(1.0),
+                rotation: parse_float(keyframe, "rotation").unwrap_or(0.0),
+                scale: parse_float(keyframe, "scale").unwrap_or(1.0).max(0.0),
+                tint: parse_color(keyframe, "tint").unwrap_or(0),
+                hidden: parse_index_list(keyframe, "hidden"),
+            })
+            .collect();
+
+        if frames.is_empty() && keyframes.is_empty() {
+            return None;
+        }
+        Some(DesignAnimation {
+            frames,
+            keyframes,
+            looping: parse_bool(block, "loop").unwrap_or(true),
+        })
+    }
+
+    fn sample(&self, time: f32) -> AnimationState {
+        let mut state = AnimationState {
+            frame: None,
+            rotation: 0.0,
+            scale: 1.0,
+            tint: 0,
+            hidden: Vec::new(),
+            finished: true,
+        };
+
+        if !self.frames.is_empty() {
+            let durations: Vec<f32> = self.frames.iter().map(|f| f.duration).collect();
+            let (index, _, finished) = timeline_position(&durations, time, self.looping);
+            state.frame = Some(index);
+            state.finished = finished;
+        }
+
+        if !self.keyframes.is_empty() {
+            let durations: Vec<f32> = self.keyframes.iter().map(|k| k.duration).collect();
+            let (index, t, finished) = timeline_position(&durations, time, self.looping);
+            let from = &self.keyframes[index];
+            let to = match self.keyframes.get(index + 1) {
+                Some(next) => next,
+                None if self.looping => &self.keyframes[0],
+                None => from,
+            };
+            state.rotation = from.rotation + (to.rotation - from.rotation) * t;
+            state.scale = from.scale + (to.scale - from.scale) * t;
+            // Fading a tint in or out only changes its alpha
+            let (from_tint, to_tint) = match (from.tint >> 24, to.tint >> 24) {
+                (0, _) => (to.tint & 0x00FFFFFF, to.tint),
+                (_, 0) => (from.tint, from.tint & 0x00FFFFFF),
+                _ => (from.tint, to.tint),
+            };
+            state.tint = lerp_argb(from_tint, to_tint, t);
+            state.hidden = from.hidden.clone();
+            state.finished &= finished;
+        }
+
+        state
+    }
+
+    /// Largest keyframe scale, the hotspot has to leave room for it
+    fn max_scale(&self) -> f32 {
+        self.keyframes.iter().map(|k| k.scale).fold(1.0, f32::max)
+    }
+
+    /// Whether keyframes rotate the design
+    fn spins(&self) -> bool {
+        self.keyframes.iter().any(|k| k.rotation != 0.0)
+    }
+}
+
+/// Find where `time` falls on back to back durations:
+/// (index, progress through it, whether a non looping timeline ended)
+fn timeline_position(durations: &[f32], time: f32, looping: bool) -> (usize, f32, bool) {
+    let total: f32 = durations.iter().sum();
+    if durations.is_empty() || total <= 0.0 {
+        return (0, 0.0, true);
+    }
+    if !looping && time >= total {
+        return (durations.len() - 1, 1.0, true);
+    }
+
+    let time = time % total;
+    let mut start = 0.0;
+    for (i, duration) in durations.iter().enumerate() {
+        if time < start + duration {
+            return (i, (time - start) / duration, false);
+        }
+        start += duration;
+    }
+    (durations.len() - 1, 1.0, !looping)
+}
+
+/// Hotspot that fits every frame of an animation at its largest scale.
+/// `relative` are points relative to their frame's hotspot. Spinning designs
+/// get a square around the hotspot that fits any rotation.
+fn animation_hotspot(
+    relative: &[(f32, f32)],
+    scale: f32,
+    rotation: f32,
+    spins: bool,
+) -> (f32, f32) {
+    if spins {
+        let radius = relative
+            .iter()
+            .map(|(x, y)| (x * x + y * y).sqrt())
+            .fold(0.0, f32::max)
+            * scale;
+        return (radius.ceil(), radius.ceil());
+    }
+
+    // The relative points are already hotspot based, a (0, 0) first point
+    // keeps scale_rotate_around_hotspot from moving them
+    let mut points = vec![(0.0, 0.0)];
+    points.extend_from_slice(relative);
+    let placed = scale_rotate_around_hotspot(&points, scale, rotation);
+    let min_x = placed.iter().map(|p| p.0).fold(0.0, f32::min);
+    let min_y = placed.iter().map(|p| p.1).fold(0.0, f32::min);
+    ((-min_x).ceil(), (-min_y).ceil())
+}
+
+fn lerp_argb(from: u32, to: u32, t: f32) -> u32 {
+    (0..4).fold(0, |color, i| {
+        let shift = i * 8;
+        let a = ((from >> shift) & 0xFF) as f32;
+        let b = ((to >> shift) & 0xFF) as f32;
+        color | (((a + (b - a) * t).round() as u32) << shift)
+    })
+}
+
+/// Mix a tint's color into `color` by the tint's alpha, keeping `color`'s alpha
+fn tint_color(color: u32, tint: u32) -> u32 {
+    let amount = ((tint >> 24) & 0xFF) as f32 / 255.0;
+    let mixed = lerp_argb(color, tint, amount);
+    (color & 0xFF000000) | (mixed & 0x00FFFFFF)
+}
+
+/// Cut the `"animation": {...}` block out of a design
+fn split_animation(content: &str) -> (String, Option<String>) {
+    let Some(key) = content.find("\"animation\"") else {
+        return (content.to_string(), None);
+    };
+    let Some((open, close)) = find_json_block(content, key, '{', '}') else {
+        return (content.to_string(), None);
+    };
+    let mut rest = String::with_capacity(content.len());
+    rest.push_str(&content[..key]);
+    rest.push_str(&content[close + 1..]);
+    (rest, Some(content[open..=close].to_string()))
+}
+
+/// Find the first `open`..`close` block after `from`, returns the byte
+/// positions of both brackets
+fn find_json_block(content: &str, from: usize, open: char, close: char) -> Option<(usize, usize)> {
+    let start = from + content[from..].find(open)?;
+    let mut depth = 0;
+    for (i, c) in content[start..].char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some((start, start + i));
+            }
+        }
+    }
+    None
+}
+
+/// The objects of a `"key": [{...}, {...}]` array
+fn parse_object_array<'a>(content: &'a str, key: &str) -> Vec<&'a str> {
+    let pattern = format!("\"{}\"", key);
+    let Some(key_pos) = content.find(&pattern) else {
+        return Vec::new();
+    };
+    let Some((open, close)) = find_json_block(content, key_pos, '[', ']') else {
+        return Vec::new();
+    };
+
+    let mut objects = Vec::new();
+    let mut pos = open + 1;
+    while let Some((start, end)) = find_json_block(&content[..close], pos, '{', '}') {
+        objects.push(&content[start..=end]);
+        pos = end + 1;
+    }
+    objects
+}
+
+/// A `"key": [1, 2, 3]` list of indices
+fn parse_index_list(content: &str, key: &str) -> Vec<usize> {
+    let pattern = format!("\"{}\"", key);
+    let Some(key_pos) = content.find(&pattern) else {
+        return Vec::new();
+    };
+    let Some((open, close)) = find_json_block(content, key_pos, '[', ']') else {
+        return Vec::new();
+    };
+    content[open + 1..close]
+        .split(',')
+        .filter_map(|item| item.trim().parse().ok())
+        .collect()
+}
+
+// =============================================================================
+// XCursor theme import
+// =============================================================================
+
+/// One decoded (and resampled) XCursor image
+struct RasterFrame {
+    width: usize,
+    height: usize,
+    xhot: usize,
+    yhot: usize,
+    /// How long the frame shows in ms, 0 for still cursors
+    delay: f32,
+    pixels: Vec<LinearPixel>,
+}
+
+/// Frames loaded for one cursor, keyed by what they were loaded for
+struct ThemeCursor {
+    theme: String,
+    cursor_type: CursorType,
+    size: u32,
+    /// None when the theme has no such cursor, so we don't look again
+    frames: Option<Vec<RasterFrame>>,
+}
+
+static THEME_CURSOR_CACHE: Mutex<Vec<ThemeCursor>> = Mutex::new(Vec::new());
+
+const XCURSOR_MAGIC: u32 = 0x72756358; // "Xcur"
+const XCURSOR_IMAGE_TYPE: u32 = 0xfffd0002;
+// Nominal XCursor size that matches our default scale of 1.5
+const XCURSOR_BASE_SIZE: f32 = 24.0;
+
+/// XCursor names to try for each cursor type, standard names first
+fn xcursor_names(cursor_type: CursorType) -> &'static [&'static str] {
+    match cursor_type {
+        CursorType::Default => &["default", "left_ptr", "arrow"],
+        CursorType::Pointer => &["pointer", "hand2", "hand1", "pointing_hand"],
+        CursorType::Text => &["text", "xterm", "ibeam"],
+        CursorType::Crosshair => &["crosshair", "cross", "tcross"],
+        CursorType::Wait => &["wait", "watch", "left_ptr_watch", "progress"],
+        CursorType::Grab => &["grabbing", "closedhand", "grab", "openhand", "fleur"],
+        CursorType::NotAllowed => &["not-allowed", "crossed_circle", "forbidden", "circle"],
+        CursorType::Custom => &[],
+    }
+}
+
+/// Theme named in cursor.conf, None when themed cursors are off
+fn get_xcursor_theme() -> Option<String> {
+    load_config();
+    let theme = CONFIG_XCURSOR_THEME
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    match theme.as_str() {
+        "" => None,
+        "auto" => Some(std::env::var("XCURSOR_THEME").unwrap_or_else(|_| "default".into())),
+        _ => Some(theme),
+    }
+}
+
+/// Icon directories in the order libXcursor searches them
+fn xcursor_search_path() -> Vec<String> {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let path = std::env::var("XCURSOR_PATH").unwrap_or_else(|_| {
+        let mut dirs = vec!["~/.local/share/icons".to_string(), "~/.icons".to_string()];
+        let data_dirs =
+            std::env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".into());
+        dirs.extend(data_dirs.split(':').map(|d| format!("{}/icons", d)));
+        dirs.push("/usr/share/pixmaps".to_string());
+        dirs.join(":")
+    });
+    path.split(':')
+        .filter(|d| !d.is_empty())
+        .map(|d| match d.strip_prefix('~') {
+            Some(rest) => format!("{}{}", home, rest),
+            None => d.to_string(),
+        })
+        .collect()
+}
+
+/// Find a cursor file in a theme or the themes it inherits from
+fn find_xcursor_file(theme: &str, names: &[&str]) -> Option<std::path::PathBuf> {
+    let dirs = xcursor_search_path();
+    let mut pending = vec![theme.to_string()];
+    let mut visited: Vec<String> = Vec::new();
+
+    while let Some(theme) = pending.pop() {
+        if visited.contains(&theme) || visited.len() > 16 {
+            continue;
+        }
+        for dir in &dirs {
+            for name in names {
+                let path = std::path::Path::new(dir)
+                    .join(&theme)
+                    .join("cursors")
+                    .join(name);
+                if path.is_file() {
+                    return Some(path);
+                }
+            }
+        }
+        // Inherited themes are searched after this one, first listed first
+        for dir in &dirs {
+            let index = std::path::Path::new(dir).join(&theme).join("index.theme");
+            if let Ok(contents) = std::fs::read_to_string(index) {
+                if let Some(inherits) = contents
+                    .lines()
+                    .find_map(|l| l.trim().strip_prefix("Inherits="))
+                {
+                    pending.extend(inherits.rsplit(',').map(|t| t.trim().to_string()));
+                    break;
+                }
+            }
+        }
+        visited.push(theme);
+    }
+    None
+}
+
+/// Decode the images of an XCursor file closest to `size`, an animation
+/// when the file has several images at that nominal size
+fn parse_xcursor(data: &[u8], size: u32) -> Option<Vec<RasterFrame>> {
+    let read = |pos: usize| -> Option<u32> {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    if read(0)? != XCURSOR_MAGIC {
+        return None;
+    }
+    let header = read(4)? as usize;
+    let ntoc = read(12)? as usize;
+
+    let mut images = Vec::new();
+    for i in 0..ntoc.min(1024) {
+        let entry = header + i * 12;
+        if read(entry)? == XCURSOR_IMAGE_TYPE {
+            images.push((read(entry + 4)?, read(entry + 8)? as usize));
+        }
+    }
+
+    // Prefer the smallest nominal size that is at least as big as wanted,
+    // downscaling looks better than upscaling
+    let nominal = images
+        .iter()
+        .map(|(nominal, _)| *nominal)
+        .min_by_key(|n| (*n < size, n.abs_diff(size)))?;
+
+    let table = srgb_decode_table();
+    let mut frames = Vec::new();
+    for (_, pos) in images.iter().filter(|(n, _)| *n == nominal) {
+        let width = read(pos + 16)? as usize;
+        let height = read(pos + 20)? as usize;
+        if width == 0 || height == 0 || width > 0x7fff || height > 0x7fff {
+            return None;
+        }
+        let pixels_start = pos + read(*pos)? as usize;
+        // A truncated file would otherwise have us allocate for pixels it
+        // doesn't have
+        if pixels_start + width * height * 4 > data.len() {
+            return None;
+        }
+        let mut pixels = Vec::with_capacity(width * height);
+        for p in 0..width * height {
+            // Stored premultiplied sRGB
+            let argb = read(pixels_start + p * 4)?;
+            let a = (argb >> 24) & 0xFF;
+            if a == 0 {
+                pixels.push(LinearPixel::TRANSPARENT);
+                continue;
+            }
+            let alpha = a as f32 / 255.0;
+            let channel = |shift: u32| {
+                let value = (((argb >> shift) & 0xFF) * 255 / a).min(255);
+                table[value as usize] * alpha
+            };
+            pixels.push(LinearPixel {
+                r: channel(16),
+                g: channel(8),
+                b: channel(0),
+                a: alpha,
+            });
+        }
+        frames.push(RasterFrame {
+            width,
+            height,
+            xhot: read(pos + 24)? as usize,
+            yhot: read(pos + 28)? as usize,
+            delay: read(pos + 32)? as f32,
+            pixels,
+        });
+    }
+    Some(frames)
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= 3.0 {
+        return 0.0;
+    }
+    let px = std::f32::consts::PI * x;
+    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+}
+
+/// Lanczos-3 weights for resampling one axis: per output pixel the first
+/// source pixel and the normalized weights from there
+fn resample_weights(src_len: usize, dst_len: usize) -> Vec<(usize, Vec<f32>)> {
+    let ratio = src_len as f32 / dst_len as f32;
+    // Widen the filter when shrinking so every source pixel contributes
+    let filter_scale = ratio.max(1.0);
+    let support = 3.0 * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * ratio;
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(src_len);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|j| lanczos3((j as f32 + 0.5 - center) / filter_scale))
+                .collect();
+            let total: f32 = weights.iter().sum();
+            if total != 0.0 {
+                for w in weights.iter_mut() {
+                    *w /= total;
+                }
+            }
+            (start, weights)
+        })
+        .collect()
+}
+
+/// Separable Lanczos-3 resample of premultiplied linear pixels
+fn resample_frame(frame: &RasterFrame, width: usize, height: usize) -> RasterFrame {
+    let columns = resample_weights(frame.width, width);
+    let rows = resample_weights(frame.height, height);
+
+    let mut horizontal = vec![LinearPixel::TRANSPARENT; width * frame.height];
+    for y in 0..frame.height {
+        for (x, (start, weights)) in columns.iter().enumerate() {
+            let mut sum = LinearPixel::TRANSPARENT;
+            for (k, w) in weights.iter().enumerate() {
+                let p = frame.pixels[y * frame.width + start + k];
+                sum = LinearPixel {
+                    r: sum.r + p.r * w,
+                    g: sum.g + p.g * w,
+                    b: sum.b + p.b * w,
+                    a: sum.a + p.a * w,
+                };
+            }
+            horizontal[y * width + x] = sum;
+        }
+    }
+
+    let mut pixels = vec![LinearPixel::TRANSPARENT; width * height];
+    for (y, (start, weights)) in rows.iter().enumerate() {
+        for x in 0..width {
+            let mut sum = LinearPixel::TRANSPARENT;
+            for (k, w) in weights.iter().enumerate() {
+                let p = horizontal[(start + k) * width + x];
+                sum = LinearPixel {
+                    r: sum.r + p.r * w,
+                    g: sum.g + p.g * w,
+                    b: sum.b + p.b * w,
+                    a: sum.a + p.a * w,
+                };
+            }
+            // Lanczos rings, keep the result a valid premultiplied pixel
+            let a = sum.a.clamp(0.0, 1.0);
+            pixels[y * width + x] = LinearPixel {
+                r: sum.r.clamp(0.0, a),
+                g: sum.g.clamp(0.0, a),
+                b: sum.b.clamp(0.0, a),
+                a,
+            };
+        }
+    }
+
+    let sx = width as f32 / frame.width as f32;
+    let sy = height as f32 / frame.height as f32;
+    RasterFrame {
+        width,
+        height,
+        xhot: (frame.xhot as f32 * sx).round() as usize,
+        yhot: (frame.yhot as f32 * sy).round() as usize,
+        delay: frame.delay,
+        pixels,
+    }
+}
+
+/// Load a theme cursor for `cursor_type` resampled to `size` pixels
+fn load_theme_cursor(theme: &str, cursor_type: CursorType, size: u32) -> Option<Vec<RasterFrame>> {
+    let path = find_xcursor_file(theme, xcursor_names(cursor_type))?;
+    let data = std::fs::read(&path).ok()?;
+    let frames = parse_xcursor(&data, size)?;
+    log!(
+        Info,
+        Render,
+        "Loaded {} frame(s) for {:?} from {}",
+        frames.len(),
+        cursor_type.as_u32(),
+        path.display()
+    );
+
+    Some(
+        frames
+            .into_iter()
+            .map(|frame| {
+                let scale = size as f32 / frame.width.max(frame.height) as f32;
+                let width = ((frame.width as f32 * scale).round() as usize).max(1);
+                let height = ((frame.height as f32 * scale).round() as usize).max(1);
+                if width == frame.width && height == frame.height {
+                    frame
+                } else {
+                    resample_frame(&frame, width, height)
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Draw the theme's cursor for this type when themed cursors are enabled for
+/// it. Animated cursors play through the animation scheduler.
+/// Returns false when we should draw our own cursor instead.
+unsafe fn render_theme_cursor(canvas: &mut Canvas, cursor_type: CursorType) -> bool {
+    if CONFIG_XCURSOR_TYPES.load(Ordering::Relaxed) & (1 << cursor_type.as_u32()) == 0 {
+        return false;
+    }
+    let Some(theme) = get_xcursor_theme() else {
+        return false;
+    };
+
+    let base = std::env::var("XCURSOR_SIZE")
+        .ok()
+        .and_then(|s| s.parse::<f32>().ok())
+        .unwrap_or(XCURSOR_BASE_SIZE);
+    let size = (base * canvas.scale / 1.5).round().clamp(8.0, 200.0) as u32;
+
+    let mut cache = THEME_CURSOR_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let cached = cache
+        .iter()
+        .position(|c| c.theme == theme && c.cursor_type == cursor_type && c.size == size);
+    let idx = match cached {
+        Some(idx) => idx,
+        None => {
+            // Only keep what the current theme and size need
+            cache.retain(|c| c.theme == theme && c.size == size);
+            let frames = load_theme_cursor(&theme, cursor_type, size);
+            cache.push(ThemeCursor {
+                theme,
+                cursor_type,
+                size,
+                frames,
+            });
+            cache.len() - 1
+        }
+    };
+    let Some(frames) = cache[idx].frames.as_ref().filter(|f| !f.is_empty()) else {
+        return false;
+    };
+
+    let frame = if frames.len() > 1 {
+        let delays: Vec<f32> = frames.iter().map(|f| f.delay.max(1.0)).collect();
+        let (index, _, _) = timeline_position(&delays, canvas.animation_time(), true);
+        &frames[index]
+    } else {
+        &frames[0]
+    };
+
+    // Line every frame up on the same hotspot
+    let pin_x = frames.iter().map(|f| f.xhot).max().unwrap_or(0);
+    let pin_y = frames.iter().map(|f| f.yhot).max().unwrap_or(0);
+    let (ox, oy) = (pin_x - frame.xhot, pin_y - frame.yhot);
+    let height = canvas.height;
+    for y in 0..frame.height.min(height.saturating_sub(oy)) {
+        for x in 0..frame.width.min(canvas.width.saturating_sub(ox)) {
+            canvas.pixels[(y + oy) * canvas.width + x + ox] = frame.pixels[y * frame.width + x];
+        }
+    }
+
+    canvas.hotspot = (pin_x as i32, pin_y as i32);
+    true
+}
+
+// =============================================================================
+// XCursor theme export
+// =============================================================================
+
+/// Nominal sizes an exported theme carries
+const XCURSOR_EXPORT_SIZES: [u32; 5] = [24, 32, 48, 64, 96];
+/// Frame length of exported animations
+const XCURSOR_FRAME_MS: f32 = 100.0;
+
+/// Length of a built-in cursor's animation in ms, None for still ones
+fn builtin_cycle_ms(cursor_type: CursorType) -> Option<f32> {
+    match cursor_type {
+        CursorType::Wait => Some(WAIT_CYCLE_MS),
+        _ => None,
+    }
+}
+
+/// Frames of a built-in cursor at one nominal size, squared off and at
+/// least that size like theme images usually are
+fn xcursor_frames(cursor_type: CursorType, nominal: u32) -> Vec<Canvas> {
+    let scale = 1.5 * nominal as f32 / XCURSOR_BASE_SIZE;
+    let frames = builtin_cycle_ms(cursor_type).map_or(1, |cycle| {
+        (cycle / XCURSOR_FRAME_MS).round().max(1.0) as usize
+    });
+    (0..frames)
+        .map(|frame| {
+            let time = frame as f32 * XCURSOR_FRAME_MS;
+            let canvas = render_frame(&Design::Builtin(cursor_type), scale, 0.0, time);
+            let trimmed = canvas.trimmed(nominal as usize);
+            let side = trimmed.width().max(trimmed.height());
+            canvas.crop(side, side)
+        })
+        .collect()
+}
+
+/// An XCursor file holding `images` as (nominal size, frames)
+fn encode_xcursor(images: &[(u32, Vec<Canvas>)]) -> Vec<u8> {
+    const HEADER_SIZE: u32 = 16;
+    const IMAGE_HEADER_SIZE: u32 = 36;
+    let entries: Vec<(u32, &Canvas, u32)> = images
+        .iter()
+        .flat_map(|(nominal, frames)| {
+            let delay = if frames.len() > 1 {
+                XCURSOR_FRAME_MS as u32
+            } else {
+                0
+            };
+            frames.iter().map(move |frame| (*nominal, frame, delay))
+        })
+        .collect();
+
+    let mut out = Vec::new();
+    let put = |out: &mut Vec<u8>, value: u32| out.extend_from_slice(&value.to_le_bytes());
+    put(&mut out, XCURSOR_MAGIC);
+    put(&mut out, HEADER_SIZE);
+    put(&mut out, 0x10000);
+    put(&mut out, entries.len() as u32);
+
+    let mut position = HEADER_SIZE + entries.len() as u32 * 12;
+    for (nominal, frame, _) in &entries {
+        put(&mut out, XCURSOR_IMAGE_TYPE);
+        put(&mut out, *nominal);
+        put(&mut out, position);
+        position += IMAGE_HEADER_SIZE + (frame.width() * frame.height() * 4) as u32;
+    }
+
+    for (nominal, frame, delay) in &entries {
+        let (xhot, yhot) = frame.hotspot();
+        put(&mut out, IMAGE_HEADER_SIZE);
+        put(&mut out, XCURSOR_IMAGE_TYPE);
+        put(&mut out, *nominal);
+        put(&mut out, 1);
+        put(&mut out, frame.width() as u32);
+        put(&mut out, frame.height() as u32);
+        put(&mut out, xhot.clamp(0, frame.width() as i32 - 1) as u32);
+        put(&mut out, yhot.clamp(0, frame.height() as i32 - 1) as u32);
+        put(&mut out, *delay);
+        // Premultiplied sRGB, the same bytes the cursor plane gets
+        for pixel in &frame.pixels {
+            put(
+                &mut out,
+                encode_pixel(*pixel, 1.0, PixelBlendMode::Premultiplied),
+            );
+        }
+    }
+    out
+}
+
+/// Write the built-in cursors as an XCursor theme called `name` into `dir`:
+/// `cursors/` with one file per cursor type, its other names as symlinks,
+/// and `index.theme`. Cursors we don't draw come from Adwaita.
+pub fn export_xcursor_theme(dir: &std::path::Path, name: &str) -> std::io::Result<()> {
+    let cursors = dir.join("cursors");
+    std::fs::create_dir_all(&cursors)?;
+
+    for cursor_type in CursorType::BUILTIN {
+        let names = xcursor_names(cursor_type);
+        let images: Vec<(u32, Vec<Canvas>)> = XCURSOR_EXPORT_SIZES
+            .iter()
+            .map(|&nominal| (nominal, xcursor_frames(cursor_type, nominal)))
+            .collect();
+        std::fs::write(cursors.join(names[0]), encode_xcursor(&images))?;
+
+        for alias in &names[1..] {
+            let link = cursors.join(alias);
+            if link.symlink_metadata().is_ok() {
+                std::fs::remove_file(&link)?;
+            }
+            std::os::unix::fs::symlink(names[0], &link)?;
+        }
+    }
+
+    let index = format!(
+        "[Icon Theme]\nName={}\nComment=The Constellation Cursor v{}\nInherits=Adwaita\n",
+        name, VERSION
+    );
+    std::fs::write(dir.join("index.theme"), index)
+}
+
+// =============================================================================
+// SVG import
+// =============================================================================
+//
+// Plain SVG shapes (path, polygon, polyline, rect, circle, ellipse) become a v2 design with one
+// layer per subpath, so they go through the same renderer as designer JSON.
+// Like in the designer, the first point drawn is the hotspot. Transforms,
+// gradients and fill-rule aren't supported, arcs are drawn as straight lines.
+
+/// Fill and stroke of one SVG element as 0xAARRGGBB, None = not painted
+struct SvgStyle {
+    fill: Option<u32>,
+    stroke: Option<u32>,
+    stroke_width: f32,
+}
+
+/// A point of a subpath, `curve` holds the cubic control points leading to it
+struct SvgPoint {
+    x: f32,
+    y: f32,
+    curve: Option<(f32, f32, f32, f32)>,
+}
+
+impl SvgPoint {
+    fn at(x: f32, y: f32) -> Self {
+        SvgPoint { x, y, curve: None }
+    }
+}
+
+enum SvgToken {
+    Command(char),
+    Number(f32),
+}
+
+/// Split path data or a points list into commands and numbers,
+/// "M1.5-2.5.5" included
+fn svg_tokens(data: &str) -> Vec<SvgToken> {
+    let bytes = data.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_alphabetic() {
+            tokens.push(SvgToken::Command(c as char));
+            i += 1;
+        } else if c.is_ascii_digit() || c == b'.' || c == b'-' || c == b'+' {
+            let start = i;
+            if c == b'-' || c == b'+' {
+                i += 1;
+            }
+            let mut seen_dot = false;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || (bytes[i] == b'.' && !seen_dot))
+            {
+                seen_dot |= bytes[i] == b'.';
+                i += 1;
+            }
+            if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                i += 1;
+                if i < bytes.len() && (bytes[i] == b'-' || bytes[i] == b'+') {
+                    i += 1;
+                }
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            if let Ok(value) = data[start..i].parse::<f32>() {
+                tokens.push(SvgToken::Number(value));
+            }
+        } else {
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Subpaths of a path's `d`, curves converted to cubics
+fn svg_path(d: &str) -> Vec<Vec<SvgPoint>> {
+    let tokens = svg_tokens(d);
+    let mut subpaths = Vec::new();
+    let mut current: Vec<SvgPoint> = Vec::new();
+    let (mut x, mut y) = (0.0f32, 0.0f32);
+    let mut start = (0.0, 0.0);
+    // Last control point, for the smooth S/T forms to mirror
+    let mut last_cubic: Option<(f32, f32)> = None;
+    let mut last_quad: Option<(f32, f32)> = None;
+    let mut command = 'M';
+    let mut i = 0;
+
+    while i < tokens.len() {
+        if let SvgToken::Command(c) = tokens[i] {
+            command = c;
+            i += 1;
+            if c == 'Z' || c == 'z' {
+                (x, y) = start;
+                if !current.is_empty() {
+                    subpaths.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+        }
+
+        let count = match command.to_ascii_uppercase() {
+            'M' | 'L' | 'T' => 2,
+            'H' | 'V' => 1,
+            'S' | 'Q' => 4,
+            'C' => 6,
+            'A' => 7,
+            _ => break,
+        };
+        let n: Vec<f32> = tokens[i..]
+            .iter()
+            .take(count)
+            .map_while(|t| match t {
+                SvgToken::Number(v) => Some(*v),
+                SvgToken::Command(_) => None,
+            })
+            .collect();
+        if n.len() < count {
+            break;
+        }
+        i += count;
+
+        let (ox, oy) = if command.is_ascii_lowercase() {
+            (x, y)
+        } else {
+            (0.0, 0.0)
+        };
+        let (mut cubic, mut quad) = (None, None);
+        match command.to_ascii_uppercase() {
+            'M' => {
+                if !current.is_empty() {
+                    subpaths.push(std::mem::take(&mut current));
+                }
+                (x, y) = (ox + n[0], oy + n[1]);
+                start = (x, y);
+                current.push(SvgPoint::at(x, y));
+                // More pairs after a moveto are linetos
+                command = if command == 'm' { 'l' } else { 'L' };
+                continue;
+            }
+            'L' => (x, y) = (ox + n[0], oy + n[1]),
+            'H' => x = ox + n[0],
+            'V' => y = oy + n[0],
+            'A' => (x, y) = (ox + n[5], oy + n[6]),
+            upper => {
+                let (c1, c2, end) = match upper {
+                    'C' => (
+                        (ox + n[0], oy + n[1]),
+                        (ox + n[2], oy + n[3]),
+                        (ox + n[4], oy + n[5]),
+                    ),
+                    'S' => {
+                        let c1 = last_cubic.map_or((x, y), |(cx, cy)| (2.0 * x - cx, 2.0 * y - cy));
+                        (c1, (ox + n[0], oy + n[1]), (ox + n[2], oy + n[3]))
+                    }
+                    _ => {
+                        let (q, end) = if upper == 'Q' {
+                            ((ox + n[0], oy + n[1]), (ox + n[2], oy + n[3]))
+                        } else {
+                            let q =
+                                last_quad.map_or((x, y), |(qx, qy)| (2.0 * x - qx, 2.0 * y - qy));
+                            (q, (ox + n[0], oy + n[1]))
+                        };
+                        quad = Some(q);
+                        let c1 = (x + (q.0 - x) * 2.0 / 3.0, y + (q.1 - y) * 2.0 / 3.0);
+                        let c2 = (
+                            end.0 + (q.0 - end.0) * 2.0 / 3.0,
+                            end.1 + (q.1 - end.1) * 2.0 / 3.0,
+                        );
+                        (c1, c2, end)
+                    }
+                };
+                if quad.is_none() {
+                    cubic = Some(c2);
+                }
+                (x, y) = end;
+                current.push(SvgPoint {
+                    x,
+                    y,
+                    curve: Some((c1.0, c1.1, c2.0, c2.1)),
+                });
+                last_cubic = cubic;
+                last_quad = quad;
+                continue;
+            }
+        }
+        current.push(SvgPoint::at(x, y));
+        last_cubic = cubic;
+        last_quad = quad;
+    }
+    if !current.is_empty() {
+        subpaths.push(current);
+    }
+    subpaths
+}
+
+/// Attribute value from an element's attribute text, exactly as written
+fn svg_raw_attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut offset = 0;
+    while let Some(pos) = attributes[offset..].find(name) {
+        let pos = offset + pos;
+        offset = pos + name.len();
+        let standalone = attributes[..pos].ends_with(|c: char| c.is_whitespace()) || pos == 0;
+        let value = match attributes[offset..].trim_start().strip_prefix('=') {
+            Some(value) if standalone => value.trim_start(),
+            _ => continue,
+        };
+        let quote = value.chars().next()?;
+        if quote == '"' || quote == '\'' {
+            let end = value[1..].find(quote)?;
+            return Some(value[1..1 + end].to_string());
+        }
+    }
+    None
+}
+
+/// Presentation attribute, the style attribute wins over the plain one
+fn svg_attribute(attributes: &str, name: &str) -> Option<String> {
+    let from_style = svg_raw_attribute(attributes, "style").and_then(|style| {
+        style.split(';').find_map(|declaration| {
+            let (key, value) = declaration.split_once(':')?;
+            (key.trim() == name).then(|| value.trim().to_string())
+        })
+    });
+    from_style.or_else(|| svg_raw_attribute(attributes, name))
+}
+
+/// Opaque 0xFFRRGGBB from #rgb, #rrggbb, black or white. None for "none"
+/// and paints we can't do (gradients, currentColor)
+fn svg_color(value: &str) -> Option<u32> {
+    let hex = match value.trim() {
+        "black" => "000000",
+        "white" => "ffffff",
+        value => value.strip_prefix('#')?,
+    };
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    let rgb = match hex.len() {
+        // #abc is #aabbcc
+        3 => (((rgb >> 8) & 0xF) * 0x110000) | (((rgb >> 4) & 0xF) * 0x1100) | ((rgb & 0xF) * 0x11),
+        6 => rgb,
+        _ => return None,
+    };
+    Some(0xFF000000 | rgb)
+}
+
+fn svg_style(attributes: &str) -> SvgStyle {
+    let number = |name| svg_attribute(attributes, name).and_then(|v| v.trim().parse::<f32>().ok());
+    let with_alpha = |color: u32, alpha: f32| {
+        let alpha = (alpha.clamp(0.0, 1.0) * 255.0).round() as u32;
+        (alpha << 24) | (color & 0x00FFFFFF)
+    };
+    let opacity = number("opacity").unwrap_or(1.0);
+    // Shapes without a fill attribute are filled black
+    let fill = match svg_attribute(attributes, "fill") {
+        Some(value) => svg_color(&value),
+        None => Some(0xFF000000),
+    };
+    let stroke = svg_attribute(attributes, "stroke").and_then(|value| svg_color(&value));
+
+    SvgStyle {
+        fill: fill.map(|c| with_alpha(c, opacity * number("fill-opacity").unwrap_or(1.0))),
+        stroke: stroke.map(|c| with_alpha(c, opacity * number("stroke-opacity").unwrap_or(1.0))),
+        stroke_width: number("stroke-width").unwrap_or(1.0),
+    }
+}
+
+/// One design layer (v2 JSON) for a subpath
+fn svg_layer(points: &[SvgPoint], style: &SvgStyle) -> String {
+    let points: Vec<String> = points
+        .iter()
+        .map(|p| match p.curve {
+            Some((cx1, cy1, cx2, cy2)) => format!(
+                "{{\"x\":{},\"y\":{},\"type\":\"curve\",\"cx1\":{},\"cy1\":{},\"cx2\":{},\"cy2\":{}}}",
+                p.x, p.y, cx1, cy1, cx2, cy2
+            ),
+            None => format!("{{\"x\":{},\"y\":{}}}", p.x, p.y),
+        })
+        .collect();
+    // Layer alphas are percentages, the half step keeps the byte exact
+    let color = |paint: Option<u32>| {
+        let paint = paint.unwrap_or(0);
+        let percent = ((paint >> 24) as f32 + 0.5) / 255.0 * 100.0;
+        (
+            paint & 0x00FFFFFF,
+            if paint >> 24 == 0 { 0.0 } else { percent },
+        )
+    };
+    let (fill, fill_alpha) = color(style.fill);
+    let (outline, outline_alpha) = color(style.stroke);
+    format!(
+        "{{\"points\":[{}],\"fill\":\"#{:06x}\",\"fillAlpha\":{},\"outline\":\"#{:06x}\",\"outlineAlpha\":{},\"outlineWidth\":{},\"shadowAlpha\":0}}",
+        points.join(","),
+        fill,
+        fill_alpha,
+        outline,
+        outline_alpha,
+        style.stroke_width
+    )
+}
+
+/// Convert an SVG to a v2 design JSON, None when it has nothing we can draw
+fn svg_to_design(svg: &str) -> Option<String> {
+    let mut layers = Vec::new();
+    let mut rest = svg;
+    while let Some(open) = rest.find('<') {
+        let element = &rest[open + 1..];
+        let close = element.find('>')?;
+        rest = &element[close + 1..];
+        let element = element[..close].trim_end_matches('/');
+        let (name, attributes) = element
+            .split_once(char::is_whitespace)
+            .unwrap_or((element, ""));
+        let number = |key| {
+            svg_raw_attribute(attributes, key)
+                .and_then(|v| v.trim().parse::<f32>().ok())
+                .unwrap_or(0.0)
+        };
+
+        let subpaths = match name {
+            "path" => svg_path(&svg_raw_attribute(attributes, "d").unwrap_or_default()),
+            "polygon" | "polyline" => {
+                let numbers: Vec<f32> =
+                    svg_tokens(&svg_raw_attribute(attributes, "points").unwrap_or_default())
+                        .into_iter()
+                        .filter_map(|t| match t {
+                            SvgToken::Number(v) => Some(v),
+                            SvgToken::Command(_) => None,
+                        })
+                        .collect();
+                vec![numbers
+                    .chunks_exact(2)
+                    .map(|p| SvgPoint::at(p[0], p[1]))
+                    .collect()]
+            }
+            "rect" => {
+                let (x, y) = (number("x"), number("y"));
+                let (w, h) = (number("width"), number("height"));
+                vec![vec![
+                    SvgPoint::at(x, y),
+                    SvgPoint::at(x + w, y),
+                    SvgPoint::at(x + w, y + h),
+                    SvgPoint::at(x, y + h),
+                ]]
+            }
+            "circle" | "ellipse" => {
+                let (cx, cy) = (number("cx"), number("cy"));
+                let (rx, ry) = match name {
+                    "circle" => (number("r"), number("r")),
+                    _ => (number("rx"), number("ry")),
+                };
+                // Four cubics, k puts their midpoints on the circle
+                let (kx, ky) = (rx * 0.5523, ry * 0.5523);
+                let mut points = vec![SvgPoint::at(cx + rx, cy)];
+                for (x, y, c1x, c1y, c2x, c2y) in [
+                    (cx, cy + ry, cx + rx, cy + ky, cx + kx, cy + ry),
+                    (cx - rx, cy, cx - kx, cy + ry, cx - rx, cy + ky),
+                    (cx, cy - ry, cx - rx, cy - ky, cx - kx, cy - ry),
+                    (cx + rx, cy, cx + kx, cy - ry, cx + rx, cy - ky),
+                ] {
+                    points.push(SvgPoint {
+                        x,
+                        y,
+                        curve: Some((c1x, c1y, c2x, c2y)),
+                    });
+                }
+                vec![points]
+            }
+            _ => continue,
+        };
+
+        let style = svg_style(attributes);
+        for points in subpaths.iter().filter(|points| points.len() >= 3) {
+            layers.push(svg_layer(points, &style));
+        }
+    }
+
+    if layers.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{{\"version\":2,\"layers\":[{}],\"settings\":{{\"scale\":1.5}}}}",
+        layers.join(",")
+    ))
+}
+
+// =============================================================================
+// Adaptive contrast
+// =============================================================================
+
+/// How the cursor reacts to the lightness of what's under it,
+/// selected with `adaptive_mode` in cursor.conf
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+enum AdaptiveMode {
+    Off = 0,
+    /// Invert the whole design when it matches the background
+    Variant = 1,
+    /// Ring the cursor with white on dark backgrounds, black on light ones
+    Outline = 2,
+}
+
+impl AdaptiveMode {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => AdaptiveMode::Variant,
+            2 => AdaptiveMode::Outline,
+            _ => AdaptiveMode::Off,
+        }
+    }
+
+    fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "variant" | "invert" | "inverted" => AdaptiveMode::Variant,
+            "outline" => AdaptiveMode::Outline,
+            _ => AdaptiveMode::Off,
+        }
+    }
+}
+
+fn get_adaptive_mode() -> AdaptiveMode {
+    AdaptiveMode::from_u32(CONFIG_ADAPTIVE_MODE.load(Ordering::Relaxed))
+}
+
+/// Lightness class of the screen under the cursor
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+enum Backdrop {
+    Unknown = 0,
+    Dark = 1,
+    Light = 2,
+}
+
+impl Backdrop {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => Backdrop::Dark,
+            2 => Backdrop::Light,
+            _ => Backdrop::Unknown,
+        }
+    }
+}
+
+// Lightness has to move this far past the threshold before the class flips,
+// so backgrounds right at the threshold don't make the cursor flicker
+const BACKDROP_HYSTERESIS: f32 = 0.05;
+
+/// Whether anything needs to read the primary plane
+unsafe fn backdrop_wanted() -> bool {
+    get_adaptive_mode() != AdaptiveMode::Off || CURSOR_SAMPLES_BACKDROP.load(Ordering::SeqCst)
+}
+
+/// Legacy compositors never tell us about the primary plane,
+/// ask the CRTC which framebuffer it is scanning out instead
+unsafe fn refresh_primary_fb_from_crtc(fd: i32, crtc_id: u32) {
+    let mut crtc: DrmModeCrtc = std::mem::zeroed();
+    crtc.crtc_id = crtc_id;
+    if real_ioctl(
+        fd,
+        DRM_IOCTL_MODE_GETCRTC,
+        &mut crtc as *mut _ as *mut c_void,
+    ) == 0
+    {
+        PRIMARY_FB_ID.store(crtc.fb_id, Ordering::SeqCst);
+    }
+}
+
+/// CIE lightness (0-1) of a linear light color
+fn lightness(r: f32, g: f32, b: f32) -> f32 {
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    if y > 0.008856 {
+        (1.16 * y.cbrt() - 0.16).clamp(0.0, 1.0)
+    } else {
+        y * 9.033
+    }
+}
+
+/// Average lightness of the screen around the hotspot, None when the
+/// primary plane can't be read
+unsafe fn sample_backdrop_lightness() -> Option<f32> {
+    // The mappings belong to the renderer, which may be mid-frost on them
+    let _render = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if !map_primary_fb() {
+        return None;
+    }
+
+    let center_x = CURSOR_SCREEN_X.load(Ordering::SeqCst) + CURSOR_HOTSPOT_X.load(Ordering::SeqCst);
+    let center_y = CURSOR_SCREEN_Y.load(Ordering::SeqCst) + CURSOR_HOTSPOT_Y.load(Ordering::SeqCst);
+    let table = srgb_decode_table();
+
+    // Sparse 9x9 grid over a 24px box is plenty to tell light from dark
+    let mut total = 0.0;
+    let mut count = 0.0;
+    for dy in (-12..=12).step_by(3) {
+        for dx in (-12..=12).step_by(3) {
+            let pixel = sample_primary_pixel(center_x + dx, center_y + dy);
+            total += lightness(
+                table[((pixel >> 16) & 0xFF) as usize],
+                table[((pixel >> 8) & 0xFF) as usize],
+                table[(pixel & 0xFF) as usize],
+            );
+            count += 1.0;
+        }
+    }
+    Some(total / count)
+}
+
+/// Re-classify the background under the cursor.
+/// Returns true when the class changed and the cursor needs a re-render.
+unsafe fn update_backdrop_class() -> bool {
+    if get_adaptive_mode() == AdaptiveMode::Off {
+        return false;
+    }
+
+    let previous = Backdrop::from_u32(BACKDROP_CLASS.load(Ordering::SeqCst));
+    let threshold = CONFIG_ADAPTIVE_THRESHOLD.load(Ordering::Relaxed) as f32 / 100.0;
+    let class = match sample_backdrop_lightness() {
+        Some(l) if l > threshold + BACKDROP_HYSTERESIS => Backdrop::Light,
+        Some(l) if l < threshold - BACKDROP_HYSTERESIS => Backdrop::Dark,
+        Some(_) if previous == Backdrop::Unknown => Backdrop::Light,
+        Some(_) => previous,
+        None => Backdrop::Unknown,
+    };
+
+    if class == previous {
+        return false;
+    }
+    log_limited!(Debug, Render, "Background under cursor is now {:?}", class);
+    BACKDROP_CLASS.store(class as u32, Ordering::SeqCst);
+    true
+}
+
+/// Coverage weighted lightness of the rendered cursor
+fn cursor_lightness(canvas: &Canvas) -> Option<f32> {
+    let mut total = 0.0;
+    let mut weight = 0.0;
+    for pixel in canvas.pixels.iter().filter(|p| p.a > 0.0) {
+        total += lightness(pixel.r / pixel.a, pixel.g / pixel.a, pixel.b / pixel.a) * pixel.a;
+        weight += pixel.a;
+    }
+    (weight > 0.0).then(|| total / weight)
+}
+
+/// Invert a premultiplied pixel's color in sRGB space, keeping its alpha
+fn invert_pixel(pixel: LinearPixel) -> LinearPixel {
+    if pixel.a <= 0.0 {
+        return pixel;
+    }
+    let table = srgb_decode_table();
+    let invert = |channel: f32| table[255 - srgb_encode(channel / pixel.a) as usize] * pixel.a;
+    LinearPixel {
+        r: invert(pixel.r),
+        g: invert(pixel.g),
+        b: invert(pixel.b),
+        a: pixel.a,
+    }
+}
+
+/// Adjust the finished render to the background class, runs right before
+/// the render buffer is presented
+unsafe fn apply_adaptive_contrast(canvas: &mut Canvas) {
+    let backdrop = Backdrop::from_u32(BACKDROP_CLASS.load(Ordering::SeqCst));
+    if backdrop == Backdrop::Unknown {
+        return;
+    }
+
+    match get_adaptive_mode() {
+        AdaptiveMode::Off => {}
+        AdaptiveMode::Variant => {
+            let Some(cursor) = cursor_lightness(canvas) else {
+                return;
+            };
+            // Only the variant that matches the background needs flipping
+            if (cursor > 0.5) == (backdrop == Backdrop::Light) {
+                for pixel in canvas.pixels.iter_mut() {
+                    *pixel = invert_pixel(*pixel);
+                }
+            }
+        }
+        AdaptiveMode::Outline => {
+            let height = canvas.height;
+            let mut silhouette = CoverageMask::new(canvas.width, height);
+            for (i, pixel) in canvas.pixels.iter().enumerate() {
+                if pixel.a > 0.0 && i < silhouette.data.len() {
+                    silhouette.data[i] = pixel.a;
+                    silhouette.include(i % canvas.width, i / canvas.width);
+                }
+            }
+
+            let mut ring = CoverageMask::new(canvas.width, height);
+            ring.data.clone_from(&silhouette.data);
+            ring.bounds = silhouette.bounds;
+            ring.spread(resolve_outline_thickness(0.0).max(1.0));
+            for (r, s) in ring.data.iter_mut().zip(&silhouette.data) {
+                *r = (*r - s).max(0.0);
+            }
+
+            let color = if backdrop == Backdrop::Dark {
+                0xFFFFFFFF
+            } else {
+                0xFF000000
+            };
+            composite_mask(canvas, &ring, color);
+        }
+    }
+}
+
+// =============================================================================
+// PNG images
+// =============================================================================
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn crc32(data: &[u8]) -> u32 {
+    static TABLE: OnceLock<[u32; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xEDB88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        table
+    });
+    !data.iter().fold(!0u32, |c, &b| {
+        table[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8)
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+fn push_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Deflate length codes 257-285: (base length, extra bits)
+const DEFLATE_LENGTHS: [(u16, u8); 29] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 1),
+    (13, 1),
+    (15, 1),
+    (17, 1),
+    (19, 2),
+    (23, 2),
+    (27, 2),
+    (31, 2),
+    (35, 3),
+    (43, 3),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 4),
+    (115, 4),
+    (131, 5),
+    (163, 5),
+    (195, 5),
+    (227, 5),
+    (258, 0),
+];
+
+/// Deflate distance codes 0-29: (base distance, extra bits)
+const DEFLATE_DISTANCES: [(u16, u8); 30] = [
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 1),
+    (7, 1),
+    (9, 2),
+    (13, 2),
+    (17, 3),
+    (25, 3),
+    (33, 4),
+    (49, 4),
+    (65, 5),
+    (97, 5),
+    (129, 6),
+    (193, 6),
+    (257, 7),
+    (385, 7),
+    (513, 8),
+    (769, 8),
+    (1025, 9),
+    (1537, 9),
+    (2049, 10),
+    (3073, 10),
+    (4097, 11),
+    (6145, 11),
+    (8193, 12),
+    (12289, 12),
+    (16385, 13),
+    (24577, 13),
+];
+
+/// Deflate's bit order: values LSB first, Huffman codes MSB first
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.acc |= value << self.bits;
+        self.bits += count;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    /// Literal/length symbol in the fixed Huffman table
+    fn symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// Index of the last (base, extra) entry whose base is <= value
+fn deflate_code(table: &[(u16, u8)], value: usize) -> usize {
+    table
+        .iter()
+        .rposition(|(base, _)| *base as usize <= value)
+        .unwrap_or(0)
+}
+
+/// zlib stream with one fixed Huffman block. Cursor images are mostly
+/// transparent rows, so plain LZ77 matching gets them small enough.
+fn zlib_deflate(data: &[u8]) -> Vec<u8> {
+    const WINDOW: usize = 32768;
+    const MAX_CHAIN: usize = 32;
+    const NONE: usize = usize::MAX;
+
+    let mut writer = BitWriter {
+        out: vec![0x78, 0x01],
+        acc: 0,
+        bits: 0,
+    };
+    // BFINAL, fixed Huffman codes
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+
+    let hash = |i: usize| {
+        let v = (data[i] as usize) << 16 | (data[i + 1] as usize) << 8 | data[i + 2] as usize;
+        (v.wrapping_mul(2654435761) >> 17) & (WINDOW - 1)
+    };
+    let mut head = vec![NONE; WINDOW];
+    let mut prev = vec![NONE; data.len()];
+    let insert = |head: &mut [usize], prev: &mut [usize], i: usize| {
+        if i + 3 <= data.len() {
+            let h = hash(i);
+            prev[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + 3 <= data.len() {
+            let max_len = (data.len() - i).min(258);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != NONE && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let len = (0..max_len)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+
+        if best_len >= 3 {
+            let code = deflate_code(&DEFLATE_LENGTHS, best_len);
+            let (base, extra) = DEFLATE_LENGTHS[code];
+            writer.symbol(257 + code as u16);
+            writer.bits((best_len - base as usize) as u32, extra as u32);
+
+            let code = deflate_code(&DEFLATE_DISTANCES, best_dist);
+            let (base, extra) = DEFLATE_DISTANCES[code];
+            writer.code(code as u32, 5);
+            writer.bits((best_dist - base as usize) as u32, extra as u32);
+
+            for k in i..i + best_len {
+                insert(&mut head, &mut prev, k);
+            }
+            i += best_len;
+        } else {
+            writer.symbol(data[i] as u16);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+    writer.symbol(256);
+
+    let mut out = writer.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// 8-bit RGBA PNG from straight alpha 0xAARRGGBB pixels, with the hotspot
+/// in a `hotspot` tEXt chunk as "x,y"
+fn encode_png(width: usize, height: usize, argb: &[u32], hotspot: (i32, i32)) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(height * (width * 4 + 1));
+    for row in argb.chunks(width).take(height) {
+        // Filter type None for every scanline
+        raw.push(0);
+        for pixel in row {
+            let [b, g, r, a] = pixel.to_le_bytes();
+            raw.extend_from_slice(&[r, g, b, a]);
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, color type 6 (RGBA), deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let text = format!("hotspot\0{},{}", hotspot.0, hotspot.1);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    push_png_chunk(&mut png, b"IHDR", &header);
+    push_png_chunk(&mut png, b"tEXt", text.as_bytes());
+    push_png_chunk(&mut png, b"IDAT", &zlib_deflate(&raw));
+    push_png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+impl Canvas {
+    /// The canvas as a PNG file, hotspot included
+    pub fn to_png(&self) -> Vec<u8> {
+        encode_png(self.width, self.height, &self.to_argb(), self.hotspot)
+    }
+
+    /// Top left crop holding everything drawn, at least `min_size` square
+    pub fn trimmed(&self, min_size: usize) -> Canvas {
+        let (mut width, mut height) = (min_size.min(self.width), min_size.min(self.height));
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if pixel.a > 0.0 {
+                width = width.max(i % self.width + 1);
+                height = height.max(i / self.width + 1);
+            }
+        }
+        self.crop(width, height)
+    }
+}
+
+// =============================================================================
+// PNG export
+// =============================================================================
+//
+// touch /tmp/constellation_cursor_export dumps what the cursor plane is showing,
+// writing a design into it renders that offline instead:
+//   echo "pointer 2.5" > /tmp/constellation_cursor_export
+//   echo "~/my_cursor.json 1.5 30" > /tmp/constellation_cursor_export
+// (design, then optional scale and rotation, designs can be JSON or SVG). The file is picked up on the
+// next cursor move and written to export_dir as constellation_cursor_<name>.png
+
+static EXPORT_CHECK_MS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+fn export_dir() -> String {
+    if let Ok(dir) = std::env::var("CONSTELLATION_CURSOR_EXPORT_DIR") {
+        return dir;
+    }
+    let dir = CONFIG_EXPORT_DIR
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    if dir.is_empty() {
+        "/tmp".into()
+    } else {
+        dir
+    }
+}
+
+/// Undo the plane's pixel format, back to straight alpha 0xAARRGGBB
+fn decode_plane_pixel(pixel: u32, mode: PixelBlendMode) -> u32 {
+    let a = pixel >> 24;
+    match mode {
+        PixelBlendMode::Coverage => pixel,
+        // The plane shows these opaque, whatever is in alpha
+        PixelBlendMode::None => pixel | 0xFF00_0000,
+        PixelBlendMode::Premultiplied if a == 0 => 0,
+        PixelBlendMode::Premultiplied => {
+            let straight = |shift: u32| ((((pixel >> shift) & 0xFF) * 255 + a / 2) / a).min(255);
+            (a << 24) | (straight(16) << 16) | (straight(8) << 8) | straight(0)
+        }
+    }
+}
+
+/// PNG of the visible part of the cursor buffer
+unsafe fn plane_png() -> Option<Vec<u8>> {
+    if CURSOR_BUFFER.is_null() {
+        return None;
+    }
+    let _render = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mode = PixelBlendMode::from_u32(CURSOR_BLEND_MODE.load(Ordering::Relaxed));
+    let stride = CURSOR_WIDTH.load(Ordering::SeqCst) as usize;
+    let size = (CURSOR_DISPLAY_SIZE as usize).min(stride);
+
+    let mut pixels = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            pixels.push(decode_plane_pixel(*CURSOR_BUFFER.add(y * stride + x), mode));
+        }
+    }
+    let hotspot = (
+        CURSOR_HOTSPOT_X.load(Ordering::SeqCst),
+        CURSOR_HOTSPOT_Y.load(Ordering::SeqCst),
+    );
+    Some(encode_png(size, size, &pixels, hotspot))
+}
+
+/// Render a design named in an export request, returns (file name part, PNG)
+unsafe fn design_png(request: &str) -> Option<(String, Vec<u8>)> {
+    let mut words = request.split_whitespace();
+    let design = words.next()?;
+    let scale = words
+        .next()
+        .and_then(|s| s.parse::<f32>().ok())
+        .unwrap_or_else(get_cursor_scale)
+        .clamp(0.5, 10.0);
+    let rotation = words
+        .next()
+        .and_then(|s| s.parse::<f32>().ok())
+        .unwrap_or(0.0);
+
+    let (name, canvas) = match CursorType::from_name(design) {
+        Some(cursor_type) => {
+            let name = format!("{:?}", cursor_type).to_lowercase();
+            (
+                name,
+                render_to_canvas(&Design::Builtin(cursor_type), scale, rotation),
+            )
+        }
+        None => {
+            let path = match design.strip_prefix("~/") {
+                Some(rest) => format!("{}/{}", std::env::var("HOME").unwrap_or_default(), rest),
+                None => design.to_string(),
+            };
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    log!(Warn, Config, "Can't export {}: {}", path, e);
+                    return None;
+                }
+            };
+            let path = std::path::Path::new(&path);
+            let name = path
+                .file_stem()
+                .map_or("design".into(), |stem| stem.to_string_lossy().into_owned());
+            (
+                name,
+                render_to_canvas(&Design::from_file(path, &content), scale, rotation),
+            )
+        }
+    };
+
+    let png = canvas.trimmed(CURSOR_DISPLAY_SIZE as usize).to_png();
+    Some((format!("{}@{}x", name, scale), png))
+}
+
+/// Handle /tmp/constellation_cursor_export if someone created it
+unsafe fn check_cursor_export() {
+    if !control_check_due(&EXPORT_CHECK_MS) {
+        return;
+    }
+    let request_path = control_path("export");
+    let request = match std::fs::read_to_string(&request_path) {
+        Ok(request) => request,
+        Err(_) => return,
+    };
+    let _ = std::fs::remove_file(&request_path);
+
+    let export = if request.trim().is_empty() {
+        plane_png().map(|png| ("plane".to_string(), png))
+    } else {
+        design_png(request.trim())
+    };
+    let (name, png) = match export {
+        Some(export) => export,
+        None => return,
+    };
+
+    let path = format!("{}/constellation_cursor_{}.png", export_dir(), name);
+    match std::fs::write(&path, png) {
+        Ok(()) => log!(Info, Config, "Exported cursor to {}", path),
+        Err(e) => log!(Warn, Config, "Can't write {}: {}", path, e),
+    }
+}
+
+// =============================================================================
+// Statistics
+// =============================================================================
+//
+// Counters for telling whether the hook does anything on a machine and what
+// it costs. touch /tmp/constellation_cursor_status and the next DRM call
+// writes them to /tmp/constellation_cursor_status.txt, with "reset" in the
+// file they start over from zero after that (to measure a while of use):
+//   echo reset > /tmp/constellation_cursor_status
+
+static STAT_ATOMIC_PROPERTIES: AtomicU64 = AtomicU64::new(0);
+static STAT_CURSOR_PROPERTIES: AtomicU64 = AtomicU64::new(0);
+// Their FB or BO swapped for ours, atomic and legacy
+static STAT_FB_SUBSTITUTIONS: AtomicU64 = AtomicU64::new(0);
+static STAT_LEGACY_CURSOR_CALLS: AtomicU64 = AtomicU64::new(0);
+// Cursor calls that went through untouched for lack of a buffer
+static STAT_PASSTHROUGH: AtomicU64 = AtomicU64::new(0);
+static STAT_RENDERS: AtomicU64 = AtomicU64::new(0);
+static STAT_RENDER_NS: AtomicU64 = AtomicU64::new(0);
+static STAT_RENDER_MAX_NS: AtomicU64 = AtomicU64::new(0);
+static STAT_CONFIG_RELOADS: AtomicU64 = AtomicU64::new(0);
+// Commits that failed with our values and were tried again with theirs
+static STAT_COMMIT_RETRIES: AtomicU64 = AtomicU64::new(0);
+static STAT_FADE_FRAMES: AtomicU64 = AtomicU64::new(0);
+static STAT_FAILED_IOCTLS: Mutex<Vec<FailedIoctl>> = Mutex::new(Vec::new());
+static STATUS_CHECK_MS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Failures of one ioctl with one errno
+struct FailedIoctl {
+    request: libc::c_ulong,
+    errno: i32,
+    count: u64,
+}
+
+fn ioctl_name(request: libc::c_ulong) -> String {
+    match request {
+        DRM_IOCTL_MODE_CURSOR => "MODE_CURSOR".into(),
+        DRM_IOCTL_MODE_CURSOR2 => "MODE_CURSOR2".into(),
+        DRM_IOCTL_MODE_CREATE_DUMB => "MODE_CREATE_DUMB".into(),
+        DRM_IOCTL_MODE_MAP_DUMB => "MODE_MAP_DUMB".into(),
+        DRM_IOCTL_MODE_DESTROY_DUMB => "MODE_DESTROY_DUMB".into(),
+        DRM_IOCTL_MODE_ADDFB2 => "MODE_ADDFB2".into(),
+        DRM_IOCTL_MODE_DIRTYFB => "MODE_DIRTYFB".into(),
+        DRM_IOCTL_MODE_GETCRTC => "MODE_GETCRTC".into(),
+        DRM_IOCTL_MODE_GETFB2 => "MODE_GETFB2".into(),
+        DRM_IOCTL_GEM_CLOSE => "GEM_CLOSE".into(),
+        DRM_IOCTL_MODE_ATOMIC => "MODE_ATOMIC".into(),
+        DRM_IOCTL_VERSION => "VERSION".into(),
+        DRM_IOCTL_GET_CAP => "GET_CAP".into(),
+        DRM_IOCTL_MODE_GETRESOURCES => "MODE_GETRESOURCES".into(),
+        DRM_IOCTL_MODE_GETPLANERESOURCES => "MODE_GETPLANERESOURCES".into(),
+        DRM_IOCTL_MODE_GETPLANE => "MODE_GETPLANE".into(),
+        DRM_IOCTL_MODE_RMFB => "MODE_RMFB".into(),
+        _ => format!("0x{:x}", request),
+    }
+}
+
+/// One of our ioctls failed, `errno` read right after it
+fn note_failed_ioctl(request: libc::c_ulong, errno: i32) {
+    let mut failures = STAT_FAILED_IOCTLS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(failure) = failures
+        .iter_mut()
+        .find(|f| f.request == request && f.errno == errno)
+    {
+        failure.count += 1;
+    } else if failures.len() < 32 {
+        // Only so many ways for a handful of ioctls to fail, but don't grow forever
+        failures.push(FailedIoctl {
+            request,
+            errno,
+            count: 1,
+        });
+    }
+    drop(failures);
+    log_limited!(
+        Debug,
+        Drm,
+        "ioctl {} failed: {}",
+        ioctl_name(request),
+        std::io::Error::from_raw_os_error(errno)
+    );
+}
+
+fn note_render(started: Instant) {
+    let ns = started.elapsed().as_nanos() as u64;
+    STAT_RENDERS.fetch_add(1, Ordering::Relaxed);
+    STAT_RENDER_NS.fetch_add(ns, Ordering::Relaxed);
+    STAT_RENDER_MAX_NS.fetch_max(ns, Ordering::Relaxed);
+}
+
+fn reset_stats() {
+    for counter in [
+        &STAT_ATOMIC_PROPERTIES,
+        &STAT_CURSOR_PROPERTIES,
+        &STAT_FB_SUBSTITUTIONS,
+        &STAT_LEGACY_CURSOR_CALLS,
+        &STAT_PASSTHROUGH,
+        &STAT_RENDERS,
+        &STAT_RENDER_NS,
+        &STAT_RENDER_MAX_NS,
+        &STAT_CONFIG_RELOADS,
+        &STAT_COMMIT_RETRIES,
+        &STAT_FADE_FRAMES,
+    ] {
+        counter.store(0, Ordering::Relaxed);
+    }
+    STAT_FAILED_IOCTLS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    STATUS_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+}
+
+/// Everything the status dump shows, one "key: value" per line
+unsafe fn status_report() -> String {
+    use std::fmt::Write;
+
+    let count = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+    let renders = count(&STAT_RENDERS);
+    let render_ns = count(&STAT_RENDER_NS);
+    let mode = if NUM_CURSOR_PLANES > 0 {
+        "atomic"
+    } else if LEGACY_CURSOR_CRTC.load(Ordering::SeqCst) != 0 {
+        "legacy"
+    } else {
+        "no cursor seen yet"
+    };
+
+    let mut report = String::new();
+    let _ = writeln!(report, "constellation-cursor {}", VERSION);
+    let _ = writeln!(report, "pid: {}", std::process::id());
+    let _ = writeln!(report, "uptime: {}s", monotonic_ms() / 1000);
+    let _ = writeln!(report, "mode: {}", mode);
+    let _ = writeln!(
+        report,
+        "cursor buffer: {}",
+        if INITIALIZED.load(Ordering::SeqCst) {
+            format!(
+                "fb {} on fd {}",
+                CURSOR_FB_ID.load(Ordering::SeqCst),
+                CURSOR_FD.load(Ordering::SeqCst)
+            )
+        } else {
+            match *BUFFER_ERROR.lock().unwrap_or_else(|e| e.into_inner()) {
+                Some(e) => format!(
+                    "none, {} ({} tries), passing the compositor's cursor through",
+                    e,
+                    BUFFER_FAILURES.load(Ordering::SeqCst)
+                ),
+                None => "not created".into(),
+            }
+        }
+    );
+    let _ = writeln!(
+        report,
+        "cursor planes: {:?}",
+        &CURSOR_PLANE_IDS[..NUM_CURSOR_PLANES]
+    );
+    let _ = writeln!(
+        report,
+        "blend mode: {:?}",
+        PixelBlendMode::from_u32(CURSOR_BLEND_MODE.load(Ordering::Relaxed))
+    );
+    let _ = writeln!(report, "visibility: {:?}", visibility());
+    let _ = writeln!(report, "cursor type: {:?}", get_cursor_type());
+    let _ = writeln!(report, "scale: {}", get_cursor_scale());
+    let hooks: Vec<&str> = HookPath::ALL
+        .into_iter()
+        .filter(|path| hook_seen(*path))
+        .map(HookPath::name)
+        .collect();
+    let _ = writeln!(report, "hooks seen: {}", hooks.join(", "));
+    let _ = writeln!(
+        report,
+        "atomic properties: {}",
+        count(&STAT_ATOMIC_PROPERTIES)
+    );
+    let _ = writeln!(
+        report,
+        "cursor plane properties: {}",
+        count(&STAT_CURSOR_PROPERTIES)
+    );
+    let _ = writeln!(
+        report,
+        "fb substitutions: {}",
+        count(&STAT_FB_SUBSTITUTIONS)
+    );
+    let _ = writeln!(
+        report,
+        "legacy cursor calls: {}",
+        count(&STAT_LEGACY_CURSOR_CALLS)
+    );
+    let _ = writeln!(
+        report,
+        "passed through without a buffer: {}",
+        count(&STAT_PASSTHROUGH)
+    );
+    let _ = writeln!(report, "renders: {}", renders);
+    let _ = writeln!(
+        report,
+        "render time: {:.3}ms total, {:.3}ms avg, {:.3}ms max",
+        render_ns as f64 / 1e6,
+        render_ns as f64 / 1e6 / renders.max(1) as f64,
+        count(&STAT_RENDER_MAX_NS) as f64 / 1e6
+    );
+    let _ = writeln!(report, "config reloads: {}", count(&STAT_CONFIG_RELOADS));
+    let _ = writeln!(
+        report,
+        "commits retried without our cursor: {}",
+        count(&STAT_COMMIT_RETRIES)
+    );
+    let given_up: Vec<u32> = (0..NUM_CURSOR_PLANES)
+        .filter(|idx| CURSOR_PLANE_GIVEN_UP[*idx])
+        .map(|idx| CURSOR_PLANE_IDS[idx])
+        .collect();
+    let _ = writeln!(report, "planes left to the compositor: {:?}", given_up);
+    let _ = writeln!(
+        report,
+        "atomic requests tracked: {}",
+        ATOMIC_REQUESTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    );
+    let _ = writeln!(report, "fade frames: {}", count(&STAT_FADE_FRAMES));
+
+    let failures = STAT_FAILED_IOCTLS.lock().unwrap_or_else(|e| e.into_inner());
+    let _ = writeln!(
+        report,
+        "failed ioctls: {}",
+        failures.iter().map(|f| f.count).sum::<u64>()
+    );
+    for failure in failures.iter() {
+        let _ = writeln!(
+            report,
+            "  {}: {} x{}",
+            ioctl_name(failure.request),
+            std::io::Error::from_raw_os_error(failure.errno),
+            failure.count
+        );
+    }
+    report
+}
+
+/// Handle /tmp/constellation_cursor_status if someone created it
+unsafe fn check_status_request() {
+    if !control_check_due(&STATUS_CHECK_MS) {
+        return;
+    }
+
+    let request_path = control_path("status");
+    let request = match std::fs::read_to_string(&request_path) {
+        Ok(request) => request,
+        Err(_) => return,
+    };
+    let _ = std::fs::remove_file(&request_path);
+
+    let report_path = control_path("status.txt");
+    match write_control_file(&report_path, &status_report()) {
+        Ok(()) => log!(Info, Config, "Wrote status to {}", report_path),
+        Err(e) => log!(Warn, Config, "Can't write {}: {}", report_path, e),
+    }
+    if request.trim() == "reset" {
+        reset_stats();
+    }
+}
+
+// =============================================================================
+// Doctor
+// =============================================================================
+//
+// CONSTELLATION_CURSOR_DOCTOR=1 looks the DRM device over shortly after the
+// compositor first uses it and writes constellation_cursor_doctor.txt in the
+// control dir (/tmp unless CONSTELLATION_CURSOR_CONTROL_DIR says otherwise):
+// driver, caps, CRTCs and planes, whether we get a buffer the display takes,
+// which of our hooks the compositor goes through and what that means for the
+// cursor. It's written again whenever the compositor uses another hook.
+
+// Give the compositor time to set its client caps and do its first commit,
+// planes only show up with universal planes on
+const DOCTOR_DELAY: Duration = Duration::from_secs(2);
+// Character device major of /dev/dri/*
+const DRM_MAJOR: u32 = 226;
+
+/// The ways a compositor can reach us, bits of HOOKS_SEEN
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+enum HookPath {
+    CursorIoctl = 0,
+    SetCursor = 1,
+    SetCursor2 = 2,
+    MoveCursor = 3,
+    GetPlane = 4,
+    AtomicAddProperty = 5,
+    AtomicCursorPlane = 6,
+    AtomicIoctl = 7,
+}
+
+impl HookPath {
+    const ALL: [HookPath; 8] = [
+        HookPath::CursorIoctl,
+        HookPath::SetCursor,
+        HookPath::SetCursor2,
+        HookPath::MoveCursor,
+        HookPath::GetPlane,
+        HookPath::AtomicAddProperty,
+        HookPath::AtomicCursorPlane,
+        HookPath::AtomicIoctl,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            HookPath::CursorIoctl => "ioctl MODE_CURSOR/MODE_CURSOR2",
+            HookPath::SetCursor => "drmModeSetCursor",
+            HookPath::SetCursor2 => "drmModeSetCursor2",
+            HookPath::MoveCursor => "drmModeMoveCursor",
+            HookPath::GetPlane => "drmModeGetPlane",
+            HookPath::AtomicAddProperty => "drmModeAtomicAddProperty",
+            HookPath::AtomicCursorPlane => "drmModeAtomicAddProperty on a cursor plane",
+            HookPath::AtomicIoctl => "ioctl MODE_ATOMIC (commits)",
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+static HOOKS_SEEN: AtomicU32 = AtomicU32::new(0);
+static DOCTOR_STARTED: AtomicBool = AtomicBool::new(false);
+static DOCTOR_PROBE: Mutex<Option<DoctorProbe>> = Mutex::new(None);
+
+/// What asking the device told us
+struct DoctorProbe {
+    /// The device part of the report
+    lines: Vec<String>,
+    cursor_planes: usize,
+    cursor_size: Option<(u64, u64)>,
+    /// Why we can't have a cursor buffer, None when we can
+    buffer_error: Option<String>,
+}
+
+fn hook_seen(path: HookPath) -> bool {
+    HOOKS_SEEN.load(Ordering::Relaxed) & path.bit() != 0
+}
+
+/// The compositor went through `path`, the first time is news for the doctor
+fn note_hook(path: HookPath) {
+    if HOOKS_SEEN.fetch_or(path.bit(), Ordering::Relaxed) & path.bit() != 0 {
+        return;
+    }
+    log!(Debug, Drm, "First call through {}", path.name());
+    if doctor_enabled() {
+        write_doctor_report();
+    }
+}
+
+fn doctor_enabled() -> bool {
+    std::env::var_os("CONSTELLATION_CURSOR_DOCTOR").is_some_and(|v| v != "0")
+}
+
+/// Whether `fd` is open on a DRM device node (char major 226)
+fn is_drm_node(fd: i32) -> bool {
+    if fd < 0 {
+        return false;
+    }
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+        return false;
+    }
+    stat.st_mode & libc::S_IFMT == libc::S_IFCHR && libc::major(stat.st_rdev) == DRM_MAJOR
+}
+
+/// First DRM use, have a look at the device in a bit if asked to
+fn start_doctor() {
+    if !doctor_enabled() || DOCTOR_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    thread::spawn(|| {
+        thread::sleep(DOCTOR_DELAY);
+        // Compositors open and close a few nodes while starting up, the fd
+        // we first saw may be gone or be something else by now
+        let fd = CURSOR_FD.load(Ordering::SeqCst);
+        if !is_drm_node(fd) {
+            log!(
+                Warn,
+                Drm,
+                "Doctor: fd {} isn't a DRM device anymore, not probing it",
+                fd
+            );
+            return;
+        }
+        let probe = unsafe { doctor_probe(fd) };
+        *DOCTOR_PROBE.lock().unwrap_or_else(|e| e.into_inner()) = Some(probe);
+        write_doctor_report();
+    });
+}
+
+/// An ioctl the doctor makes, the error says which and why
+unsafe fn probe_ioctl(fd: i32, request: libc::c_ulong, arg: *mut c_void) -> Result<(), String> {
+    if forward_ioctl(fd, request, arg) < 0 {
+        return Err(format!(
+            "{} failed: {}",
+            ioctl_name(request),
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+unsafe fn probe_cap(fd: i32, capability: u64) -> Option<u64> {
+    let mut cap = DrmGetCap {
+        capability,
+        ..Default::default()
+    };
+    probe_ioctl(fd, DRM_IOCTL_GET_CAP, &mut cap as *mut _ as *mut c_void).ok()?;
+    Some(cap.value)
+}
+
+/// "amdgpu 3.57.0 (AMD GPU)"
+unsafe fn probe_driver(fd: i32) -> Result<String, String> {
+    let mut version = DrmVersion::default();
+    probe_ioctl(fd, DRM_IOCTL_VERSION, &mut version as *mut _ as *mut c_void)?;
+    let mut name = vec![0u8; version.name_len];
+    let mut desc = vec![0u8; version.desc_len];
+    version.name = name.as_mut_ptr() as usize;
+    version.desc = desc.as_mut_ptr() as usize;
+    version.date_len = 0;
+    probe_ioctl(fd, DRM_IOCTL_VERSION, &mut version as *mut _ as *mut c_void)?;
+    name.truncate(version.name_len);
+    desc.truncate(version.desc_len);
+    Ok(format!(
+        "{} {}.{}.{} ({})",
+        String::from_utf8_lossy(&name),
+        version.major,
+        version.minor,
+        version.patchlevel,
+        String::from_utf8_lossy(&desc)
+    ))
+}
+
+/// IDs from one of the count + pointer ioctls, asked twice: once for the
+/// count and once with room for that many
+unsafe fn probe_crtcs(fd: i32) -> Result<Vec<u32>, String> {
+    let mut res = DrmModeCardRes::default();
+    probe_ioctl(
+        fd,
+        DRM_IOCTL_MODE_GETRESOURCES,
+        &mut res as *mut _ as *mut c_void,
+    )?;
+    let mut crtcs = vec![0u32; res.count_crtcs as usize];
+    let mut res = DrmModeCardRes {
+        crtc_id_ptr: crtcs.as_mut_ptr() as u64,
+        count_crtcs: crtcs.len() as u32,
+        ..Default::default()
+    };
+    probe_ioctl(
+        fd,
+        DRM_IOCTL_MODE_GETRESOURCES,
+        &mut res as *mut _ as *mut c_void,
+    )?;
+    crtcs.truncate(res.count_crtcs as usize);
+    Ok(crtcs)
+}
+
+unsafe fn probe_planes(fd: i32) -> Result<Vec<u32>, String> {
+    let mut res = DrmModeGetPlaneRes::default();
+    probe_ioctl(
+        fd,
+        DRM_IOCTL_MODE_GETPLANERESOURCES,
+        &mut res as *mut _ as *mut c_void,
+    )?;
+    let mut planes = vec![0u32; res.count_planes as usize];
+    let mut res = DrmModeGetPlaneRes {
+        plane_id_ptr: planes.as_mut_ptr() as u64,
+        count_planes: planes.len() as u32,
+    };
+    probe_ioctl(
+        fd,
+        DRM_IOCTL_MODE_GETPLANERESOURCES,
+        &mut res as *mut _ as *mut c_void,
+    )?;
+    planes.truncate(res.count_planes as usize);
+    Ok(planes)
+}
+
+/// A plane's "type" property, read without registering it like a scan would
+unsafe fn probe_plane_type(fd: i32, plane_id: u32) -> Option<u64> {
+    let (Some(get_props), Some(get_prop)) = (REAL_GET_OBJECT_PROPERTIES, REAL_GET_PROPERTY) else {
+        return None;
+    };
+    let props = get_props(fd, plane_id, DRM_MODE_OBJECT_PLANE);
+    if props.is_null() {
+        return None;
+    }
+    let mut plane_type = None;
+    for i in 0..(*props).count_props as usize {
+        let prop = get_prop(fd, *(*props).props.add(i));
+        if prop.is_null() {
+            continue;
+        }
+        if libc::strcmp((*prop).name.as_ptr(), c"type".as_ptr()) == 0 {
+            plane_type = Some(*(*props).prop_values.add(i));
+        }
+        if let Some(free_prop) = REAL_FREE_PROPERTY {
+            free_prop(prop);
+        }
+    }
+    if let Some(free_props) = REAL_FREE_OBJECT_PROPERTIES {
+        free_props(props);
+    }
+    plane_type
+}
+
+/// Make a buffer like create_cursor_buffer does and throw it away again
+unsafe fn probe_cursor_buffer(fd: i32) -> Result<(), String> {
+    let mut create = DrmModeCreateDumb {
+        width: CURSOR_BUFFER_SIZE,
+        height: CURSOR_BUFFER_SIZE,
+        bpp: 32,
+        ..Default::default()
+    };
+    probe_ioctl(
+        fd,
+        DRM_IOCTL_MODE_CREATE_DUMB,
+        &mut create as *mut _ as *mut c_void,
+    )?;
+
+    let mut fb = DrmModeFB2 {
+        width: CURSOR_BUFFER_SIZE,
+        height: CURSOR_BUFFER_SIZE,
+        pixel_format: DRM_FORMAT_ARGB8888,
+        handles: [create.handle, 0, 0, 0],
+        pitches: [create.pitch, 0, 0, 0],
+        ..Default::default()
+    };
+    let added = probe_ioctl(fd, DRM_IOCTL_MODE_ADDFB2, &mut fb as *mut _ as *mut c_void);
+    if added.is_ok() {
+        let mut fb_id = fb.fb_id;
+        forward_ioctl(fd, DRM_IOCTL_MODE_RMFB, &mut fb_id as *mut _ as *mut c_void);
+    }
+    let mut destroy = DrmModeDestroyDumb {
+        handle: create.handle,
+    };
+    forward_ioctl(
+        fd,
+        DRM_IOCTL_MODE_DESTROY_DUMB,
+        &mut destroy as *mut _ as *mut c_void,
+    );
+    added
+}
+
+unsafe fn doctor_probe(fd: i32) -> DoctorProbe {
+    init_plane_functions();
+    let mut lines = Vec::new();
+
+    match probe_driver(fd) {
+        Ok(driver) => lines.push(format!("driver: {}", driver)),
+        Err(e) => lines.push(format!("driver: unknown, {}", e)),
+    }
+    let dumb = probe_cap(fd, DRM_CAP_DUMB_BUFFER);
+    lines.push(format!(
+        "dumb buffers: {}",
+        match dumb {
+            Some(0) => "not supported",
+            Some(_) => "supported",
+            None => "unknown",
+        }
+    ));
+    let cursor_size = probe_cap(fd, DRM_CAP_CURSOR_WIDTH).zip(probe_cap(fd, DRM_CAP_CURSOR_HEIGHT));
+    lines.push(match cursor_size {
+        Some((w, h)) => format!("cursor size: {}x{}", w, h),
+        None => "cursor size: not reported".into(),
+    });
+
+    match probe_crtcs(fd) {
+        Ok(crtcs) => {
+            let list: Vec<String> = crtcs.iter().map(|c| c.to_string()).collect();
+            lines.push(format!("CRTCs: {}", list.join(", ")));
+        }
+        Err(e) => lines.push(format!("CRTCs: {}", e)),
+    }
+
+    let mut cursor_planes = 0;
+    match probe_planes(fd) {
+        Ok(planes) => {
+            lines.push("planes:".into());
+            let mut universal = false;
+            for plane_id in planes {
+                let plane_type = probe_plane_type(fd, plane_id);
+                let kind = match plane_type {
+                    Some(DRM_PLANE_TYPE_CURSOR) => "cursor",
+                    Some(DRM_PLANE_TYPE_PRIMARY) => "primary",
+                    Some(_) => "overlay",
+                    None => "?",
+                };
+                universal |= matches!(
+                    plane_type,
+                    Some(DRM_PLANE_TYPE_CURSOR | DRM_PLANE_TYPE_PRIMARY)
+                );
+                if plane_type == Some(DRM_PLANE_TYPE_CURSOR) {
+                    cursor_planes += 1;
                 }
+
+                let mut plane = DrmModeGetPlaneCmd {
+                    plane_id,
+                    ..Default::default()
+                };
+                let state = match probe_ioctl(
+                    fd,
+                    DRM_IOCTL_MODE_GETPLANE,
+                    &mut plane as *mut _ as *mut c_void,
+                ) {
+                    Ok(()) if plane.crtc_id != 0 => {
+                        format!("on CRTC {} showing FB {}", plane.crtc_id, plane.fb_id)
+                    }
+                    Ok(()) => "off".into(),
+                    Err(e) => e,
+                };
+                lines.push(format!(
+                    "  {:<4} {:<8} CRTCs 0x{:x}  {}",
+                    plane_id, kind, plane.possible_crtcs, state
+                ));
+            }
+            if !universal {
+                lines.push(
+                    "  (primary and cursor planes are only listed once the compositor turns on universal planes)"
+                        .into(),
+                );
             }
+        }
+        Err(e) => lines.push(format!("planes: {}", e)),
+    }
+
+    let buffer_error = probe_cursor_buffer(fd).err();
+    lines.push(format!(
+        "buffer test: CREATE_DUMB {0}x{0} + ADDFB2 ARGB8888 {1}",
+        CURSOR_BUFFER_SIZE,
+        buffer_error.as_deref().unwrap_or("ok")
+    ));
+
+    DoctorProbe {
+        lines,
+        cursor_planes,
+        cursor_size,
+        buffer_error,
+    }
+}
 
-            thread::sleep(frame_time);
+/// What the probe and the hooks seen so far mean for the cursor
+fn doctor_verdict(probe: &DoctorProbe) -> Vec<String> {
+    let mut verdict = Vec::new();
+    let legacy = [
+        HookPath::CursorIoctl,
+        HookPath::SetCursor,
+        HookPath::SetCursor2,
+    ]
+    .into_iter()
+    .any(hook_seen);
+
+    if let Some(e) = &probe.buffer_error {
+        verdict.push(format!(
+            "We can't make a cursor buffer the display takes ({}). The compositor's own cursor is left alone.",
+            e
+        ));
+    }
+    if let Some((w, h)) = probe.cursor_size {
+        if w < CURSOR_DISPLAY_SIZE as u64 || h < CURSOR_DISPLAY_SIZE as u64 {
+            verdict.push(format!(
+                "The driver's cursor is {}x{}, smaller than the {}x{} we show, it may refuse our buffer.",
+                w, h, CURSOR_DISPLAY_SIZE, CURSOR_DISPLAY_SIZE
+            ));
         }
+    }
+
+    let given_up = unsafe { (0..NUM_CURSOR_PLANES).any(|idx| CURSOR_PLANE_GIVEN_UP[idx]) };
+    if given_up {
+        verdict.push(
+            "The driver kept failing commits with our cursor, the compositor's is shown on that plane instead."
+                .into(),
+        );
+    }
+    if hook_seen(HookPath::AtomicCursorPlane) {
+        verdict.push(
+            "Atomic: the compositor sets the cursor plane through libdrm, we swap its FB_ID for ours."
+                .into(),
+        );
+    } else if hook_seen(HookPath::AtomicAddProperty) && probe.cursor_planes == 0 {
+        verdict.push(
+            "The compositor commits atomically but the driver has no cursor type plane, there's nothing to swap. It draws the cursor into the screen itself."
+                .into(),
+        );
+    } else if hook_seen(HookPath::AtomicAddProperty) {
+        verdict.push(
+            "Atomic commits, but none touched a cursor plane yet. If the cursor has moved the compositor draws it in software, check its settings (WLR_NO_HARDWARE_CURSORS and the like)."
+                .into(),
+        );
+    } else if hook_seen(HookPath::AtomicIoctl) {
+        verdict.push(
+            "The compositor commits atomically without drmModeAtomicAddProperty (a static libdrm or its own ioctls), we can't change its properties. The cursor is left alone."
+                .into(),
+        );
+    }
+
+    if legacy {
+        verdict.push(
+            "Legacy: the compositor sets its cursor with drmModeSetCursor or the cursor ioctls, we hand the driver our buffer instead."
+                .into(),
+        );
+    }
+
+    if verdict.is_empty() {
+        verdict.push(
+            "No cursor calls seen yet. Move the mouse, if this stays the compositor doesn't put its cursor up through DRM (software cursor or another API)."
+                .into(),
+        );
+    }
+    verdict
+}
 
-        if CURSOR_FADING_OUT.load(Ordering::SeqCst) {
-            CURSOR_FADING_OUT.store(false, Ordering::SeqCst);
-            CURSOR_FADE_ALPHA.store(0, Ordering::SeqCst);
+fn doctor_report(probe: &DoctorProbe) -> String {
+    use std::fmt::Write;
 
-            unsafe {
-                if !CURSOR_BUFFER.is_null() {
-                    let width = CURSOR_WIDTH.load(Ordering::SeqCst) as usize;
-                    let height = CURSOR_HEIGHT.load(Ordering::SeqCst) as usize;
-                    for i in 0..(width * height) {
-                        *CURSOR_BUFFER.add(i) = 0x00000000;
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "Constellation Cursor doctor (v{}, pid {})",
+        VERSION,
+        std::process::id()
+    );
+    let _ = writeln!(report, "\nDevice");
+    for line in &probe.lines {
+        let _ = writeln!(report, "  {}", line);
+    }
+    let _ = writeln!(report, "\nHooks the compositor went through");
+    for path in HookPath::ALL {
+        let seen = if hook_seen(path) { "yes" } else { "no " };
+        let _ = writeln!(report, "  {} {}", seen, path.name());
+    }
+    let _ = writeln!(report, "\nWhat that means");
+    for line in doctor_verdict(probe) {
+        let _ = writeln!(report, "  - {}", line);
+    }
+    report
+}
+
+/// Write the report, once the probe has been and gone
+fn write_doctor_report() {
+    let probe = DOCTOR_PROBE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(probe) = probe.as_ref() else {
+        return;
+    };
+    let path = control_path("doctor.txt");
+    match write_control_file(&path, &doctor_report(probe)) {
+        Ok(()) => log!(Info, Drm, "Doctor report written to {}", path),
+        Err(e) => log!(Warn, Drm, "Can't write {}: {}", path, e),
+    }
+}
+
+/// Hooked `ioctl`, every DRM ioctl of the compositor comes through here.
+///
+/// # Safety
+/// Same as `ioctl(2)`: `arg` points at the struct `request` expects.
+#[no_mangle]
+pub unsafe extern "C" fn ioctl(fd: i32, request: libc::c_ulong, arg: *mut c_void) -> i32 {
+    init_real_functions();
+
+    // Capture DRM fd from any DRM ioctl (they all start with 0x64 = 'd') //noice
+    if (request >> 8) & 0xFF == 0x64 && CURSOR_FD.load(Ordering::SeqCst) < 0 {
+        CURSOR_FD.store(fd, Ordering::SeqCst);
+        log!(Info, Drm, "Captured DRM fd: {}", fd);
+        start_doctor();
+    }
+    check_status_request();
+    if request == DRM_IOCTL_MODE_ATOMIC {
+        note_hook(HookPath::AtomicIoctl);
+    }
+
+    // Handle legacy cursor operations (hopefully)
+    if request == DRM_IOCTL_MODE_CURSOR || request == DRM_IOCTL_MODE_CURSOR2 {
+        log_limited!(Trace, Drm, "Legacy cursor ioctl: 0x{:x}", request);
+        STAT_LEGACY_CURSOR_CALLS.fetch_add(1, Ordering::Relaxed);
+        note_hook(HookPath::CursorIoctl);
+        check_cursor_refresh();
+
+        // For cursor operations, use OUR proper buffer instead
+        let cursor = arg as *mut DrmModeCursor2;
+        if !cursor.is_null() {
+            let flags = (*cursor).flags;
+            let hiding = flags & DRM_MODE_CURSOR_BO != 0 && (*cursor).handle == 0;
+            if !hiding && !ensure_cursor_buffer(fd) {
+                return pass_cursor_through(fd, request, &mut *cursor);
+            }
+
+            if flags & DRM_MODE_CURSOR_BO != 0 {
+                // If compositor wants to hide cursor (handle = 0), allow it through
+                if hiding {
+                    if INITIALIZED.load(Ordering::SeqCst) && cursor_hide_requested() {
+                        log!(
+                            Debug,
+                            Drm,
+                            "Compositor hiding cursor (handle=0), fading out first"
+                        );
+                        return 0;
                     }
+                    log!(
+                        Debug,
+                        Drm,
+                        "Compositor hiding cursor (handle=0), passing through"
+                    );
+                    LEGACY_CURSOR_CRTC.store(0, Ordering::SeqCst);
+                    return real_ioctl(fd, request, arg);
                 }
+
+                cursor_show_requested();
+                let handle = (*cursor).handle;
+                let offset = hotspot_offset();
+                if request == DRM_IOCTL_MODE_CURSOR2 {
+                    let hotspot = ((*cursor).hot_x, (*cursor).hot_y);
+                    compositor_shape_changed(handle, Some(hotspot), ShapeImage::None);
+                    (*cursor).hot_x = CURSOR_HOTSPOT_X.load(Ordering::SeqCst);
+                    (*cursor).hot_y = CURSOR_HOTSPOT_Y.load(Ordering::SeqCst);
+                } else {
+                    let image = ShapeImage::Handle {
+                        handle,
+                        width: (*cursor).width,
+                        height: (*cursor).height,
+                    };
+                    compositor_shape_changed(handle, None, image);
+                }
+
+                (*cursor).handle = CURSOR_HANDLE.load(Ordering::SeqCst);
+                STAT_FB_SUBSTITUTIONS.fetch_add(1, Ordering::Relaxed);
+                LEGACY_CURSOR_CRTC.store((*cursor).crtc_id, Ordering::SeqCst);
+                // Use display size, not buffer size (hardware may not support large cursors)
+                (*cursor).width = CURSOR_DISPLAY_SIZE;
+                (*cursor).height = CURSOR_DISPLAY_SIZE;
+
+                // A new shape with another hotspot shifts where our image
+                // belongs, even when the pointer stays put
+                if flags & DRM_MODE_CURSOR_MOVE == 0
+                    && COMPOSITOR_CURSOR_SEEN.load(Ordering::SeqCst)
+                    && hotspot_offset() != offset
+                {
+                    let (dx, dy) = hotspot_offset();
+                    (*cursor).flags |= DRM_MODE_CURSOR_MOVE;
+                    (*cursor).x = COMPOSITOR_CURSOR_X.load(Ordering::SeqCst) + dx;
+                    (*cursor).y = COMPOSITOR_CURSOR_Y.load(Ordering::SeqCst) + dy;
+                    CURSOR_SCREEN_X.store((*cursor).x, Ordering::SeqCst);
+                    CURSOR_SCREEN_Y.store((*cursor).y, Ordering::SeqCst);
+                }
+            }
+
+            if flags & DRM_MODE_CURSOR_MOVE != 0 {
+                let (x, y) = cursor_moved(fd, (*cursor).crtc_id, (*cursor).x, (*cursor).y);
+                (*cursor).x = x;
+                (*cursor).y = y;
             }
+
+            return real_ioctl(fd, request, arg);
+        }
+    }
+
+    // Not ours, its failures are the compositor's business
+    let ret = forward_ioctl(fd, request, arg);
+    if ret == 0 {
+        note_dumb_buffers(request, arg);
+        if request == DRM_IOCTL_MODE_RMFB {
+            forget_framebuffer(*(arg as *const u32));
+        }
+    }
+    ret
+}
+
+/// Hooked `drmModeSetCursor`, puts up our buffer instead of the compositor's.
+///
+/// # Safety
+/// Same as libdrm's, `fd` is an open DRM device.
+#[no_mangle]
+pub unsafe extern "C" fn drmModeSetCursor(
+    fd: i32,
+    crtc_id: u32,
+    bo_handle: u32,
+    width: u32,
+    height: u32,
+) -> i32 {
+    STAT_LEGACY_CURSOR_CALLS.fetch_add(1, Ordering::Relaxed);
+    note_hook(HookPath::SetCursor);
+    check_cursor_refresh();
+    // If compositor wants to hide cursor (handle = 0), allow it through
+    if bo_handle == 0 {
+        log!(Debug, Drm, "drmModeSetCursor: hiding cursor (handle=0)");
+        if INITIALIZED.load(Ordering::SeqCst) && cursor_hide_requested() {
+            return 0;
         }
+        LEGACY_CURSOR_CRTC.store(0, Ordering::SeqCst);
+        let cursor = DrmModeCursor2 {
+            flags: DRM_MODE_CURSOR_BO,
+            crtc_id,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            handle: 0,
+            hot_x: 0,
+            hot_y: 0,
+        };
+        return real_ioctl(
+            fd,
+            DRM_IOCTL_MODE_CURSOR2,
+            &cursor as *const _ as *mut c_void,
+        );
+    }
 
-        FADE_THREAD_RUNNING.store(false, Ordering::SeqCst);
-    });
-}
-
-fn spawn_fade_in_thread() {
-    if FADE_THREAD_RUNNING.swap(true, Ordering::SeqCst) {
-        return;
+    if !ensure_cursor_buffer(fd) {
+        let mut cursor = DrmModeCursor2 {
+            flags: DRM_MODE_CURSOR_BO,
+            crtc_id,
+            x: 0,
+            y: 0,
+            width,
+            height,
+            handle: bo_handle,
+            hot_x: 0,
+            hot_y: 0,
+        };
+        return pass_cursor_through(fd, DRM_IOCTL_MODE_CURSOR, &mut cursor);
     }
+    cursor_show_requested();
+    LEGACY_CURSOR_CRTC.store(crtc_id, Ordering::SeqCst);
+    // No hotspot given, it's only in the compositor's image
+    let image = ShapeImage::Handle {
+        handle: bo_handle,
+        width,
+        height,
+    };
+    compositor_shape_changed(bo_handle, None, image);
 
-    thread::spawn(move || {
-        let fade_speed = CONFIG_FADE_SPEED.load(Ordering::Relaxed) as f32;
-        let frame_time = Duration::from_millis(16); // set to a standard ~60fps
-        let step = fade_speed.max(5.0);
+    set_legacy_cursor(fd, crtc_id)
+}
 
-        let mut alpha = 0.0_f32;
+/// FaceSmack drmModeSetCursor2
+///
+/// # Safety
+/// Same as libdrm's, `fd` is an open DRM device.
+#[no_mangle]
+pub unsafe extern "C" fn drmModeSetCursor2(
+    fd: i32,
+    crtc_id: u32,
+    bo_handle: u32,
+    width: u32,
+    height: u32,
+    hot_x: i32,
+    hot_y: i32,
+) -> i32 {
+    STAT_LEGACY_CURSOR_CALLS.fetch_add(1, Ordering::Relaxed);
+    note_hook(HookPath::SetCursor2);
+    check_cursor_refresh();
+    // If compositor wants to hide cursor (handle = 0)
+    if bo_handle == 0 {
+        log!(Debug, Drm, "drmModeSetCursor2: hiding cursor (handle=0)");
 
-        while alpha < 255.0 {
-            if !CURSOR_FADING_IN.load(Ordering::SeqCst) {
-                break;
-            }
+        // Don't actually hide yet when fading, the scheduler hides it at the end
+        if INITIALIZED.load(Ordering::SeqCst) && cursor_hide_requested() {
+            return 0;
+        }
 
-            alpha = (alpha + step).min(255.0);
+        LEGACY_CURSOR_CRTC.store(0, Ordering::SeqCst);
+        let cursor = DrmModeCursor2 {
+            flags: DRM_MODE_CURSOR_BO,
+            crtc_id,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            handle: 0,
+            hot_x: 0,
+            hot_y: 0,
+        };
+        return real_ioctl(
+            fd,
+            DRM_IOCTL_MODE_CURSOR2,
+            &cursor as *const _ as *mut c_void,
+        );
+    }
 
-            unsafe {
-                if !CURSOR_BUFFER.is_null() {
-                    render_cursor();
-                    apply_cursor_fade(alpha);
-                }
-            }
+    if !ensure_cursor_buffer(fd) {
+        let mut cursor = DrmModeCursor2 {
+            flags: DRM_MODE_CURSOR_BO,
+            crtc_id,
+            x: 0,
+            y: 0,
+            width,
+            height,
+            handle: bo_handle,
+            hot_x,
+            hot_y,
+        };
+        return pass_cursor_through(fd, DRM_IOCTL_MODE_CURSOR2, &mut cursor);
+    }
+    cursor_show_requested();
+    LEGACY_CURSOR_CRTC.store(crtc_id, Ordering::SeqCst);
+    compositor_shape_changed(bo_handle, Some((hot_x, hot_y)), ShapeImage::None);
 
-            thread::sleep(frame_time);
-        }
+    set_legacy_cursor(fd, crtc_id)
+}
 
-        if CURSOR_FADING_IN.load(Ordering::SeqCst) {
-            CURSOR_FADING_IN.store(false, Ordering::SeqCst);
-            CURSOR_FADE_ALPHA.store(255, Ordering::SeqCst);
+/// We have no buffer, the compositor's cursor goes to the driver as it is
+unsafe fn pass_cursor_through(fd: i32, request: libc::c_ulong, cursor: &mut DrmModeCursor2) -> i32 {
+    STAT_PASSTHROUGH.fetch_add(1, Ordering::Relaxed);
+    log_limited!(
+        Debug,
+        Drm,
+        "No cursor buffer, passing the compositor's cursor through"
+    );
+    forward_ioctl(fd, request, cursor as *mut _ as *mut c_void)
+}
 
-            unsafe {
-                if !CURSOR_BUFFER.is_null() {
-                    render_cursor();
-                }
-            }
-        }
+/// Put our buffer up as the legacy cursor with our hotspot, moved along
+/// when the compositor's hotspot changed with its shape
+unsafe fn set_legacy_cursor(fd: i32, crtc_id: u32) -> i32 {
+    STAT_FB_SUBSTITUTIONS.fetch_add(1, Ordering::Relaxed);
+    let mut cursor = DrmModeCursor2 {
+        flags: DRM_MODE_CURSOR_BO,
+        crtc_id,
+        x: 0,
+        y: 0,
+        // Use display size, not buffer size (again, hardware may not support large cursors)
+        width: CURSOR_DISPLAY_SIZE,
+        height: CURSOR_DISPLAY_SIZE,
+        handle: CURSOR_HANDLE.load(Ordering::SeqCst),
+        hot_x: CURSOR_HOTSPOT_X.load(Ordering::SeqCst),
+        hot_y: CURSOR_HOTSPOT_Y.load(Ordering::SeqCst),
+    };
+    if COMPOSITOR_CURSOR_SEEN.load(Ordering::SeqCst) {
+        let (dx, dy) = hotspot_offset();
+        cursor.flags |= DRM_MODE_CURSOR_MOVE;
+        cursor.x = COMPOSITOR_CURSOR_X.load(Ordering::SeqCst) + dx;
+        cursor.y = COMPOSITOR_CURSOR_Y.load(Ordering::SeqCst) + dy;
+        CURSOR_SCREEN_X.store(cursor.x, Ordering::SeqCst);
+        CURSOR_SCREEN_Y.store(cursor.y, Ordering::SeqCst);
+    }
 
-        FADE_THREAD_RUNNING.store(false, Ordering::SeqCst);
-    });
+    real_ioctl(
+        fd,
+        DRM_IOCTL_MODE_CURSOR2,
+        &cursor as *const _ as *mut c_void,
+    )
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn drmModeMoveCursor(fd: i32, crtc_id: u32, x: i32, y: i32) -> i32 {
+/// The compositor moved the cursor to (x, y) on `crtc_id`, from a legacy
+/// move or an atomic CRTC_X/CRTC_Y update. Everything that depends on where
+/// the cursor is reads CURSOR_SCREEN_X/Y and CURSOR_CRTC_ID, updated here.
+/// Returns where our image goes, corrected for the hotspot difference.
+unsafe fn cursor_moved(fd: i32, crtc_id: u32, x: i32, y: i32) -> (i32, i32) {
+    refine_compositor_hotspot(x, y);
+    COMPOSITOR_CURSOR_X.store(x, Ordering::SeqCst);
+    COMPOSITOR_CURSOR_Y.store(y, Ordering::SeqCst);
+    COMPOSITOR_CURSOR_SEEN.store(true, Ordering::SeqCst);
+    COMPOSITOR_MOVE_MS.store(monotonic_ms(), Ordering::SeqCst);
+
+    let (dx, dy) = hotspot_offset();
+    let (x, y) = (x + dx, y + dy);
     CURSOR_SCREEN_X.store(x, Ordering::SeqCst);
     CURSOR_SCREEN_Y.store(y, Ordering::SeqCst);
+    if crtc_id != 0 {
+        CURSOR_CRTC_ID.store(crtc_id, Ordering::SeqCst);
+    }
 
     check_config_changed();
+    check_cursor_export();
+    note_cursor_moved();
 
-    if CURSOR_FADING_OUT.load(Ordering::SeqCst) {
-        let current_alpha = CURSOR_FADE_ALPHA.load(Ordering::SeqCst);
-
-        if current_alpha > 0 {
-            let fade_speed = CONFIG_FADE_SPEED.load(Ordering::Relaxed);
-            let new_alpha = current_alpha.saturating_sub(fade_speed);
-            CURSOR_FADE_ALPHA.store(new_alpha, Ordering::SeqCst);
+    if NUM_PRIMARY_PLANES == 0 && backdrop_wanted() {
+        refresh_primary_fb_from_crtc(fd, crtc_id);
+    }
 
-            render_cursor();
-            let fade_mult = new_alpha as f32 / 255.0;
-            apply_cursor_fade(fade_mult);
-
-            if new_alpha == 0 {
-                CURSOR_FADING_OUT.store(false, Ordering::SeqCst);
-                let cursor = DrmModeCursor2 {
-                    flags: DRM_MODE_CURSOR_BO,
-                    crtc_id,
-                    x: 0,
-                    y: 0,
-                    width: 0,
-                    height: 0,
-                    handle: 0,
-                    hot_x: 0,
-                    hot_y: 0,
-                };
-                return real_ioctl(
-                    fd,
-                    DRM_IOCTL_MODE_CURSOR2,
-                    &cursor as *const _ as *mut c_void,
-                );
-            }
+    // Frosted layers show what's under the cursor, which just changed,
+    // adaptive contrast only cares when the background flips light/dark
+    let backdrop_changed = update_backdrop_class();
+    if CURSOR_SAMPLES_BACKDROP.load(Ordering::SeqCst) || backdrop_changed {
+        render_cursor();
+    }
+    (x, y)
+}
 
-            // Buffer was modified and need to re-set the cursor BO, not just mov
-            // DRM_MODE_CURSOR_BO | DRM_MODE_CURSOR_MOVE combined sets and moves
-            let cursor = DrmModeCursor2 {
-                flags: DRM_MODE_CURSOR_BO | DRM_MODE_CURSOR_MOVE,
-                crtc_id,
-                x,
-                y,
-                width: CURSOR_DISPLAY_SIZE,
-                height: CURSOR_DISPLAY_SIZE,
-                handle: CURSOR_HANDLE.load(Ordering::SeqCst),
-                hot_x: APPLIED_HOTSPOT_X.load(Ordering::SeqCst),
-                hot_y: APPLIED_HOTSPOT_Y.load(Ordering::SeqCst),
-            };
-            return real_ioctl(
-                fd,
-                DRM_IOCTL_MODE_CURSOR2,
-                &cursor as *const _ as *mut c_void,
-            );
-        }
+/// Hooked `drmModeMoveCursor`, moves our image so the hotspots line up.
+///
+/// # Safety
+/// Same as libdrm's, `fd` is an open DRM device.
+#[no_mangle]
+pub unsafe extern "C" fn drmModeMoveCursor(fd: i32, crtc_id: u32, x: i32, y: i32) -> i32 {
+    STAT_LEGACY_CURSOR_CALLS.fetch_add(1, Ordering::Relaxed);
+    note_hook(HookPath::MoveCursor);
+    check_cursor_refresh();
+    if !INITIALIZED.load(Ordering::SeqCst) {
+        let mut cursor = DrmModeCursor2 {
+            flags: DRM_MODE_CURSOR_MOVE,
+            crtc_id,
+            x,
+            y,
+            width: 0,
+            height: 0,
+            handle: 0,
+            hot_x: 0,
+            hot_y: 0,
+        };
+        return pass_cursor_through(fd, DRM_IOCTL_MODE_CURSOR, &mut cursor);
     }
+    let (x, y) = cursor_moved(fd, crtc_id, x, y);
 
     let cursor = DrmModeCursor2 {
         flags: DRM_MODE_CURSOR_MOVE,
@@ -2457,6 +7903,37 @@ pub unsafe extern "C" fn drmModeMoveCursor(fd: i32, crtc_id: u32, x: i32, y: i32
 // track planes and filter their updates
 static mut CURSOR_PLANE_IDS: [u32; 8] = [0; 8];
 static mut NUM_CURSOR_PLANES: usize = 0;
+static mut CURSOR_CRTC_PROP_IDS: [u32; 8] = [0; 8];
+
+// CRTC the cursor plane was last put on, 0 until we've seen one
+static CURSOR_CRTC_ID: AtomicU32 = AtomicU32::new(0);
+
+// Where each cursor plane is (CRTC_X/CRTC_Y) and the CRTC it's on (0 = off)
+static mut CURSOR_PLANE_POSITIONS: [(i32, i32); 8] = [(0, 0); 8];
+static mut CURSOR_PLANE_CRTCS: [u32; 8] = [0; 8];
+
+/// A primary plane and what the compositor last attached to it
+#[derive(Clone, Copy)]
+struct PrimaryPlane {
+    plane_id: u32,
+    fb_prop_id: u32,
+    crtc_prop_id: u32,
+    fb_id: u32,
+    crtc_id: u32,
+}
+
+impl PrimaryPlane {
+    const EMPTY: PrimaryPlane = PrimaryPlane {
+        plane_id: 0,
+        fb_prop_id: 0,
+        crtc_prop_id: 0,
+        fb_id: 0,
+        crtc_id: 0,
+    };
+}
+
+static mut PRIMARY_PLANES: [PrimaryPlane; 8] = [PrimaryPlane::EMPTY; 8];
+static mut NUM_PRIMARY_PLANES: usize = 0;
 
 // Real function pointers for atomic stuff, I promise
 static mut REAL_GET_PLANE: Option<unsafe extern "C" fn(i32, u32) -> *mut DrmModePlane> = None;
@@ -2474,7 +7951,7 @@ static mut REAL_ATOMIC_ADD: Option<unsafe extern "C" fn(*mut c_void, u32, u32, u
 const DRM_MODE_OBJECT_PLANE: u32 = 0xeeeeeeee;
 
 #[repr(C)]
-struct DrmModePlane {
+pub struct DrmModePlane {
     count_formats: u32,
     formats: *mut u32,
     plane_id: u32,
@@ -2495,6 +7972,12 @@ struct DrmModeObjectProperties {
     prop_values: *mut u64,
 }
 
+#[repr(C)]
+struct DrmModePropertyEnum {
+    value: u64,
+    name: [i8; 32],
+}
+
 #[repr(C)]
 struct DrmModePropertyRes {
     prop_id: u32,
@@ -2509,79 +7992,20 @@ struct DrmModePropertyRes {
 }
 
 unsafe fn init_plane_functions() {
-    if REAL_GET_PLANE.is_none() {
-        let sym = libc::dlsym(libc::RTLD_NEXT, b"drmModeGetPlane\0".as_ptr() as *const i8);
-        if !sym.is_null() {
-            REAL_GET_PLANE = Some(std::mem::transmute(sym));
-        }
-    }
-    if REAL_GET_OBJECT_PROPERTIES.is_none() {
-        let sym = libc::dlsym(
-            libc::RTLD_NEXT,
-            b"drmModeObjectGetProperties\0".as_ptr() as *const i8,
-        );
-        if !sym.is_null() {
-            REAL_GET_OBJECT_PROPERTIES = Some(std::mem::transmute(sym));
-        }
-    }
-    if REAL_GET_PROPERTY.is_none() {
-        let sym = libc::dlsym(
-            libc::RTLD_NEXT,
-            b"drmModeGetProperty\0".as_ptr() as *const i8,
-        );
-        if !sym.is_null() {
-            REAL_GET_PROPERTY = Some(std::mem::transmute(sym));
-        }
-    }
-    if REAL_FREE_PLANE.is_none() {
-        let sym = libc::dlsym(libc::RTLD_NEXT, b"drmModeFreePlane\0".as_ptr() as *const i8);
-        if !sym.is_null() {
-            REAL_FREE_PLANE = Some(std::mem::transmute(sym));
-        }
-    }
-    if REAL_FREE_OBJECT_PROPERTIES.is_none() {
-        let sym = libc::dlsym(
-            libc::RTLD_NEXT,
-            b"drmModeFreeObjectProperties\0".as_ptr() as *const i8,
-        );
-        if !sym.is_null() {
-            REAL_FREE_OBJECT_PROPERTIES = Some(std::mem::transmute(sym));
-        }
-    }
-    if REAL_FREE_PROPERTY.is_none() {
-        let sym = libc::dlsym(
-            libc::RTLD_NEXT,
-            b"drmModeFreeProperty\0".as_ptr() as *const i8,
-        );
-        if !sym.is_null() {
-            REAL_FREE_PROPERTY = Some(std::mem::transmute(sym));
-        }
-    }
-    if REAL_ATOMIC_ADD.is_none() {
-        let sym = libc::dlsym(
-            libc::RTLD_NEXT,
-            b"drmModeAtomicAddProperty\0".as_ptr() as *const i8,
-        );
-        if !sym.is_null() {
-            REAL_ATOMIC_ADD = Some(std::mem::transmute(sym));
-        }
-    }
-}
-
-unsafe fn is_cursor_plane(plane_id: u32) -> bool {
-    for i in 0..NUM_CURSOR_PLANES {
-        if CURSOR_PLANE_IDS[i] == plane_id {
-            return true;
-        }
-    }
-    false
+    REAL_GET_PLANE = REAL_GET_PLANE.or_else(|| next_symbol(c"drmModeGetPlane"));
+    REAL_GET_OBJECT_PROPERTIES =
+        REAL_GET_OBJECT_PROPERTIES.or_else(|| next_symbol(c"drmModeObjectGetProperties"));
+    REAL_GET_PROPERTY = REAL_GET_PROPERTY.or_else(|| next_symbol(c"drmModeGetProperty"));
+    REAL_FREE_PLANE = REAL_FREE_PLANE.or_else(|| next_symbol(c"drmModeFreePlane"));
+    REAL_FREE_OBJECT_PROPERTIES =
+        REAL_FREE_OBJECT_PROPERTIES.or_else(|| next_symbol(c"drmModeFreeObjectProperties"));
+    REAL_FREE_PROPERTY = REAL_FREE_PROPERTY.or_else(|| next_symbol(c"drmModeFreeProperty"));
+    REAL_ATOMIC_ADD = REAL_ATOMIC_ADD.or_else(|| next_symbol(c"drmModeAtomicAddProperty"));
 }
 
 unsafe fn register_cursor_plane(plane_id: u32) -> usize {
-    for i in 0..NUM_CURSOR_PLANES {
-        if CURSOR_PLANE_IDS[i] == plane_id {
-            return i;
-        }
+    if let Some(idx) = get_cursor_plane_index(plane_id) {
+        return idx;
     }
     if NUM_CURSOR_PLANES < 8 {
         let idx = NUM_CURSOR_PLANES;
@@ -2593,20 +8017,91 @@ unsafe fn register_cursor_plane(plane_id: u32) -> usize {
 }
 
 unsafe fn get_cursor_plane_index(plane_id: u32) -> Option<usize> {
-    for i in 0..NUM_CURSOR_PLANES {
-        if CURSOR_PLANE_IDS[i] == plane_id {
-            return Some(i);
+    CURSOR_PLANE_IDS[..NUM_CURSOR_PLANES]
+        .iter()
+        .position(|&id| id == plane_id)
+}
+
+/// Atomic counterpart of drmModeMoveCursor, once per commit. Compositors
+/// resend the position with every cursor commit, only a changed one is a move.
+unsafe fn move_cursor_plane(idx: usize, x: i32, y: i32) {
+    if CURSOR_PLANE_POSITIONS[idx] == (x, y) {
+        return;
+    }
+    CURSOR_PLANE_POSITIONS[idx] = (x, y);
+
+    let crtc_id = CURSOR_PLANE_CRTCS[idx];
+    if crtc_id != 0 && CURSOR_CRTC_ID.swap(crtc_id, Ordering::SeqCst) != crtc_id {
+        update_primary_fb();
+    }
+    cursor_moved(CURSOR_FD.load(Ordering::SeqCst), crtc_id, x, y);
+}
+
+unsafe fn get_primary_plane_index(plane_id: u32) -> Option<usize> {
+    PRIMARY_PLANES[..NUM_PRIMARY_PLANES]
+        .iter()
+        .position(|p| p.plane_id == plane_id)
+}
+
+unsafe fn register_primary_plane(plane: PrimaryPlane) {
+    let idx = match get_primary_plane_index(plane.plane_id) {
+        Some(idx) => idx,
+        None if NUM_PRIMARY_PLANES < 8 => {
+            NUM_PRIMARY_PLANES += 1;
+            NUM_PRIMARY_PLANES - 1
         }
+        None => return,
+    };
+    PRIMARY_PLANES[idx] = plane;
+    log!(
+        Debug,
+        Drm,
+        "Primary plane {} on CRTC {} with FB {}",
+        plane.plane_id,
+        plane.crtc_id,
+        plane.fb_id
+    );
+    update_primary_fb();
+}
+
+/// Follow FB_ID/CRTC_ID changes on a primary plane
+unsafe fn track_primary_property(idx: usize, property_id: u32, value: u64) {
+    let plane = &mut PRIMARY_PLANES[idx];
+    if plane.fb_prop_id != 0 && property_id == plane.fb_prop_id {
+        plane.fb_id = value as u32;
+    } else if plane.crtc_prop_id != 0 && property_id == plane.crtc_prop_id {
+        plane.crtc_id = value as u32;
+    } else {
+        return;
     }
-    None
+    update_primary_fb();
+}
+
+/// Point PRIMARY_FB_ID at the primary plane sharing the cursor's CRTC,
+/// or the first one with a framebuffer while the cursor CRTC is unknown
+unsafe fn update_primary_fb() {
+    let cursor_crtc = CURSOR_CRTC_ID.load(Ordering::SeqCst);
+    let fb_id = PRIMARY_PLANES[..NUM_PRIMARY_PLANES]
+        .iter()
+        .find(|p| p.fb_id != 0 && (cursor_crtc == 0 || p.crtc_id == cursor_crtc))
+        .map(|p| p.fb_id)
+        .unwrap_or(0);
+    PRIMARY_FB_ID.store(fb_id, Ordering::SeqCst);
 }
 
+/// Hooked `drmModeGetPlane`, finds cursor and primary planes when the
+/// compositor looks them up.
+///
+/// # Safety
+/// Same as libdrm's, the result is freed with `drmModeFreePlane`.
 #[no_mangle]
 pub unsafe extern "C" fn drmModeGetPlane(fd: i32, plane_id: u32) -> *mut DrmModePlane {
     init_plane_functions();
+    note_hook(HookPath::GetPlane);
 
     if CURSOR_FD.load(Ordering::SeqCst) < 0 {
         CURSOR_FD.store(fd, Ordering::SeqCst);
+        start_doctor();
     }
 
     let plane = match REAL_GET_PLANE {
@@ -2618,185 +8113,240 @@ pub unsafe extern "C" fn drmModeGetPlane(fd: i32, plane_id: u32) -> *mut DrmMode
         return plane;
     }
 
-    if let Some(get_props) = REAL_GET_OBJECT_PROPERTIES {
-        let props = get_props(fd, plane_id, DRM_MODE_OBJECT_PLANE);
-        if !props.is_null() {
-            let count = (*props).count_props as usize;
-            let mut is_cursor = false;
-            let mut fb_id_prop = 0u32;
-            let mut src_w_prop = 0u32;
-            let mut src_h_prop = 0u32;
-            let mut crtc_w_prop = 0u32;
-            let mut crtc_h_prop = 0u32;
-
-            for i in 0..count {
-                let prop_id = *(*props).props.add(i);
-                let prop_value = *(*props).prop_values.add(i);
-
-                if let Some(get_prop) = REAL_GET_PROPERTY {
-                    let prop = get_prop(fd, prop_id);
-                    if !prop.is_null() {
-                        let name_ptr = (*prop).name.as_ptr();
-
-                        // Check if property name is "type"
-                        if libc::strcmp(name_ptr, b"type\0".as_ptr() as *const i8) == 0 {
-                            if prop_value == DRM_PLANE_TYPE_CURSOR {
-                                is_cursor = true;
-                            }
-                        }
+    scan_plane_properties(fd, plane_id);
 
-                        if libc::strcmp(name_ptr, b"FB_ID\0".as_ptr() as *const i8) == 0 {
-                            fb_id_prop = prop_id;
-                        }
-                        if libc::strcmp(name_ptr, b"SRC_W\0".as_ptr() as *const i8) == 0 {
-                            src_w_prop = prop_id;
-                        }
-                        if libc::strcmp(name_ptr, b"SRC_H\0".as_ptr() as *const i8) == 0 {
-                            src_h_prop = prop_id;
-                        }
-                        if libc::strcmp(name_ptr, b"CRTC_W\0".as_ptr() as *const i8) == 0 {
-                            crtc_w_prop = prop_id;
-                        }
-                        if libc::strcmp(name_ptr, b"CRTC_H\0".as_ptr() as *const i8) == 0 {
-                            crtc_h_prop = prop_id;
-                        }
+    plane
+}
 
-                        if let Some(free_prop) = REAL_FREE_PROPERTY {
-                            free_prop(prop);
-                        }
-                    }
+/// Try
+unsafe fn try_detect_cursor_plane(object_id: u32) -> bool {
+    let fd = CURSOR_FD.load(Ordering::SeqCst);
+    if fd < 0 {
+        return false;
+    }
+
+    // Hmmm, Already known cursor plane?
+    if get_cursor_plane_index(object_id).is_some() {
+        return true;
+    }
+
+    // let's see if it's a cursor plane
+    let is_cursor = scan_plane_properties(fd, object_id);
+    if is_cursor {
+        if let Some(idx) = get_cursor_plane_index(object_id) {
+            log!(
+                Info,
+                Drm,
+                "Detected cursor plane {} with FB_ID prop {}",
+                object_id,
+                CURSOR_FB_PROP_IDS[idx]
+            );
+        }
+    }
+    is_cursor
+}
+
+/// Read a plane's properties, register it when its type is cursor and
+/// remember the IDs of the properties we rewrite or watch.
+/// Returns whether the plane is a cursor plane.
+unsafe fn scan_plane_properties(fd: i32, plane_id: u32) -> bool {
+    let Some(get_props) = REAL_GET_OBJECT_PROPERTIES else {
+        return false;
+    };
+    let props = get_props(fd, plane_id, DRM_MODE_OBJECT_PLANE);
+    if props.is_null() {
+        return false;
+    }
+
+    let count = (*props).count_props as usize;
+    let mut is_cursor = false;
+    let mut is_primary = false;
+    let mut fb_id_prop = 0u32;
+    let mut fb_id = 0u32;
+    let mut crtc_id_prop = 0u32;
+    let mut crtc_id = 0u32;
+    let mut src_w_prop = 0u32;
+    let mut src_h_prop = 0u32;
+    let mut crtc_w_prop = 0u32;
+    let mut crtc_h_prop = 0u32;
+    let mut crtc_x_prop = 0u32;
+    let mut crtc_y_prop = 0u32;
+    let mut position = (0i32, 0i32);
+    let mut hotspot_x_prop = 0u32;
+    let mut hotspot_y_prop = 0u32;
+    let mut blend_prop = 0u32;
+    let mut blend_mode = None;
+
+    for i in 0..count {
+        let prop_id = *(*props).props.add(i);
+        let prop_value = *(*props).prop_values.add(i);
+
+        if let Some(get_prop) = REAL_GET_PROPERTY {
+            let prop = get_prop(fd, prop_id);
+            if !prop.is_null() {
+                let name_ptr = (*prop).name.as_ptr();
+
+                // Check if property name is "type"
+                if libc::strcmp(name_ptr, c"type".as_ptr()) == 0
+                    && prop_value == DRM_PLANE_TYPE_CURSOR
+                {
+                    is_cursor = true;
+                }
+                if libc::strcmp(name_ptr, c"type".as_ptr()) == 0
+                    && prop_value == DRM_PLANE_TYPE_PRIMARY
+                {
+                    is_primary = true;
                 }
-            }
 
-            if is_cursor {
-                let idx = register_cursor_plane(plane_id);
-                if idx < 8 {
-                    if fb_id_prop != 0 {
-                        CURSOR_FB_PROP_IDS[idx] = fb_id_prop;
-                    }
-                    if src_w_prop != 0 {
-                        CURSOR_SRC_W_PROP_IDS[idx] = src_w_prop;
-                    }
-                    if src_h_prop != 0 {
-                        CURSOR_SRC_H_PROP_IDS[idx] = src_h_prop;
-                    }
-                    if crtc_w_prop != 0 {
-                        CURSOR_CRTC_W_PROP_IDS[idx] = crtc_w_prop;
-                    }
-                    if crtc_h_prop != 0 {
-                        CURSOR_CRTC_H_PROP_IDS[idx] = crtc_h_prop;
-                    }
+                if libc::strcmp(name_ptr, c"FB_ID".as_ptr()) == 0 {
+                    fb_id_prop = prop_id;
+                    fb_id = prop_value as u32;
+                }
+                if libc::strcmp(name_ptr, c"CRTC_ID".as_ptr()) == 0 {
+                    crtc_id_prop = prop_id;
+                    crtc_id = prop_value as u32;
+                }
+                if libc::strcmp(name_ptr, c"SRC_W".as_ptr()) == 0 {
+                    src_w_prop = prop_id;
+                }
+                if libc::strcmp(name_ptr, c"SRC_H".as_ptr()) == 0 {
+                    src_h_prop = prop_id;
+                }
+                if libc::strcmp(name_ptr, c"CRTC_W".as_ptr()) == 0 {
+                    crtc_w_prop = prop_id;
+                }
+                if libc::strcmp(name_ptr, c"CRTC_H".as_ptr()) == 0 {
+                    crtc_h_prop = prop_id;
+                }
+                if libc::strcmp(name_ptr, c"CRTC_X".as_ptr()) == 0 {
+                    crtc_x_prop = prop_id;
+                    position.0 = prop_value as i32;
+                }
+                if libc::strcmp(name_ptr, c"CRTC_Y".as_ptr()) == 0 {
+                    crtc_y_prop = prop_id;
+                    position.1 = prop_value as i32;
+                }
+                if libc::strcmp(name_ptr, c"HOTSPOT_X".as_ptr()) == 0 {
+                    hotspot_x_prop = prop_id;
+                }
+                if libc::strcmp(name_ptr, c"HOTSPOT_Y".as_ptr()) == 0 {
+                    hotspot_y_prop = prop_id;
+                }
+                if libc::strcmp(name_ptr, c"pixel blend mode".as_ptr()) == 0 {
+                    blend_prop = prop_id;
+                    record_blend_mode_enums(prop);
+                    blend_mode = blend_mode_for_value(prop_value);
                 }
-            }
 
-            if let Some(free_props) = REAL_FREE_OBJECT_PROPERTIES {
-                free_props(props);
+                if let Some(free_prop) = REAL_FREE_PROPERTY {
+                    free_prop(prop);
+                }
             }
         }
     }
 
-    plane
-}
-
-/// Try
-unsafe fn try_detect_cursor_plane(object_id: u32) -> bool {
-    let fd = CURSOR_FD.load(Ordering::SeqCst);
-    if fd < 0 {
-        return false;
+    if is_cursor {
+        let idx = register_cursor_plane(plane_id);
+        if idx < 8 {
+            if fb_id_prop != 0 {
+                CURSOR_FB_PROP_IDS[idx] = fb_id_prop;
+            }
+            if src_w_prop != 0 {
+                CURSOR_SRC_W_PROP_IDS[idx] = src_w_prop;
+            }
+            if src_h_prop != 0 {
+                CURSOR_SRC_H_PROP_IDS[idx] = src_h_prop;
+            }
+            if crtc_w_prop != 0 {
+                CURSOR_CRTC_W_PROP_IDS[idx] = crtc_w_prop;
+            }
+            if crtc_h_prop != 0 {
+                CURSOR_CRTC_H_PROP_IDS[idx] = crtc_h_prop;
+            }
+            if crtc_x_prop != 0 {
+                CURSOR_CRTC_X_PROP_IDS[idx] = crtc_x_prop;
+            }
+            if crtc_y_prop != 0 {
+                CURSOR_CRTC_Y_PROP_IDS[idx] = crtc_y_prop;
+            }
+            if hotspot_x_prop != 0 {
+                CURSOR_HOTSPOT_X_PROP_IDS[idx] = hotspot_x_prop;
+            }
+            if hotspot_y_prop != 0 {
+                CURSOR_HOTSPOT_Y_PROP_IDS[idx] = hotspot_y_prop;
+            }
+            if blend_prop != 0 {
+                CURSOR_BLEND_PROP_IDS[idx] = blend_prop;
+            }
+            if crtc_id_prop != 0 {
+                CURSOR_CRTC_PROP_IDS[idx] = crtc_id_prop;
+            }
+            CURSOR_PLANE_POSITIONS[idx] = position;
+            CURSOR_PLANE_CRTCS[idx] = crtc_id;
+        }
+        if let Some(mode) = blend_mode {
+            set_cursor_blend_mode(mode);
+        }
+        if crtc_id != 0 && CURSOR_CRTC_ID.load(Ordering::SeqCst) == 0 {
+            CURSOR_CRTC_ID.store(crtc_id, Ordering::SeqCst);
+        }
     }
 
-    // Hmmm, Already known cursor plane?
-    if get_cursor_plane_index(object_id).is_some() {
-        return true;
+    if is_primary {
+        register_primary_plane(PrimaryPlane {
+            plane_id,
+            fb_prop_id: fb_id_prop,
+            crtc_prop_id: crtc_id_prop,
+            fb_id,
+            crtc_id,
+        });
     }
 
-    // let's see if it's a cursor plane
-    if let Some(get_props) = REAL_GET_OBJECT_PROPERTIES {
-        let props = get_props(fd, object_id, DRM_MODE_OBJECT_PLANE);
-        if !props.is_null() {
-            let count = (*props).count_props as usize;
-            let mut is_cursor = false;
-            let mut fb_id_prop = 0u32;
-            let mut src_w_prop = 0u32;
-            let mut src_h_prop = 0u32;
-            let mut crtc_w_prop = 0u32;
-            let mut crtc_h_prop = 0u32;
-
-            for i in 0..count {
-                let prop_id = *(*props).props.add(i);
-                let prop_value = *(*props).prop_values.add(i);
-
-                if let Some(get_prop) = REAL_GET_PROPERTY {
-                    let prop = get_prop(fd, prop_id);
-                    if !prop.is_null() {
-                        let name_ptr = (*prop).name.as_ptr();
-
-                        if libc::strcmp(name_ptr, b"type\0".as_ptr() as *const i8) == 0 {
-                            if prop_value == DRM_PLANE_TYPE_CURSOR {
-                                is_cursor = true;
-                            }
-                        }
-
-                        if libc::strcmp(name_ptr, b"FB_ID\0".as_ptr() as *const i8) == 0 {
-                            fb_id_prop = prop_id;
-                        }
-                        if libc::strcmp(name_ptr, b"SRC_W\0".as_ptr() as *const i8) == 0 {
-                            src_w_prop = prop_id;
-                        }
-                        if libc::strcmp(name_ptr, b"SRC_H\0".as_ptr() as *const i8) == 0 {
-                            src_h_prop = prop_id;
-                        }
-                        if libc::strcmp(name_ptr, b"CRTC_W\0".as_ptr() as *const i8) == 0 {
-                            crtc_w_prop = prop_id;
-                        }
-                        if libc::strcmp(name_ptr, b"CRTC_H\0".as_ptr() as *const i8) == 0 {
-                            crtc_h_prop = prop_id;
-                        }
+    if let Some(free_props) = REAL_FREE_OBJECT_PROPERTIES {
+        free_props(props);
+    }
 
-                        if let Some(free_prop) = REAL_FREE_PROPERTY {
-                            free_prop(prop);
-                        }
-                    }
-                }
-            }
+    is_cursor
+}
 
-            if is_cursor {
-                debug_print!(
-                    "Detected cursor plane {} with FB_ID prop {}",
-                    object_id,
-                    fb_id_prop
-                );
-                let idx = register_cursor_plane(object_id);
-                if idx < 8 {
-                    if fb_id_prop != 0 {
-                        CURSOR_FB_PROP_IDS[idx] = fb_id_prop;
-                    }
-                    if src_w_prop != 0 {
-                        CURSOR_SRC_W_PROP_IDS[idx] = src_w_prop;
-                    }
-                    if src_h_prop != 0 {
-                        CURSOR_SRC_H_PROP_IDS[idx] = src_h_prop;
-                    }
-                    if crtc_w_prop != 0 {
-                        CURSOR_CRTC_W_PROP_IDS[idx] = crtc_w_prop;
-                    }
-                    if crtc_h_prop != 0 {
-                        CURSOR_CRTC_H_PROP_IDS[idx] = crtc_h_prop;
-                    }
-                }
-            }
+/// Remember which enum value the driver uses for each blend mode name
+unsafe fn record_blend_mode_enums(prop: *mut DrmModePropertyRes) {
+    let enums = (*prop).enums as *const DrmModePropertyEnum;
+    if enums.is_null() {
+        return;
+    }
+    for i in 0..(*prop).count_enums as usize {
+        let entry = &*enums.add(i);
+        let name = std::ffi::CStr::from_ptr(entry.name.as_ptr());
+        if let Some(mode) = PixelBlendMode::from_name(name.to_bytes()) {
+            BLEND_MODE_ENUM_VALUES[mode as usize] = Some(entry.value);
+        }
+    }
+}
 
-            if let Some(free_props) = REAL_FREE_OBJECT_PROPERTIES {
-                free_props(props);
-            }
+unsafe fn blend_mode_for_value(value: u64) -> Option<PixelBlendMode> {
+    let values = BLEND_MODE_ENUM_VALUES;
+    values
+        .iter()
+        .position(|v| *v == Some(value))
+        .map(|i| PixelBlendMode::from_u32(i as u32))
+}
 
-            return is_cursor;
+/// Switch pixel encoding, re-rendering when the plane's blend mode changed
+unsafe fn set_cursor_blend_mode(mode: PixelBlendMode) {
+    let previous = CURSOR_BLEND_MODE.swap(mode as u32, Ordering::SeqCst);
+    if previous != mode as u32 {
+        log!(Info, Drm, "Cursor plane pixel blend mode: {:?}", mode);
+        if INITIALIZED.load(Ordering::SeqCst) && !CURSOR_BUFFER.is_null() {
+            render_cursor();
         }
     }
-
-    false
 }
 
+/// Hooked `drmModeAtomicAddProperty`, swaps in our values for cursor planes.
+///
+/// # Safety
+/// Same as libdrm's, `req` comes from `drmModeAtomicAlloc`.
 #[no_mangle]
 pub unsafe extern "C" fn drmModeAtomicAddProperty(
     req: *mut c_void,
@@ -2805,37 +8355,49 @@ pub unsafe extern "C" fn drmModeAtomicAddProperty(
     value: u64,
 ) -> i32 {
     init_plane_functions();
+    STAT_ATOMIC_PROPERTIES.fetch_add(1, Ordering::Relaxed);
+    note_hook(HookPath::AtomicAddProperty);
 
     check_cursor_refresh();
 
     check_config_changed();
+    check_status_request();
+
+    if let Some(idx) = get_primary_plane_index(object_id) {
+        track_primary_property(idx, property_id, value);
+        return pass_property(req, object_id, property_id, value);
+    }
 
     let is_cursor =
         get_cursor_plane_index(object_id).is_some() || try_detect_cursor_plane(object_id);
 
+    // Detection may have just found a primary plane
+    if let Some(idx) = get_primary_plane_index(object_id) {
+        track_primary_property(idx, property_id, value);
+    }
+
     if is_cursor {
-        debug_print!(
+        STAT_CURSOR_PROPERTIES.fetch_add(1, Ordering::Relaxed);
+        note_hook(HookPath::AtomicCursorPlane);
+        log_limited!(
+            Trace,
+            Drm,
             "Cursor plane {} property {} = {}",
             object_id,
             property_id,
             value
         );
 
-        if !INITIALIZED.load(Ordering::SeqCst) {
-            let fd = CURSOR_FD.load(Ordering::SeqCst);
-            if fd >= 0 {
-                debug_print!("Creating cursor buffer on fd {}", fd);
-                if create_cursor_buffer(fd, 256, 256) {
-                    debug_print!(
-                        "Cursor buffer created, FB_ID={}",
-                        CURSOR_FB_ID.load(Ordering::SeqCst)
-                    );
-                } else {
-                    debug_print!("Failed to create cursor buffer!");
-                }
-            } else {
-                debug_print!("No DRM fd captured yet!");
-            }
+        let fd = CURSOR_FD.load(Ordering::SeqCst);
+        if fd < 0 {
+            log_limited!(Warn, Drm, "No DRM fd captured yet!");
+        }
+        let given_up =
+            get_cursor_plane_index(object_id).is_some_and(|idx| CURSOR_PLANE_GIVEN_UP[idx]);
+        if given_up || fd < 0 || !ensure_cursor_buffer(fd) {
+            // Their FB with their sizes and hotspot, nothing of ours
+            STAT_PASSTHROUGH.fetch_add(1, Ordering::Relaxed);
+            return pass_property(req, object_id, property_id, value);
         }
 
         if let Some(idx) = get_cursor_plane_index(object_id) {
@@ -2844,95 +8406,662 @@ pub unsafe extern "C" fn drmModeAtomicAddProperty(
             let src_h_prop_id = CURSOR_SRC_H_PROP_IDS[idx];
             let crtc_w_prop_id = CURSOR_CRTC_W_PROP_IDS[idx];
             let crtc_h_prop_id = CURSOR_CRTC_H_PROP_IDS[idx];
+            let blend_prop_id = CURSOR_BLEND_PROP_IDS[idx];
+            let crtc_prop_id = CURSOR_CRTC_PROP_IDS[idx];
+            let crtc_x_prop_id = CURSOR_CRTC_X_PROP_IDS[idx];
+            let crtc_y_prop_id = CURSOR_CRTC_Y_PROP_IDS[idx];
+            let hotspot_x_prop_id = CURSOR_HOTSPOT_X_PROP_IDS[idx];
+            let hotspot_y_prop_id = CURSOR_HOTSPOT_Y_PROP_IDS[idx];
+
+            // The compositor tells the driver its hotspot, we learn it and
+            // hand the driver ours instead
+            let hotspot_x = hotspot_x_prop_id != 0 && property_id == hotspot_x_prop_id;
+            let hotspot_y = hotspot_y_prop_id != 0 && property_id == hotspot_y_prop_id;
+            if hotspot_x || hotspot_y {
+                let ours = if hotspot_x {
+                    CURSOR_HOTSPOT_X.load(Ordering::SeqCst)
+                } else {
+                    CURSOR_HOTSPOT_Y.load(Ordering::SeqCst)
+                };
+                let ret =
+                    substitute_property(req, object_id, property_id, value, ours as i64 as u64);
+                learn_request_hotspot(req, idx, false);
+                return ret;
+            }
+
+            // Positions are the pointer minus the compositor's hotspot,
+            // shift them so our hotspot lands on the pointer instead
+            // The move itself happens when the request is committed
+            if crtc_x_prop_id != 0 && property_id == crtc_x_prop_id {
+                let (dx, _) = hotspot_offset();
+                if dx != 0 {
+                    let x = (value as i32 + dx) as i64 as u64;
+                    return substitute_property(req, object_id, property_id, value, x);
+                }
+            }
+            if crtc_y_prop_id != 0 && property_id == crtc_y_prop_id {
+                let (_, dy) = hotspot_offset();
+                if dy != 0 {
+                    let y = (value as i32 + dy) as i64 as u64;
+                    return substitute_property(req, object_id, property_id, value, y);
+                }
+            }
+
+            if crtc_prop_id != 0 && property_id == crtc_prop_id {
+                CURSOR_PLANE_CRTCS[idx] = value as u32;
+                if value != 0 {
+                    CURSOR_CRTC_ID.store(value as u32, Ordering::SeqCst);
+                    update_primary_fb();
+                }
+            }
+
+            // The compositor picks the blend mode, we only follow it
+            if blend_prop_id != 0 && property_id == blend_prop_id {
+                if let Some(mode) = blend_mode_for_value(value) {
+                    set_cursor_blend_mode(mode);
+                }
+            }
 
             if fb_prop_id != 0 && property_id == fb_prop_id {
                 // If compositor wants to hide cursor (FB_ID = 0)
                 if value == 0 {
-                    CURSOR_FADING_IN.store(false, Ordering::SeqCst);
-                    CURSOR_VISIBLE.store(false, Ordering::SeqCst);
-
-                    if cursor_fade_enabled() && !CURSOR_FADING_OUT.load(Ordering::SeqCst) {
-                        CURSOR_FADING_OUT.store(true, Ordering::SeqCst);
-                        spawn_fade_out_thread();
-
+                    if cursor_hide_requested() {
                         // Tell compositor "ok" but keep showing our cursor for the fade effect
                         let our_fb = CURSOR_FB_ID.load(Ordering::SeqCst);
                         if our_fb != 0 {
-                            if let Some(func) = REAL_ATOMIC_ADD {
-                                return func(req, object_id, property_id, our_fb as u64);
-                            }
+                            return substitute_property(
+                                req,
+                                object_id,
+                                property_id,
+                                value,
+                                our_fb as u64,
+                            );
                         }
                     }
 
-                    if let Some(func) = REAL_ATOMIC_ADD {
-                        return func(req, object_id, property_id, 0);
-                    }
+                    return pass_property(req, object_id, property_id, 0);
                 }
 
-                CURSOR_FADING_OUT.store(false, Ordering::SeqCst);
-                CURSOR_VISIBLE.store(true, Ordering::SeqCst);
-
-                if CONFIG_FADE_IN_ENABLED.load(Ordering::Relaxed)
-                    && !CURSOR_FADING_IN.load(Ordering::SeqCst)
-                    && CURSOR_FADE_ALPHA.load(Ordering::SeqCst) < 255
-                {
-                    CURSOR_FADING_IN.store(true, Ordering::SeqCst);
-                    CURSOR_FADE_ALPHA.store(10, Ordering::SeqCst);
-                    spawn_fade_in_thread();
-                } else {
-                    CURSOR_FADE_ALPHA.store(255, Ordering::SeqCst);
-                }
+                cursor_show_requested();
+                let fb_id = value as u32;
+                compositor_shape_changed(fb_id, None, ShapeImage::Framebuffer(fb_id));
 
                 let our_fb = CURSOR_FB_ID.load(Ordering::SeqCst);
                 if our_fb != 0 {
-                    debug_print!("Replacing FB_ID {} with our FB_ID {}", value, our_fb);
-                    if let Some(func) = REAL_ATOMIC_ADD {
-                        return func(req, object_id, property_id, our_fb as u64);
-                    }
+                    log_limited!(
+                        Trace,
+                        Drm,
+                        "Replacing FB_ID {} with our FB_ID {}",
+                        value,
+                        our_fb
+                    );
+                    STAT_FB_SUBSTITUTIONS.fetch_add(1, Ordering::Relaxed);
+                    return substitute_property(req, object_id, property_id, value, our_fb as u64);
                 } else {
-                    debug_print!("FB_ID property matched but our FB_ID is 0!");
+                    log_limited!(Warn, Drm, "FB_ID property matched but our FB_ID is 0!");
                 }
             }
 
             if src_w_prop_id != 0 && property_id == src_w_prop_id {
                 let our_src_w = (CURSOR_DISPLAY_SIZE as u64) << 16;
-                debug_print!("Overriding SRC_W {} with {}", value, our_src_w);
-                if let Some(func) = REAL_ATOMIC_ADD {
-                    return func(req, object_id, property_id, our_src_w);
-                }
+                log_limited!(Trace, Drm, "Overriding SRC_W {} with {}", value, our_src_w);
+                return substitute_property(req, object_id, property_id, value, our_src_w);
             }
 
             if src_h_prop_id != 0 && property_id == src_h_prop_id {
                 let our_src_h = (CURSOR_DISPLAY_SIZE as u64) << 16;
-                debug_print!("Overriding SRC_H {} with {}", value, our_src_h);
-                if let Some(func) = REAL_ATOMIC_ADD {
-                    return func(req, object_id, property_id, our_src_h);
-                }
+                log_limited!(Trace, Drm, "Overriding SRC_H {} with {}", value, our_src_h);
+                return substitute_property(req, object_id, property_id, value, our_src_h);
             }
 
             if crtc_w_prop_id != 0 && property_id == crtc_w_prop_id {
-                debug_print!("Overriding CRTC_W {} with {}", value, CURSOR_DISPLAY_SIZE);
-                if let Some(func) = REAL_ATOMIC_ADD {
-                    return func(req, object_id, property_id, CURSOR_DISPLAY_SIZE as u64);
-                }
+                log_limited!(
+                    Trace,
+                    Drm,
+                    "Overriding CRTC_W {} with {}",
+                    value,
+                    CURSOR_DISPLAY_SIZE
+                );
+                return substitute_property(
+                    req,
+                    object_id,
+                    property_id,
+                    value,
+                    CURSOR_DISPLAY_SIZE as u64,
+                );
             }
 
             if crtc_h_prop_id != 0 && property_id == crtc_h_prop_id {
-                debug_print!("Overriding CRTC_H {} with {}", value, CURSOR_DISPLAY_SIZE);
-                if let Some(func) = REAL_ATOMIC_ADD {
-                    return func(req, object_id, property_id, CURSOR_DISPLAY_SIZE as u64);
-                }
+                log_limited!(
+                    Trace,
+                    Drm,
+                    "Overriding CRTC_H {} with {}",
+                    value,
+                    CURSOR_DISPLAY_SIZE
+                );
+                return substitute_property(
+                    req,
+                    object_id,
+                    property_id,
+                    value,
+                    CURSOR_DISPLAY_SIZE as u64,
+                );
             }
         }
 
-        if let Some(func) = REAL_ATOMIC_ADD {
-            return func(req, object_id, property_id, value);
+        return pass_property(req, object_id, property_id, value);
+    }
+
+    pass_property(req, object_id, property_id, value)
+}
+
+// =============================================================================
+// Atomic requests
+// =============================================================================
+//
+// Every property the compositor adds to a cursor plane is kept per
+// drmModeAtomicReq, with the value we gave libdrm instead where we swapped it.
+// Requests are followed through alloc/duplicate/merge/set-cursor/free the way
+// libdrm handles them, so what we have matches what the commit will send.
+//
+// A driver can refuse our cursor in a commit (format, size) and a compositor
+// that sees commits fail may turn hardware cursors off for good. When a
+// commit with our values fails we add the compositor's back (libdrm keeps the
+// last value of a property) and commit again. Planes that keep doing it are
+// left to the compositor.
+
+// Failed commits in a row before a plane is left alone
+const PLANE_MAX_REJECTS: u32 = 3;
+// Check the commit without doing it, compositors try plane setups this way
+const DRM_MODE_ATOMIC_TEST_ONLY: u32 = 0x0100;
+// Requests compositors never free don't pile up past this
+const MAX_TRACKED_REQUESTS: usize = 64;
+
+static mut REAL_ATOMIC_ALLOC: Option<unsafe extern "C" fn() -> *mut c_void> = None;
+static mut REAL_ATOMIC_DUPLICATE: Option<unsafe extern "C" fn(*mut c_void) -> *mut c_void> = None;
+static mut REAL_ATOMIC_MERGE: Option<unsafe extern "C" fn(*mut c_void, *mut c_void) -> i32> = None;
+static mut REAL_ATOMIC_SET_CURSOR: Option<unsafe extern "C" fn(*mut c_void, i32)> = None;
+static mut REAL_ATOMIC_COMMIT: Option<
+    unsafe extern "C" fn(i32, *mut c_void, u32, *mut c_void) -> i32,
+> = None;
+static mut REAL_ATOMIC_FREE: Option<unsafe extern "C" fn(*mut c_void)> = None;
+
+// Commits with our values that failed in a row, per cursor plane
+static mut CURSOR_PLANE_REJECTS: [u32; 8] = [0; 8];
+// Planes we stopped substituting on
+static mut CURSOR_PLANE_GIVEN_UP: [bool; 8] = [false; 8];
+
+/// A cursor plane property the compositor added to a request
+#[derive(Clone, Copy, Debug)]
+struct TrackedProperty {
+    // Position in the request, what drmModeAtomicSetCursor counts
+    at: u32,
+    object_id: u32,
+    property_id: u32,
+    theirs: u64,
+    // What libdrm got instead, if we changed it
+    ours: Option<u64>,
+}
+
+/// One drmModeAtomicReq as the compositor built it
+#[derive(Clone)]
+struct TrackedRequest {
+    req: usize,
+    // Properties added so far on any object, like libdrm's cursor
+    cursor: u32,
+    props: Vec<TrackedProperty>,
+}
+
+impl TrackedRequest {
+    fn new(req: usize) -> Self {
+        TrackedRequest {
+            req,
+            cursor: 0,
+            props: Vec::new(),
+        }
+    }
+
+    /// Properties where our value is the one that will be committed
+    fn edits(&self) -> Vec<TrackedProperty> {
+        self.props
+            .iter()
+            .enumerate()
+            .filter(|(idx, prop)| {
+                prop.ours.is_some()
+                    && !self.props[idx + 1..].iter().any(|later| {
+                        later.object_id == prop.object_id && later.property_id == prop.property_id
+                    })
+            })
+            .map(|(_, prop)| *prop)
+            .collect()
+    }
+}
+
+static ATOMIC_REQUESTS: Mutex<Vec<TrackedRequest>> = Mutex::new(Vec::new());
+
+unsafe fn init_request_functions() {
+    REAL_ATOMIC_ALLOC = REAL_ATOMIC_ALLOC.or_else(|| next_symbol(c"drmModeAtomicAlloc"));
+    REAL_ATOMIC_DUPLICATE =
+        REAL_ATOMIC_DUPLICATE.or_else(|| next_symbol(c"drmModeAtomicDuplicate"));
+    REAL_ATOMIC_MERGE = REAL_ATOMIC_MERGE.or_else(|| next_symbol(c"drmModeAtomicMerge"));
+    REAL_ATOMIC_SET_CURSOR =
+        REAL_ATOMIC_SET_CURSOR.or_else(|| next_symbol(c"drmModeAtomicSetCursor"));
+    REAL_ATOMIC_COMMIT = REAL_ATOMIC_COMMIT.or_else(|| next_symbol(c"drmModeAtomicCommit"));
+    REAL_ATOMIC_FREE = REAL_ATOMIC_FREE.or_else(|| next_symbol(c"drmModeAtomicFree"));
+}
+
+/// The request at `req`, starting to track it if we weren't. Requests built
+/// before we were loaded or never freed push the oldest one out.
+fn tracked_request(requests: &mut Vec<TrackedRequest>, req: usize) -> &mut TrackedRequest {
+    let idx = match requests.iter().position(|r| r.req == req) {
+        Some(idx) => idx,
+        None => {
+            if requests.len() >= MAX_TRACKED_REQUESTS {
+                requests.remove(0);
+            }
+            requests.push(TrackedRequest::new(req));
+            requests.len() - 1
         }
+    };
+    &mut requests[idx]
+}
+
+fn forget_request(req: *mut c_void) {
+    ATOMIC_REQUESTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|r| r.req != req as usize);
+}
+
+/// What we changed in `req` that's still in effect
+fn request_edits(req: *mut c_void) -> Vec<TrackedProperty> {
+    ATOMIC_REQUESTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|r| r.req == req as usize)
+        .map(|r| r.edits())
+        .unwrap_or_default()
+}
+
+/// Note a property libdrm took, keeping the compositor's value if it's on a
+/// cursor plane. `cursor` is what the add returned, libdrm's count of
+/// properties in the request.
+unsafe fn track_property(
+    req: *mut c_void,
+    object_id: u32,
+    property_id: u32,
+    theirs: u64,
+    ours: Option<u64>,
+    cursor: i32,
+) {
+    if req.is_null() || cursor <= 0 {
+        return;
+    }
+    let is_cursor = get_cursor_plane_index(object_id).is_some();
+    let mut requests = ATOMIC_REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+    let request = tracked_request(&mut requests, req as usize);
+    request.cursor = cursor as u32;
+    if is_cursor {
+        request.props.push(TrackedProperty {
+            at: request.cursor - 1,
+            object_id,
+            property_id,
+            theirs,
+            ours,
+        });
+    }
+}
+
+/// Give libdrm our value for one of the compositor's properties, keeping
+/// theirs in case the commit fails with ours
+unsafe fn substitute_property(
+    req: *mut c_void,
+    object_id: u32,
+    property_id: u32,
+    theirs: u64,
+    ours: u64,
+) -> i32 {
+    let Some(func) = REAL_ATOMIC_ADD else {
         return -1;
+    };
+    let ret = func(req, object_id, property_id, ours);
+    let ours = (ours != theirs).then_some(ours);
+    track_property(req, object_id, property_id, theirs, ours, ret);
+    ret
+}
+
+/// Give libdrm the compositor's own value
+unsafe fn pass_property(req: *mut c_void, object_id: u32, property_id: u32, value: u64) -> i32 {
+    substitute_property(req, object_id, property_id, value, value)
+}
+
+/// Put the compositor's values back in `req`, returns the cursor planes that
+/// had ours
+unsafe fn restore_request(req: *mut c_void, edits: &[TrackedProperty]) -> Vec<usize> {
+    let mut planes = Vec::new();
+    for edit in edits {
+        pass_property(req, edit.object_id, edit.property_id, edit.theirs);
+        if let Some(idx) = get_cursor_plane_index(edit.object_id) {
+            if !planes.contains(&idx) {
+                planes.push(idx);
+            }
+        }
+    }
+    planes
+}
+
+/// Errnos a driver fails a configuration it won't take with. Anything else
+/// (EBUSY for a flip still pending, EINTR, ENOMEM) isn't about our cursor.
+fn rejects_configuration(errno: i32) -> bool {
+    matches!(errno, libc::EINVAL | libc::ERANGE | libc::ENOSPC)
+}
+
+/// A commit with our values went through, the planes in it are fine
+unsafe fn planes_accepted(edits: &[TrackedProperty]) {
+    for edit in edits {
+        log_limited!(
+            Trace,
+            Drm,
+            "Committed plane {} property {} = {} (compositor had {})",
+            edit.object_id,
+            edit.property_id,
+            edit.ours.unwrap_or(edit.theirs),
+            edit.theirs
+        );
+        if let Some(idx) = get_cursor_plane_index(edit.object_id) {
+            CURSOR_PLANE_REJECTS[idx] = 0;
+        }
+    }
+}
+
+/// Hooked `drmModeAtomicAlloc`, starts tracking the request.
+///
+/// # Safety
+/// Same as libdrm's.
+#[no_mangle]
+pub unsafe extern "C" fn drmModeAtomicAlloc() -> *mut c_void {
+    init_request_functions();
+    let Some(alloc) = REAL_ATOMIC_ALLOC else {
+        return std::ptr::null_mut();
+    };
+    let req = alloc();
+    if !req.is_null() {
+        // A fresh request can land where a freed one was
+        forget_request(req);
+        let mut requests = ATOMIC_REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+        tracked_request(&mut requests, req as usize);
+    }
+    req
+}
+
+/// Hooked `drmModeAtomicDuplicate`, the copy keeps what we swapped.
+///
+/// # Safety
+/// Same as libdrm's, `old` comes from `drmModeAtomicAlloc`.
+#[no_mangle]
+pub unsafe extern "C" fn drmModeAtomicDuplicate(old: *mut c_void) -> *mut c_void {
+    init_request_functions();
+    let Some(duplicate) = REAL_ATOMIC_DUPLICATE else {
+        return std::ptr::null_mut();
+    };
+    let new = duplicate(old);
+    if !new.is_null() {
+        forget_request(new);
+        let mut requests = ATOMIC_REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let copy = requests
+            .iter()
+            .find(|r| r.req == old as usize)
+            .map(|r| TrackedRequest {
+                req: new as usize,
+                ..r.clone()
+            });
+        if let Some(copy) = copy {
+            *tracked_request(&mut requests, new as usize) = copy;
+        }
+    }
+    new
+}
+
+/// Hooked `drmModeAtomicMerge`, `base` takes over what we swapped in `augment`.
+///
+/// # Safety
+/// Same as libdrm's, both requests come from `drmModeAtomicAlloc`.
+#[no_mangle]
+pub unsafe extern "C" fn drmModeAtomicMerge(base: *mut c_void, augment: *mut c_void) -> i32 {
+    init_request_functions();
+    let Some(merge) = REAL_ATOMIC_MERGE else {
+        return -libc::ENOSYS;
+    };
+    let ret = merge(base, augment);
+    if ret == 0 && !base.is_null() && !augment.is_null() {
+        // libdrm appends augment's properties after base's. Without both
+        // counts there's no telling where they ended up.
+        let mut requests = ATOMIC_REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let added = requests.iter().find(|r| r.req == augment as usize).cloned();
+        let request = requests.iter_mut().find(|r| r.req == base as usize);
+        let (Some(added), Some(request)) = (added, request) else {
+            return ret;
+        };
+        let offset = request.cursor;
+        request
+            .props
+            .extend(added.props.iter().map(|p| TrackedProperty {
+                at: p.at + offset,
+                ..*p
+            }));
+        request.cursor += added.cursor;
+    }
+    ret
+}
+
+/// Hooked `drmModeAtomicSetCursor`, forgets what was truncated away.
+///
+/// # Safety
+/// Same as libdrm's, `req` comes from `drmModeAtomicAlloc`.
+#[no_mangle]
+pub unsafe extern "C" fn drmModeAtomicSetCursor(req: *mut c_void, cursor: i32) {
+    init_request_functions();
+    if let Some(set_cursor) = REAL_ATOMIC_SET_CURSOR {
+        set_cursor(req, cursor);
+    }
+    if !req.is_null() {
+        // Everything added past the cursor is gone
+        let cursor = cursor.max(0) as u32;
+        let mut requests = ATOMIC_REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let request = tracked_request(&mut requests, req as usize);
+        request.props.retain(|p| p.at < cursor);
+        request.cursor = cursor;
+    }
+}
+
+/// The compositor's values of an X/Y property pair on each cursor plane in
+/// `req`, per plane index. None for a coordinate it didn't set.
+unsafe fn request_pairs(
+    req: *mut c_void,
+    x_props: [u32; 8],
+    y_props: [u32; 8],
+) -> Vec<(usize, Option<i32>, Option<i32>)> {
+    let requests = ATOMIC_REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(request) = requests.iter().find(|r| r.req == req as usize) else {
+        return Vec::new();
+    };
+    let mut positions: Vec<(usize, Option<i32>, Option<i32>)> = Vec::new();
+    for prop in &request.props {
+        let Some(idx) = get_cursor_plane_index(prop.object_id) else {
+            continue;
+        };
+        let is_x = prop.property_id == x_props[idx];
+        let is_y = prop.property_id == y_props[idx];
+        if !is_x && !is_y {
+            continue;
+        }
+        let at = match positions.iter().position(|p| p.0 == idx) {
+            Some(at) => at,
+            None => {
+                positions.push((idx, None, None));
+                positions.len() - 1
+            }
+        };
+        if is_x {
+            positions[at].1 = Some(prop.theirs as i32);
+        } else {
+            positions[at].2 = Some(prop.theirs as i32);
+        }
+    }
+    positions
+}
+
+/// Where the compositor put each cursor plane in `req`
+unsafe fn request_positions(req: *mut c_void) -> Vec<(usize, Option<i32>, Option<i32>)> {
+    request_pairs(req, CURSOR_CRTC_X_PROP_IDS, CURSOR_CRTC_Y_PROP_IDS)
+}
+
+/// Learn the compositor's hotspot for plane `idx` from `req` once, with both
+/// axes: when the second one is added, or at commit when it only set one
+/// and the other stays what it was. Taking each axis as it comes pairs the
+/// new X with the last shape's Y.
+unsafe fn learn_request_hotspot(req: *mut c_void, idx: usize, committing: bool) {
+    let hotspots = request_pairs(req, CURSOR_HOTSPOT_X_PROP_IDS, CURSOR_HOTSPOT_Y_PROP_IDS);
+    let Some(&(_, x, y)) = hotspots.iter().find(|h| h.0 == idx) else {
+        return;
+    };
+    let hotspot = match (x, y) {
+        (Some(x), Some(y)) if !committing => (x, y),
+        (Some(_), Some(_)) => return,
+        _ if committing => (
+            x.unwrap_or(COMPOSITOR_HOTSPOT_X.load(Ordering::SeqCst)),
+            y.unwrap_or(COMPOSITOR_HOTSPOT_Y.load(Ordering::SeqCst)),
+        ),
+        _ => return,
+    };
+    remember_compositor_hotspot(hotspot, HotspotSource::Explicit);
+}
+
+/// The cursor planes in a commit that went through are where it put them,
+/// one move for X and Y together
+unsafe fn commit_plane_positions(req: *mut c_void) {
+    for (idx, x, y) in request_positions(req) {
+        let (old_x, old_y) = CURSOR_PLANE_POSITIONS[idx];
+        move_cursor_plane(idx, x.unwrap_or(old_x), y.unwrap_or(old_y));
+    }
+}
+
+/// Hooked `drmModeAtomicCommit`, moves our cursor once the commit went
+/// through and retries without it when the driver refused it.
+///
+/// # Safety
+/// Same as libdrm's, `req` comes from `drmModeAtomicAlloc`.
+#[no_mangle]
+pub unsafe extern "C" fn drmModeAtomicCommit(
+    fd: i32,
+    req: *mut c_void,
+    flags: u32,
+    user_data: *mut c_void,
+) -> i32 {
+    init_request_functions();
+    let Some(commit) = REAL_ATOMIC_COMMIT else {
+        return -libc::ENOSYS;
+    };
+
+    for idx in 0..NUM_CURSOR_PLANES {
+        learn_request_hotspot(req, idx, true);
+    }
+    let ret = commit_with_fallback(commit, fd, req, flags, user_data);
+    if ret == 0 && flags & DRM_MODE_ATOMIC_TEST_ONLY == 0 {
+        commit_plane_positions(req);
+    }
+    ret
+}
+
+/// Commit `req`, and again with the compositor's values if the driver
+/// refused ours
+unsafe fn commit_with_fallback(
+    commit: unsafe extern "C" fn(i32, *mut c_void, u32, *mut c_void) -> i32,
+    fd: i32,
+    req: *mut c_void,
+    flags: u32,
+    user_data: *mut c_void,
+) -> i32 {
+    let ret = commit(fd, req, flags, user_data);
+    let edits = request_edits(req);
+    if edits.is_empty() {
+        return ret;
+    }
+    if ret == 0 {
+        planes_accepted(&edits);
+        return ret;
+    }
+    if !rejects_configuration(-ret) {
+        return ret;
     }
 
-    match REAL_ATOMIC_ADD {
-        Some(func) => func(req, object_id, property_id, value),
-        None => -1,
+    // libdrm returns -errno. Restoring makes the compositor's values the
+    // last ones in the request, so a later commit of it goes out as theirs.
+    let error = std::io::Error::from_raw_os_error(-ret);
+    let planes = restore_request(req, &edits);
+    STAT_COMMIT_RETRIES.fetch_add(1, Ordering::Relaxed);
+    let retry = commit(fd, req, flags, user_data);
+    if retry != 0 {
+        // Fails either way, not our doing
+        log_limited!(
+            Debug,
+            Drm,
+            "Commit failed with and without our cursor: {}",
+            error
+        );
+        return retry;
+    }
+    if flags & DRM_MODE_ATOMIC_TEST_ONLY != 0 {
+        // Only the compositor trying a plane setup, the real commit counts
+        log_limited!(
+            Debug,
+            Drm,
+            "Test commit with our cursor failed ({}), passed with the compositor's",
+            error
+        );
+        return 0;
+    }
+
+    for idx in planes {
+        CURSOR_PLANE_REJECTS[idx] += 1;
+        let plane_id = CURSOR_PLANE_IDS[idx];
+        if CURSOR_PLANE_REJECTS[idx] >= PLANE_MAX_REJECTS {
+            CURSOR_PLANE_GIVEN_UP[idx] = true;
+            log!(
+                Error,
+                Drm,
+                "The driver keeps rejecting our cursor on plane {} ({}), leaving that plane to the compositor",
+                plane_id,
+                error
+            );
+        } else {
+            log!(
+                Warn,
+                Drm,
+                "Commit with our cursor on plane {} failed ({}), went through with the compositor's",
+                plane_id,
+                error
+            );
+        }
+    }
+    0
+}
+
+/// Hooked `drmModeAtomicFree`, stops tracking the request.
+///
+/// # Safety
+/// Same as libdrm's, `req` comes from `drmModeAtomicAlloc`.
+#[no_mangle]
+pub unsafe extern "C" fn drmModeAtomicFree(req: *mut c_void) {
+    init_request_functions();
+    forget_request(req);
+    if let Some(free) = REAL_ATOMIC_FREE {
+        free(req);
     }
 }
+
+#[cfg(test)]
+mod fake_drm;
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,1443 @@
+//! Hook behaviour against the fake DRM device in `fake_drm`, and golden
+//! images of the rasterizer in `tests/golden`
+
+use super::*;
+use fake_drm::{FakeDevice, PlaneKind};
+
+const CRTC: u32 = 7;
+
+fn our_hotspot() -> (i32, i32) {
+    (
+        CURSOR_HOTSPOT_X.load(Ordering::SeqCst),
+        CURSOR_HOTSPOT_Y.load(Ordering::SeqCst),
+    )
+}
+
+/// A 64x64 arrow with its tip at (3, 2), like most themes draw it
+fn arrow_image() -> Vec<u32> {
+    let mut pixels = vec![0u32; 64 * 64];
+    for y in 2..26 {
+        for x in 3..=(3 + (y - 2) / 2) {
+            pixels[y * 64 + x] = 0xff00_0000;
+        }
+    }
+    pixels
+}
+
+fn atomic_cursor(dev: &FakeDevice) -> u32 {
+    let plane = dev.add_cursor_plane();
+    dev.get_plane(plane);
+    plane
+}
+
+#[test]
+fn atomic_fb_is_replaced_with_ours() {
+    let dev = FakeDevice::open();
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+
+    let fb = dev.atomic_add(plane, "FB_ID", theirs as u64);
+    assert_eq!(fb, Some(CURSOR_FB_ID.load(Ordering::SeqCst) as u64));
+    assert_ne!(fb, Some(theirs as u64));
+
+    let size = CURSOR_DISPLAY_SIZE as u64;
+    assert_eq!(dev.atomic_add(plane, "SRC_W", 24 << 16), Some(size << 16));
+    assert_eq!(dev.atomic_add(plane, "SRC_H", 24 << 16), Some(size << 16));
+    assert_eq!(dev.atomic_add(plane, "CRTC_W", 24), Some(size));
+    assert_eq!(dev.atomic_add(plane, "CRTC_H", 24), Some(size));
+
+    assert!(dev.cursor_pixels().iter().any(|p| p >> 24 != 0));
+}
+
+#[test]
+fn atomic_hide_passes_through_without_fade() {
+    let dev = FakeDevice::open();
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+
+    assert_eq!(dev.atomic_add(plane, "FB_ID", 0), Some(0));
+    assert_eq!(visibility(), Visibility::Hidden);
+
+    let fb = dev.atomic_add(plane, "FB_ID", theirs as u64);
+    assert_eq!(fb, Some(CURSOR_FB_ID.load(Ordering::SeqCst) as u64));
+    assert_eq!(visibility(), Visibility::Shown);
+}
+
+#[test]
+fn atomic_hide_fades_out_on_our_buffer() {
+    let dev = FakeDevice::open();
+    CURSOR_FADE_ENABLED.store(true, Ordering::Relaxed);
+    CONFIG_FADE_SPEED.store(255, Ordering::Relaxed);
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+
+    // Our image stays up while it fades
+    let ours = CURSOR_FB_ID.load(Ordering::SeqCst);
+    assert_eq!(dev.atomic_add(plane, "FB_ID", 0), Some(ours as u64));
+    assert_eq!(visibility(), Visibility::FadingOut);
+
+    assert!(dev.wait_until(1000, || visibility() == Visibility::Hidden));
+    assert!(dev.cursor_pixels().iter().all(|p| *p == 0));
+    assert!(dev.dirty_fbs().contains(&ours));
+}
+
+#[test]
+fn idle_cursor_is_cleared_and_a_move_brings_it_back() {
+    let dev = FakeDevice::open();
+    CONFIG_IDLE_HIDE_MS.store(50, Ordering::Relaxed);
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+    let move_to = |x: u64| {
+        let req = dev.atomic_request();
+        dev.atomic_add_to(req, plane, "CRTC_X", x);
+        dev.atomic_add_to(req, plane, "CRTC_Y", 100);
+        assert_eq!(dev.atomic_commit(req), 0);
+    };
+
+    move_to(100);
+    assert!(dev.cursor_pixels().iter().any(|p| *p != 0));
+    assert!(dev.wait_until(1000, || IDLE_HIDDEN.load(Ordering::SeqCst)));
+    assert!(dev.wait_until(1000, || dev.cursor_pixels().iter().all(|p| *p == 0)));
+    // The plane keeps our FB, the compositor thinks the cursor is shown
+    assert_eq!(
+        dev.atomic_add(plane, "FB_ID", theirs as u64),
+        Some(CURSOR_FB_ID.load(Ordering::SeqCst) as u64)
+    );
+
+    move_to(110);
+    assert!(!IDLE_HIDDEN.load(Ordering::SeqCst));
+    assert!(dev.cursor_pixels().iter().any(|p| *p != 0));
+}
+
+#[test]
+fn fade_turns_around_where_it_is() {
+    let dev = FakeDevice::open();
+    CURSOR_FADE_ENABLED.store(true, Ordering::Relaxed);
+    CONFIG_FADE_IN_ENABLED.store(true, Ordering::Relaxed);
+    CONFIG_FADE_SPEED.store(1, Ordering::Relaxed);
+    CONFIG_FADE_EASING.store(FadeEasing::Linear as u32, Ordering::Relaxed);
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+
+    dev.atomic_add(plane, "FB_ID", 0);
+    thread::sleep(Duration::from_millis(20));
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+
+    // A 4s fade barely got going, coming back in starts from near opaque
+    assert_eq!(visibility(), Visibility::FadingIn);
+    let from = f32::from_bits(FADE_FROM_OPACITY.load(Ordering::SeqCst));
+    assert!(from > 0.9 && from < 1.0, "faded in from {}", from);
+    assert!(cursor_opacity() >= from);
+}
+
+#[test]
+fn set_cursor2_puts_up_our_buffer_and_hotspot() {
+    let dev = FakeDevice::open();
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    let handle = dev.handle_of(theirs);
+
+    unsafe {
+        drmModeSetCursor2(dev.fd, CRTC, handle, 24, 24, 4, 5);
+    }
+    let call = dev.last_cursor_call().unwrap();
+    assert_eq!(call.handle, CURSOR_HANDLE.load(Ordering::SeqCst));
+    assert_eq!(call.crtc_id, CRTC);
+    assert_eq!((call.width, call.height), (64, 64));
+    assert_eq!((call.hot_x, call.hot_y), our_hotspot());
+    assert_eq!(hotspot_offset(), (4 - our_hotspot().0, 5 - our_hotspot().1));
+}
+
+#[test]
+fn legacy_move_lands_our_hotspot_on_theirs() {
+    let dev = FakeDevice::open();
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+
+    unsafe {
+        drmModeSetCursor2(dev.fd, CRTC, dev.handle_of(theirs), 24, 24, 10, 12);
+        drmModeMoveCursor(dev.fd, CRTC, 100, 200);
+    }
+    let (hot_x, hot_y) = our_hotspot();
+    let call = dev.last_cursor_call().unwrap();
+    assert_eq!(call.flags, DRM_MODE_CURSOR_MOVE);
+    assert_eq!((call.x, call.y), (100 + 10 - hot_x, 200 + 12 - hot_y));
+    assert_eq!(CURSOR_SCREEN_X.load(Ordering::SeqCst), call.x);
+    assert_eq!(CURSOR_CRTC_ID.load(Ordering::SeqCst), CRTC);
+}
+
+#[test]
+fn legacy_ioctl_swaps_the_bo_and_passes_hides_through() {
+    let dev = FakeDevice::open();
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    let mut cursor = DrmModeCursor2 {
+        flags: DRM_MODE_CURSOR_BO,
+        crtc_id: CRTC,
+        x: 0,
+        y: 0,
+        width: 24,
+        height: 24,
+        handle: dev.handle_of(theirs),
+        hot_x: 1,
+        hot_y: 1,
+    };
+
+    unsafe {
+        ioctl(
+            dev.fd,
+            DRM_IOCTL_MODE_CURSOR2,
+            &mut cursor as *mut _ as *mut c_void,
+        );
+    }
+    let call = dev.last_cursor_call().unwrap();
+    assert_eq!(call.handle, CURSOR_HANDLE.load(Ordering::SeqCst));
+    assert_eq!((call.hot_x, call.hot_y), our_hotspot());
+
+    cursor.handle = 0;
+    unsafe {
+        ioctl(
+            dev.fd,
+            DRM_IOCTL_MODE_CURSOR2,
+            &mut cursor as *mut _ as *mut c_void,
+        );
+    }
+    assert_eq!(dev.last_cursor_call().unwrap().handle, 0);
+    assert_eq!(visibility(), Visibility::Hidden);
+    assert_eq!(LEGACY_CURSOR_CRTC.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn legacy_bo_swap_with_another_hotspot_moves_our_image() {
+    let dev = FakeDevice::open();
+    let arrow = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    let hand = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    let mut cursor = DrmModeCursor2 {
+        flags: DRM_MODE_CURSOR_BO | DRM_MODE_CURSOR_MOVE,
+        crtc_id: CRTC,
+        x: 100,
+        y: 200,
+        width: 24,
+        height: 24,
+        handle: dev.handle_of(arrow),
+        hot_x: 1,
+        hot_y: 1,
+    };
+    let send = |cursor: &mut DrmModeCursor2| unsafe {
+        ioctl(
+            dev.fd,
+            DRM_IOCTL_MODE_CURSOR2,
+            cursor as *mut _ as *mut c_void,
+        );
+    };
+    send(&mut cursor);
+
+    // Same pointer position, new shape with its hotspot elsewhere
+    cursor.flags = DRM_MODE_CURSOR_BO;
+    cursor.handle = dev.handle_of(hand);
+    (cursor.hot_x, cursor.hot_y) = (8, 9);
+    send(&mut cursor);
+    let (hot_x, hot_y) = our_hotspot();
+    let call = dev.last_cursor_call().unwrap();
+    assert_eq!(call.flags, DRM_MODE_CURSOR_BO | DRM_MODE_CURSOR_MOVE);
+    assert_eq!((call.x, call.y), (100 + 8 - hot_x, 200 + 9 - hot_y));
+
+    // Set again as it is, nothing to move
+    cursor.flags = DRM_MODE_CURSOR_BO;
+    cursor.handle = dev.handle_of(hand);
+    (cursor.hot_x, cursor.hot_y) = (8, 9);
+    send(&mut cursor);
+    assert_eq!(dev.last_cursor_call().unwrap().flags, DRM_MODE_CURSOR_BO);
+}
+
+#[test]
+fn primary_plane_updates_pass_through() {
+    let dev = FakeDevice::open();
+    let primary = dev.add_plane(PlaneKind::Primary, &[("FB_ID", 0), ("CRTC_ID", 0)]);
+    dev.get_plane(primary);
+    let scanout = dev.add_framebuffer(64, 64, &[0; 64 * 64]);
+
+    assert_eq!(
+        dev.atomic_add(primary, "FB_ID", scanout as u64),
+        Some(scanout as u64)
+    );
+    assert_eq!(dev.atomic_add(primary, "CRTC_ID", 3), Some(3));
+    assert!(!INITIALIZED.load(Ordering::SeqCst));
+}
+
+#[test]
+fn atomic_hotspot_props_get_ours_and_positions_follow() {
+    let dev = FakeDevice::open();
+    let plane = dev.add_plane(
+        PlaneKind::Cursor,
+        &[
+            ("FB_ID", 0),
+            ("CRTC_ID", 0),
+            ("CRTC_X", 0),
+            ("CRTC_Y", 0),
+            ("HOTSPOT_X", 0),
+            ("HOTSPOT_Y", 0),
+        ],
+    );
+    dev.get_plane(plane);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+
+    let (hot_x, hot_y) = our_hotspot();
+    let req = dev.atomic_request();
+    assert_eq!(
+        dev.atomic_add_to(req, plane, "HOTSPOT_X", 9),
+        Some(hot_x as u64)
+    );
+    // Half a hotspot isn't learned, it would pair 9 with the old Y
+    assert!(!COMPOSITOR_HOTSPOT_KNOWN.load(Ordering::SeqCst));
+    assert_eq!(
+        dev.atomic_add_to(req, plane, "HOTSPOT_Y", 5),
+        Some(hot_y as u64)
+    );
+
+    let x = dev.atomic_add_to(req, plane, "CRTC_X", 300).unwrap() as i32;
+    let y = dev.atomic_add_to(req, plane, "CRTC_Y", 400).unwrap() as i32;
+    assert_eq!((x, y), (300 + 9 - hot_x, 400 + 5 - hot_y));
+    assert_eq!(dev.atomic_commit(req), 0);
+
+    // Only X changes, Y stays what the driver has
+    let req = dev.atomic_request();
+    dev.atomic_add_to(req, plane, "HOTSPOT_X", 2);
+    assert_eq!(COMPOSITOR_HOTSPOT_X.load(Ordering::SeqCst), 9);
+    assert_eq!(dev.atomic_commit(req), 0);
+    assert_eq!(
+        (
+            COMPOSITOR_HOTSPOT_X.load(Ordering::SeqCst),
+            COMPOSITOR_HOTSPOT_Y.load(Ordering::SeqCst)
+        ),
+        (2, 5)
+    );
+}
+
+#[test]
+fn compositor_hotspot_is_read_from_its_image() {
+    let dev = FakeDevice::open();
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(64, 64, &arrow_image());
+
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+    assert!(COMPOSITOR_HOTSPOT_KNOWN.load(Ordering::SeqCst));
+    assert_eq!(
+        (
+            COMPOSITOR_HOTSPOT_X.load(Ordering::SeqCst),
+            COMPOSITOR_HOTSPOT_Y.load(Ordering::SeqCst)
+        ),
+        (3, 2)
+    );
+}
+
+#[test]
+fn only_linear_or_dumb_framebuffers_are_read() {
+    let dev = FakeDevice::open();
+    let readable = |fb_id: u32| unsafe {
+        let mapping = map_framebuffer(dev.fd, fb_id);
+        if let Some(mapping) = &mapping {
+            libc::munmap(mapping.ptr, mapping.len);
+        }
+        mapping.is_some()
+    };
+
+    let linear = dev.add_framebuffer(16, 16, &[0; 16 * 16]);
+    assert!(readable(linear));
+    // I915_FORMAT_MOD_X_TILED
+    let tiled = dev.add_framebuffer_with(16, 16, &[0; 16 * 16], Some(0x0100000000000001));
+    assert!(!readable(tiled));
+    // No modifiers and not a dumb buffer we saw made, the layout is anyone's guess
+    let unknown = dev.add_framebuffer_with(16, 16, &[0; 16 * 16], None);
+    assert!(!readable(unknown));
+
+    // The compositor's own dumb buffer is linear without saying so
+    let mut create = DrmModeCreateDumb {
+        width: 16,
+        height: 16,
+        bpp: 32,
+        ..Default::default()
+    };
+    let mut fb = DrmModeFB2 {
+        width: 16,
+        height: 16,
+        pixel_format: DRM_FORMAT_XRGB8888,
+        ..Default::default()
+    };
+    unsafe {
+        assert_eq!(
+            ioctl(
+                dev.fd,
+                DRM_IOCTL_MODE_CREATE_DUMB,
+                &mut create as *mut _ as *mut c_void
+            ),
+            0
+        );
+        fb.handles[0] = create.handle;
+        fb.pitches[0] = create.pitch;
+        assert_eq!(
+            ioctl(
+                dev.fd,
+                DRM_IOCTL_MODE_ADDFB2,
+                &mut fb as *mut _ as *mut c_void
+            ),
+            0
+        );
+    }
+    assert!(readable(fb.fb_id));
+}
+
+#[test]
+fn reused_framebuffer_ids_are_read_again() {
+    let dev = FakeDevice::open();
+    let primary = dev.add_plane(PlaneKind::Primary, &[("FB_ID", 0), ("CRTC_ID", 0)]);
+    dev.get_plane(primary);
+    let sample = || unsafe {
+        let _render = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        map_primary_fb().then(|| sample_primary_pixel(1, 1))
+    };
+
+    let black = dev.add_framebuffer(16, 16, &[0xFF000000; 16 * 16]);
+    dev.atomic_add(primary, "FB_ID", black as u64);
+    assert_eq!(sample(), Some(0xFF000000));
+
+    // Same id, another buffer: the old mapping must not be used for it
+    dev.remove_framebuffer(black);
+    let white = dev.add_framebuffer(16, 16, &[0xFFFFFFFF; 16 * 16]);
+    assert_eq!(white, black);
+    dev.atomic_add(primary, "FB_ID", white as u64);
+    assert_eq!(sample(), Some(0xFFFFFFFF));
+
+    // Nor does an unreadable FB spoil its id for the next one
+    dev.remove_framebuffer(white);
+    let tiled = dev.add_framebuffer_with(16, 16, &[0; 16 * 16], None);
+    assert_eq!(sample(), None);
+    dev.remove_framebuffer(tiled);
+    let gray = dev.add_framebuffer(16, 16, &[0xFF808080; 16 * 16]);
+    assert_eq!(gray, tiled);
+    assert_eq!(sample(), Some(0xFF808080));
+}
+
+#[test]
+fn adaptive_variant_flips_once_per_backdrop_change() {
+    let dev = FakeDevice::open();
+    CONFIG_ADAPTIVE_MODE.store(AdaptiveMode::Variant as u32, Ordering::Relaxed);
+    let primary = dev.add_plane(PlaneKind::Primary, &[("FB_ID", 0), ("CRTC_ID", 0)]);
+    dev.get_plane(primary);
+    let black = dev.add_framebuffer(256, 256, &[0xFF000000; 256 * 256]);
+    let white = dev.add_framebuffer(256, 256, &[0xFFFFFFFF; 256 * 256]);
+    // Lightness 0.52, inside the hysteresis band around 0.5
+    let gray = dev.add_framebuffer(256, 256, &[0xFF7C7C7C; 256 * 256]);
+
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+    let renders = || STAT_RENDERS.load(Ordering::Relaxed);
+    let move_over = |fb: u32, x: u64| {
+        dev.atomic_add(primary, "FB_ID", fb as u64);
+        let before = renders();
+        let req = dev.atomic_request();
+        dev.atomic_add_to(req, plane, "CRTC_X", x);
+        dev.atomic_add_to(req, plane, "CRTC_Y", 100);
+        assert_eq!(dev.atomic_commit(req), 0);
+        renders() - before
+    };
+
+    // The default arrow is dark, so it gets inverted over a dark screen only
+    assert_eq!(move_over(black, 100), 1);
+    assert_eq!(
+        Backdrop::from_u32(BACKDROP_CLASS.load(Ordering::SeqCst)),
+        Backdrop::Dark
+    );
+    let on_dark = dev.cursor_pixels();
+    assert_eq!(move_over(black, 110), 0);
+
+    assert_eq!(move_over(white, 120), 1);
+    let on_light = dev.cursor_pixels();
+    assert_eq!(move_over(white, 130), 0);
+    // Close to the threshold the class holds instead of flickering
+    assert_eq!(move_over(gray, 140), 0);
+    assert_eq!(
+        Backdrop::from_u32(BACKDROP_CLASS.load(Ordering::SeqCst)),
+        Backdrop::Light
+    );
+
+    // Same shape, opaque pixels are their sRGB inverse
+    let channels = |p: u32| [(p >> 16) & 0xFF, (p >> 8) & 0xFF, p & 0xFF];
+    let mut opaque = 0;
+    for (dark, light) in on_dark.iter().zip(&on_light) {
+        assert_eq!(dark >> 24, light >> 24);
+        if dark >> 24 == 0xFF {
+            for (d, l) in channels(*dark).into_iter().zip(channels(*light)) {
+                assert!((d + l).abs_diff(0xFF) <= 1, "{:08x} vs {:08x}", dark, light);
+            }
+            opaque += 1;
+        }
+    }
+    assert!(opaque > 0);
+
+    assert_eq!(move_over(black, 150), 1);
+    assert!(dev.cursor_pixels() == on_dark);
+
+    // Outline mode keeps the cursor and rings it in the opposite color
+    CONFIG_ADAPTIVE_MODE.store(AdaptiveMode::Outline as u32, Ordering::Relaxed);
+    let ring = |ringed: &[u32]| -> Vec<u32> {
+        let mut ring = Vec::new();
+        for (plain, ringed) in on_light.iter().zip(ringed) {
+            if plain >> 24 == 0xFF {
+                assert_eq!(plain, ringed);
+            } else if plain >> 24 == 0 && *ringed != 0 {
+                ring.push(*ringed);
+            }
+        }
+        ring
+    };
+    assert_eq!(move_over(white, 160), 1);
+    let on_light_ring = ring(&dev.cursor_pixels());
+    assert!(!on_light_ring.is_empty());
+    assert!(on_light_ring.iter().all(|p| p & 0xFFFFFF == 0));
+    assert_eq!(move_over(black, 170), 1);
+    let on_dark_ring = ring(&dev.cursor_pixels());
+    assert_eq!(on_dark_ring.len(), on_light_ring.len());
+    // Premultiplied white
+    assert!(on_dark_ring
+        .iter()
+        .all(|p| p & 0xFFFFFF == (p >> 24) * 0x010101));
+}
+
+#[test]
+fn atomic_position_and_crtc_are_tracked() {
+    let dev = FakeDevice::open();
+    let plane = atomic_cursor(&dev);
+
+    dev.atomic_add(plane, "CRTC_ID", 42);
+    // No compositor hotspot known yet, so nothing to correct
+    let req = dev.atomic_request();
+    assert_eq!(dev.atomic_add_to(req, plane, "CRTC_X", 500), Some(500));
+    // A diagonal move is one move, not X first with the old Y
+    assert_eq!(CURSOR_SCREEN_X.load(Ordering::SeqCst), 0);
+    assert_eq!(dev.atomic_add_to(req, plane, "CRTC_Y", 300), Some(300));
+    assert_eq!(dev.atomic_test(req), 0);
+    assert_eq!(CURSOR_SCREEN_X.load(Ordering::SeqCst), 0);
+    assert_eq!(dev.atomic_commit(req), 0);
+
+    assert_eq!(CURSOR_CRTC_ID.load(Ordering::SeqCst), 42);
+    assert_eq!(CURSOR_SCREEN_X.load(Ordering::SeqCst), 500);
+    assert_eq!(CURSOR_SCREEN_Y.load(Ordering::SeqCst), 300);
+}
+
+// Rasterizer goldens. Regenerate with `UPDATE_GOLDEN=1 cargo test` after an
+// intended rendering change, and look at the diff before committing it.
+
+const GOLDEN_SCALES: [f32; 3] = [1.0, 1.5, 2.5];
+const GOLDEN_TOLERANCE: u8 = 2;
+
+fn golden_dir() -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Deflate bits, values LSB first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: usize) -> usize {
+        (0..count).fold(0, |value, i| {
+            let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+            self.pos += 1;
+            value | (bit as usize) << i
+        })
+    }
+
+    /// Huffman codes come MSB first
+    fn code(&mut self, len: usize) -> usize {
+        (0..len).fold(0, |code, _| code << 1 | self.bits(1))
+    }
+
+    /// Fixed Huffman literal/length symbol
+    fn symbol(&mut self) -> usize {
+        let code = self.code(7);
+        if code <= 0x17 {
+            return 256 + code;
+        }
+        let code = code << 1 | self.bits(1);
+        match code {
+            0x30..=0xBF => code - 0x30,
+            0xC0..=0xC7 => 280 + code - 0xC0,
+            _ => 144 + (code << 1 | self.bits(1)) - 0x190,
+        }
+    }
+}
+
+/// The parts of inflate `encode_png` needs: stored and fixed Huffman blocks
+fn inflate(zlib: &[u8]) -> Vec<u8> {
+    let mut reader = BitReader {
+        data: &zlib[2..],
+        pos: 0,
+    };
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let last = reader.bits(1) == 1;
+        match reader.bits(2) {
+            0 => {
+                let start = reader.pos.div_ceil(8);
+                let len = u16::from_le_bytes([reader.data[start], reader.data[start + 1]]) as usize;
+                out.extend_from_slice(&reader.data[start + 4..start + 4 + len]);
+                reader.pos = (start + 4 + len) * 8;
+            }
+            1 => loop {
+                let symbol = reader.symbol();
+                match symbol {
+                    0..=255 => out.push(symbol as u8),
+                    256 => break,
+                    _ => {
+                        let (base, extra) = DEFLATE_LENGTHS[symbol - 257];
+                        let len = base as usize + reader.bits(extra as usize);
+                        let (base, extra) = DEFLATE_DISTANCES[reader.code(5)];
+                        let dist = base as usize + reader.bits(extra as usize);
+                        for _ in 0..len {
+                            out.push(out[out.len() - dist]);
+                        }
+                    }
+                }
+            },
+            kind => panic!("deflate block type {}", kind),
+        }
+        if last {
+            break;
+        }
+    }
+    let checksum = u32::from_be_bytes(zlib[zlib.len() - 4..].try_into().unwrap());
+    assert_eq!(checksum, adler32(&out));
+    out
+}
+
+struct Png {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+    hotspot: Option<String>,
+}
+
+/// Reads back what `encode_png` writes, unfiltered 8-bit RGBA
+fn decode_png(png: &[u8]) -> Png {
+    assert_eq!(png[..8], PNG_SIGNATURE);
+    let (mut width, mut height, mut idat, mut hotspot) = (0, 0, Vec::new(), None);
+    let mut pos = 8;
+    while pos < png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        let data = &png[pos + 8..pos + 8 + len];
+        let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+        assert_eq!(crc, crc32(&png[pos + 4..pos + 8 + len]));
+        match &png[pos + 4..pos + 8] {
+            b"IHDR" => {
+                width = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+                height = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+                assert_eq!(data[8..10], [8, 6], "not 8-bit RGBA");
+            }
+            b"tEXt" => {
+                let text = String::from_utf8(data.to_vec()).unwrap();
+                if let Some(value) = text.strip_prefix("hotspot\0") {
+                    hotspot = Some(value.to_string());
+                }
+            }
+            b"IDAT" => idat.extend_from_slice(data),
+            _ => {}
+        }
+        pos += len + 12;
+    }
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for row in inflate(&idat).chunks(width * 4 + 1) {
+        assert_eq!(row[0], 0, "filtered scanline");
+        rgba.extend_from_slice(&row[1..]);
+    }
+    Png {
+        width,
+        height,
+        rgba,
+        hotspot,
+    }
+}
+
+fn check_golden(name: &str, canvas: &Canvas) {
+    let png = canvas.to_png();
+    let path = golden_dir().join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        std::fs::write(&path, &png).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing {}, run with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    let expected = decode_png(&expected);
+    let actual = decode_png(&png);
+    assert_eq!(actual.hotspot, expected.hotspot, "{} hotspot moved", name);
+    let worst = expected
+        .rgba
+        .iter()
+        .zip(&actual.rgba)
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap_or(0);
+    if (expected.width, expected.height) != (actual.width, actual.height)
+        || worst > GOLDEN_TOLERANCE
+    {
+        let out = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden");
+        std::fs::create_dir_all(&out).unwrap();
+        std::fs::write(out.join(format!("{}.png", name)), &png).unwrap();
+        panic!(
+            "{} differs from its golden by up to {}, actual image in target/golden",
+            name, worst
+        );
+    }
+}
+
+#[test]
+fn png_round_trips() {
+    let mut canvas = Canvas::new(3, 2);
+    canvas.pixels[4] = LinearPixel {
+        r: 1.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    canvas.hotspot = (2, 1);
+    let png = decode_png(&canvas.to_png());
+    assert_eq!((png.width, png.height), (3, 2));
+    assert_eq!(png.rgba[16..20], [255, 0, 0, 255]);
+    assert!(png.rgba[..16].iter().all(|b| *b == 0));
+    assert_eq!(png.hotspot.as_deref(), Some("2,1"));
+}
+
+#[test]
+fn deflate_round_trips() {
+    let mut data = vec![0u8; 70000];
+    for (i, byte) in data.iter_mut().enumerate().skip(40000) {
+        *byte = (i * 7 % 251) as u8;
+    }
+    for data in [&[][..], &[1, 2], &data] {
+        assert_eq!(inflate(&zlib_deflate(data)), data);
+    }
+}
+
+#[test]
+fn export_writes_the_plane_and_offline_renders() {
+    let dev = FakeDevice::open();
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+
+    let dir = std::env::temp_dir().join(format!("constellation_export_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::env::set_var("CONSTELLATION_CURSOR_EXPORT_DIR", &dir);
+
+    std::fs::write(control_path("export"), "").unwrap();
+    dev.atomic_move(plane, 10, 0);
+    let png = decode_png(&std::fs::read(dir.join("constellation_cursor_plane.png")).unwrap());
+    let (hot_x, hot_y) = our_hotspot();
+    assert_eq!(png.hotspot, Some(format!("{},{}", hot_x, hot_y)));
+    assert_eq!(png.width, CURSOR_DISPLAY_SIZE as usize);
+    assert!(png.rgba.chunks(4).any(|p| p[3] == 255));
+
+    // Looked for every CONTROL_CHECK_INTERVAL_MS, don't wait for the next one
+    EXPORT_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+    std::fs::write(control_path("export"), "wait 4").unwrap();
+    dev.atomic_move(plane, 20, 0);
+    let png = decode_png(&std::fs::read(dir.join("constellation_cursor_wait@4x.png")).unwrap());
+    assert!(png.height > CURSOR_DISPLAY_SIZE as usize);
+    assert!(!std::path::Path::new(&control_path("export")).exists());
+
+    // Design files are SVG or JSON going by their extension
+    let svg = dir.join("square.svg");
+    std::fs::write(
+        &svg,
+        r##"<svg xmlns="http://www.w3.org/2000/svg"><rect x="2" y="2" width="12" height="12" fill="#ff0000"/></svg>"##,
+    )
+    .unwrap();
+    EXPORT_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+    std::fs::write(control_path("export"), format!("{} 1", svg.display())).unwrap();
+    dev.atomic_move(plane, 30, 0);
+    let png = decode_png(&std::fs::read(dir.join("constellation_cursor_square@1x.png")).unwrap());
+    assert!(png.rgba.chunks(4).any(|p| p == [255, 0, 0, 255]));
+
+    std::env::remove_var("CONSTELLATION_CURSOR_EXPORT_DIR");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn builtin_cursors_match_goldens() {
+    let _lock = fake_drm::lock();
+    for cursor_type in CursorType::BUILTIN {
+        for scale in GOLDEN_SCALES {
+            let canvas = render_to_canvas(&Design::Builtin(cursor_type), scale, 0.0);
+            let (hot_x, hot_y) = canvas.hotspot();
+            assert!((0..64).contains(&hot_x) && (0..64).contains(&hot_y));
+            let name = format!("{:?}@{}x", cursor_type, scale).to_lowercase();
+            check_golden(&name, &canvas.crop(64, 64));
+        }
+    }
+}
+
+#[test]
+fn rotated_design_matches_golden() {
+    let _lock = fake_drm::lock();
+    let canvas = render_to_canvas(&Design::Json(EMBEDDED_CURSOR_JSON), 1.5, 30.0);
+    check_golden("embedded-rotated@1.5x", &canvas.crop(64, 64));
+}
+
+#[test]
+fn svg_paths_follow_relative_and_implicit_commands() {
+    let subpaths = svg_path("m1 2 3 4h5v-1L0 0zM9 9l1-1.5.5.5q1 0 1 1");
+    let xy = |points: &Vec<SvgPoint>| points.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>();
+
+    assert_eq!(subpaths.len(), 2);
+    assert_eq!(
+        xy(&subpaths[0]),
+        [(1.0, 2.0), (4.0, 6.0), (9.0, 6.0), (9.0, 5.0), (0.0, 0.0)]
+    );
+    assert_eq!(
+        xy(&subpaths[1]),
+        [(9.0, 9.0), (10.0, 7.5), (10.5, 8.0), (11.5, 9.0)]
+    );
+    assert!(subpaths[1][3].curve.is_some());
+}
+
+#[test]
+fn svg_shapes_render_like_their_design_json() {
+    let _lock = fake_drm::lock();
+    let svg = r##"<svg xmlns="http://www.w3.org/2000/svg">
+        <polygon points="0,0 0,18 13,12" style="fill:#fff; stroke:#000" stroke-width="2"/>
+    </svg>"##;
+    let json = r##"{"version":2,"layers":[{"points":[{"x":0,"y":0},{"x":0,"y":18},{"x":13,"y":12}],
+        "fill":"#ffffff","outline":"#000000","outlineWidth":2,"shadowAlpha":0}]}"##;
+
+    let from_svg = render_to_canvas(&Design::Svg(svg), 2.0, 0.0);
+    let from_json = render_to_canvas(&Design::Json(json), 2.0, 0.0);
+    assert_eq!(from_svg.hotspot(), from_json.hotspot());
+    assert!(from_svg.to_argb() == from_json.to_argb());
+    assert!(from_svg.to_argb().contains(&0xFFFFFFFF));
+}
+
+/// Two 20x20 squares overlapping on x 10..20, red below and blue on top with
+/// `extra` on the blue one, rendered at 1.5 so the design maps 1:1.5
+fn render_overlapping_squares(extra: &str) -> Canvas {
+    let json = format!(
+        r##"{{"version":2,"layers":[
+        {{"points":[{{"x":0,"y":0}},{{"x":20,"y":0}},{{"x":20,"y":20}},{{"x":0,"y":20}}],
+            "fill":"#ff0000","outlineWidth":0,"shadowAlpha":0}},
+        {{"points":[{{"x":10,"y":0}},{{"x":30,"y":0}},{{"x":30,"y":20}},{{"x":10,"y":20}}],
+            "fill":"#0000ff","outlineWidth":0,"shadowAlpha":0,{extra}}}]}}"##
+    );
+    render_to_canvas(&Design::Json(&json), 1.5, 0.0)
+}
+
+/// Pixels left of, inside and right of the overlap, mid height
+fn overlap_pixels(canvas: &Canvas) -> [u32; 3] {
+    let argb = canvas.to_argb();
+    let at = |x: usize| argb[15 * canvas.width + x * 3 / 2];
+    [at(5), at(15), at(25)]
+}
+
+const RED: u32 = 0xFFFF0000;
+const BLUE: u32 = 0xFF0000FF;
+
+#[test]
+fn clip_and_mask_layers_cut_by_their_target() {
+    let _lock = fake_drm::lock();
+    assert_eq!(
+        overlap_pixels(&render_overlapping_squares(r#""blendMode":"normal""#)),
+        [RED, BLUE, BLUE]
+    );
+    assert_eq!(
+        overlap_pixels(&render_overlapping_squares(r#""clipTo":0"#)),
+        [RED, BLUE, 0]
+    );
+    assert_eq!(
+        overlap_pixels(&render_overlapping_squares(
+            r#""maskBy":0,"maskMode":"intersect""#
+        )),
+        [RED, BLUE, 0]
+    );
+    // Subtract is the default
+    assert_eq!(
+        overlap_pixels(&render_overlapping_squares(r#""maskBy":0"#)),
+        [RED, RED, BLUE]
+    );
+    // Exclude keeps only where one of the two shapes is, in the layer's fill
+    assert_eq!(
+        overlap_pixels(&render_overlapping_squares(
+            r#""maskBy":0,"maskMode":"exclude""#
+        )),
+        [BLUE, RED, BLUE]
+    );
+}
+
+#[test]
+fn passthrough_layers_reveal_what_was_below_their_target() {
+    let _lock = fake_drm::lock();
+    // Red, then green over all of it, then a see-through window in the
+    // middle third that looks through the green
+    let json = r##"{"version":2,"layers":[
+        {"points":[{"x":0,"y":0},{"x":30,"y":0},{"x":30,"y":20},{"x":0,"y":20}],
+            "fill":"#ff0000","outlineWidth":0,"shadowAlpha":0},
+        {"points":[{"x":0,"y":0},{"x":30,"y":0},{"x":30,"y":20},{"x":0,"y":20}],
+            "fill":"#00ff00","outlineWidth":0,"shadowAlpha":0},
+        {"points":[{"x":10,"y":0},{"x":20,"y":0},{"x":20,"y":20},{"x":10,"y":20}],
+            "fill":"#ffffff","fillAlpha":0,"outlineWidth":0,"shadowAlpha":0,
+            "passthroughTo":1}]}"##;
+    let canvas = render_to_canvas(&Design::Json(json), 1.5, 0.0);
+    assert_eq!(overlap_pixels(&canvas), [0xFF00FF00, RED, 0xFF00FF00]);
+
+    // Through to layer 0 shows what was below everything, nothing
+    let json = json.replace(r#""passthroughTo":1"#, r#""passthroughTo":0"#);
+    let canvas = render_to_canvas(&Design::Json(&json), 1.5, 0.0);
+    assert_eq!(overlap_pixels(&canvas), [0xFF00FF00, 0, 0xFF00FF00]);
+}
+
+#[test]
+fn layer_blend_modes_follow_the_compositing_formulas() {
+    let rgba = |p: LinearPixel| [p.r, p.g, p.b, p.a];
+    let pixel = |r, g, b, a| LinearPixel { r, g, b, a };
+    // Opaque backdrop, and a half transparent source that's (0.5, 0.25, 1.0)
+    // before premultiplying
+    let dst = pixel(0.25, 0.5, 0.75, 1.0);
+    let src = pixel(0.25, 0.125, 0.5, 0.5);
+    let blend = |dst, name| rgba(blend_layer_pixel(dst, src, LayerBlendMode::from_name(name)));
+
+    assert_eq!(blend(dst, "multiply"), [0.1875, 0.3125, 0.75, 1.0]);
+    assert_eq!(blend(dst, "screen"), [0.4375, 0.5625, 0.875, 1.0]);
+    assert_eq!(blend(dst, "Overlay"), [0.25, 0.375, 0.875, 1.0]);
+    assert_eq!(blend(dst, "difference"), [0.25, 0.375, 0.5, 1.0]);
+    assert_eq!(blend(dst, "erase"), [0.125, 0.25, 0.375, 0.5]);
+    // Xor only shows where just one of them is, so half transparent below too
+    let half = pixel(0.25, 0.25, 0.25, 0.5);
+    assert_eq!(blend(half, "xor"), [0.25, 0.1875, 0.375, 0.5]);
+
+    // Nothing of the source, nothing changes
+    for name in [
+        "multiply",
+        "screen",
+        "overlay",
+        "difference",
+        "xor",
+        "erase",
+    ] {
+        let mode = LayerBlendMode::from_name(name);
+        assert_eq!(
+            rgba(blend_layer_pixel(dst, LinearPixel::TRANSPARENT, mode)),
+            rgba(dst),
+            "{}",
+            name
+        );
+    }
+    assert_eq!(LayerBlendMode::from_name("bogus"), LayerBlendMode::Normal);
+}
+
+#[test]
+fn timeline_loops_or_holds_the_last_entry() {
+    let durations = [100.0, 300.0];
+    assert_eq!(timeline_position(&durations, 50.0, true), (0, 0.5, false));
+    assert_eq!(timeline_position(&durations, 250.0, true), (1, 0.5, false));
+    assert_eq!(timeline_position(&durations, 450.0, true), (0, 0.5, false));
+
+    // Without loop the last entry is held once it's over
+    assert_eq!(timeline_position(&durations, 250.0, false), (1, 0.5, false));
+    assert_eq!(timeline_position(&durations, 400.0, false), (1, 1.0, true));
+    assert_eq!(timeline_position(&durations, 9000.0, false), (1, 1.0, true));
+
+    // Zero length entries are stepped over, a timeline of nothing is done
+    let durations = [100.0, 0.0, 100.0];
+    assert_eq!(timeline_position(&durations, 150.0, true), (2, 0.5, false));
+    assert_eq!(timeline_position(&[0.0, 0.0], 50.0, true), (0, 0.0, true));
+    assert_eq!(timeline_position(&[], 50.0, false), (0, 0.0, true));
+}
+
+#[test]
+fn keyframes_ease_the_tint_and_hide_layers() {
+    let keyframes = r##"{"keyframes":[
+        {"duration":100,"hidden":[1]},
+        {"duration":100,"rotation":90,"tint":"#80ff0000"},
+        {"duration":100,"scale":2,"tint":"#ff0000ff"}]"##;
+    let looping = DesignAnimation::parse(&format!("{}}}", keyframes)).unwrap();
+    let once = DesignAnimation::parse(&format!(r#"{},"loop":false}}"#, keyframes)).unwrap();
+
+    // Tinting in from nothing only fades the tint's alpha up
+    let state = looping.sample(50.0);
+    assert_eq!(state.tint, 0x40FF0000);
+    assert_eq!(state.hidden, [1]);
+    assert_eq!(state.rotation, 45.0);
+
+    let state = looping.sample(150.0);
+    assert_eq!(state.tint, 0xC0800080);
+    assert!(state.hidden.is_empty());
+    assert_eq!(state.scale, 1.5);
+
+    // Back around to the untinted first keyframe, the color stays put
+    let state = looping.sample(250.0);
+    assert_eq!(state.tint, 0x800000FF);
+    assert!(!state.finished);
+
+    let state = once.sample(250.0);
+    assert_eq!(state.tint, 0xFF0000FF);
+    assert!(!state.finished);
+    let state = once.sample(1000.0);
+    assert_eq!((state.tint, state.scale), (0xFF0000FF, 2.0));
+    assert!(state.hidden.is_empty());
+    assert!(state.finished);
+}
+
+#[test]
+fn custom_design_is_read_again_only_when_it_changes() {
+    let _lock = fake_drm::lock();
+    let red = r##"{"version":2,"layers":[
+        {"points":[{"x":0,"y":0},{"x":20,"y":0},{"x":20,"y":20},{"x":0,"y":20}],
+            "fill":"#ff0000","outlineWidth":0,"shadowAlpha":0}]}"##;
+    // Not the same length either, a quick rewrite can keep the mtime
+    let blue = red.replace("#ff0000", "#ff0000ff");
+    let center = || {
+        let canvas = render_to_canvas(&Design::Builtin(CursorType::Custom), 1.5, 0.0);
+        canvas.to_argb()[15 * canvas.width + 15]
+    };
+
+    std::fs::write(control_path("custom"), red).unwrap();
+    assert_eq!(center(), 0xFFFF0000);
+    // Frames in between checks use what was parsed
+    std::fs::write(control_path("custom"), &blue).unwrap();
+    CUSTOM_DESIGN_CHECK_MS.store(monotonic_ms(), Ordering::Relaxed);
+    assert_eq!(center(), 0xFFFF0000);
+
+    CUSTOM_DESIGN_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+    assert_eq!(center(), 0xFF0000FF);
+    std::fs::remove_file(control_path("custom")).unwrap();
+    CUSTOM_DESIGN_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+    assert_ne!(center(), 0xFF0000FF);
+}
+
+#[test]
+fn exported_xcursor_theme_reads_back_through_the_importer() {
+    let _lock = fake_drm::lock();
+    let dir = std::env::temp_dir().join(format!("constellation_theme_{}", std::process::id()));
+    export_xcursor_theme(&dir, "constellation").unwrap();
+
+    let default = std::fs::read(dir.join("cursors/default")).unwrap();
+    for nominal in XCURSOR_EXPORT_SIZES {
+        let frames = parse_xcursor(&default, nominal).unwrap();
+        let expected = &xcursor_frames(CursorType::Default, nominal)[0];
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            (frames[0].width, frames[0].height),
+            (expected.width(), expected.height())
+        );
+        assert_eq!(
+            (frames[0].xhot as i32, frames[0].yhot as i32),
+            expected.hotspot()
+        );
+    }
+
+    // The plane's default look is the 24px image, give or take the
+    // importer's rounding when it un-premultiplies
+    let plane = render_to_canvas(&Design::Builtin(CursorType::Default), 1.5, 0.0);
+    let image = &parse_xcursor(&default, 24).unwrap()[0];
+    let plane = premultiplied(&plane.crop(image.width, image.height).pixels);
+    for (ours, theirs) in plane.iter().zip(premultiplied(&image.pixels)) {
+        let worst = ours
+            .to_le_bytes()
+            .iter()
+            .zip(theirs.to_le_bytes())
+            .map(|(a, b)| a.abs_diff(b))
+            .max();
+        assert!(worst <= Some(2), "{:08x} came back as {:08x}", ours, theirs);
+    }
+
+    // Cut short, the last image's pixels aren't all there
+    let largest = *XCURSOR_EXPORT_SIZES.iter().max().unwrap();
+    assert!(parse_xcursor(&default[..default.len() - 4], largest).is_none());
+
+    let wait = parse_xcursor(&std::fs::read(dir.join("cursors/wait")).unwrap(), 32).unwrap();
+    assert_eq!(wait.len(), (WAIT_CYCLE_MS / XCURSOR_FRAME_MS) as usize);
+    assert!(wait.iter().all(|frame| frame.delay == XCURSOR_FRAME_MS));
+
+    assert_eq!(
+        std::fs::read_link(dir.join("cursors/left_ptr")).unwrap(),
+        std::path::Path::new("default")
+    );
+    let index = std::fs::read_to_string(dir.join("index.theme")).unwrap();
+    assert!(index.contains("Name=constellation"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn premultiplied(pixels: &[LinearPixel]) -> Vec<u32> {
+    pixels
+        .iter()
+        .map(|p| encode_pixel(*p, 1.0, PixelBlendMode::Premultiplied))
+        .collect()
+}
+
+#[test]
+fn log_spec_sets_levels_per_subsystem() {
+    let _lock = fake_drm::lock();
+    let saved: Vec<u32> = LOG_LEVELS
+        .iter()
+        .map(|l| l.load(Ordering::Relaxed))
+        .collect();
+
+    apply_log_spec("info, drm=trace,fade=off,bogus=debug,render=loud");
+    assert!(log_enabled(LogLevel::Trace, LogSubsystem::Drm));
+    assert!(log_enabled(LogLevel::Info, LogSubsystem::Render));
+    assert!(!log_enabled(LogLevel::Debug, LogSubsystem::Config));
+    assert!(!log_enabled(LogLevel::Error, LogSubsystem::Fade));
+
+    for (level, value) in LOG_LEVELS.iter().zip(saved) {
+        level.store(value, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn log_file_rotates_while_it_is_written() {
+    let _lock = fake_drm::lock();
+    let state = std::env::temp_dir().join(format!("constellation_state_{}", std::process::id()));
+    let log = state.join("constellation_cursor/cursor.log");
+    std::fs::create_dir_all(log.parent().unwrap()).unwrap();
+    std::fs::write(&log, vec![b'.'; LOG_FILE_MAX_BYTES as usize - 10]).unwrap();
+    std::env::set_var("XDG_STATE_HOME", &state);
+    LOG_FILE_ENABLED.store(true, Ordering::Relaxed);
+
+    write_log(LogLevel::Info, LogSubsystem::Config, "fills it up");
+    assert!(!log.with_extension("log.old").exists());
+    write_log(LogLevel::Info, LogSubsystem::Config, "starts a new one");
+    assert!(log.with_extension("log.old").exists());
+    let fresh = std::fs::read_to_string(&log).unwrap();
+    assert!(
+        fresh.ends_with("INFO config: starts a new one\n"),
+        "{}",
+        fresh
+    );
+
+    LOG_FILE_ENABLED.store(false, Ordering::Relaxed);
+    *LOG_FILE.lock().unwrap() = None;
+    std::env::remove_var("XDG_STATE_HOME");
+    let _ = std::fs::remove_dir_all(&state);
+}
+
+#[test]
+fn limited_logs_count_what_they_drop() {
+    let limit = LogRateLimit::new();
+    assert_eq!(limit.allow(), Some(0));
+    assert_eq!(limit.allow(), None);
+    assert_eq!(limit.allow(), None);
+
+    // The clock starts with the process, make sure a second back exists
+    while monotonic_ms() < LogRateLimit::INTERVAL_MS {
+        thread::sleep(Duration::from_millis(50));
+    }
+    let a_second_ago = monotonic_ms() - LogRateLimit::INTERVAL_MS;
+    limit.last_ms.store(a_second_ago, Ordering::Relaxed);
+    assert_eq!(limit.allow(), Some(2));
+}
+
+#[test]
+fn status_dump_counts_what_the_hook_did() {
+    let dev = FakeDevice::open();
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+    dev.atomic_add(plane, "CRTC_X", 10);
+    unsafe {
+        drmModeMoveCursor(dev.fd, CRTC, 5, 5);
+        let mut map = DrmModeMapDumb {
+            handle: 9999,
+            ..Default::default()
+        };
+        real_ioctl(
+            dev.fd,
+            DRM_IOCTL_MODE_MAP_DUMB,
+            &mut map as *mut _ as *mut c_void,
+        );
+    }
+    assert_eq!(STAT_ATOMIC_PROPERTIES.load(Ordering::Relaxed), 2);
+    assert_eq!(STAT_FB_SUBSTITUTIONS.load(Ordering::Relaxed), 1);
+    assert_eq!(STAT_LEGACY_CURSOR_CALLS.load(Ordering::Relaxed), 1);
+    assert!(STAT_RENDERS.load(Ordering::Relaxed) >= 1);
+
+    std::fs::write(control_path("status"), "reset").unwrap();
+    // Checked every CONTROL_CHECK_INTERVAL_MS, don't wait for the next one
+    STATUS_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+    dev.atomic_add(plane, "CRTC_X", 20);
+    let report = std::fs::read_to_string(control_path("status.txt")).unwrap();
+    assert!(!std::path::Path::new(&control_path("status")).exists());
+    assert!(report.contains(&format!("pid: {}", std::process::id())));
+    assert!(report.contains("mode: atomic"));
+    assert!(report.contains("atomic properties: 3"));
+    assert!(report.contains("fb substitutions: 1"));
+    assert!(report.contains("failed ioctls: 1"));
+    assert!(report.contains("  MODE_MAP_DUMB: "));
+
+    // Counting starts over after a reset
+    assert_eq!(STAT_ATOMIC_PROPERTIES.load(Ordering::Relaxed), 0);
+    assert_eq!(STAT_RENDER_MAX_NS.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn doctor_explains_which_path_is_in_effect() {
+    let dev = FakeDevice::open();
+    dev.add_crtc(CRTC);
+    dev.add_plane(
+        PlaneKind::Primary,
+        &[("FB_ID", 0), ("CRTC_ID", CRTC as u64)],
+    );
+    let plane = atomic_cursor(&dev);
+
+    // Only a real /dev/dri node gets probed on its own
+    assert!(!is_drm_node(dev.fd));
+    assert!(!is_drm_node(-1));
+
+    let probe = unsafe { doctor_probe(dev.fd) };
+    assert_eq!(probe.cursor_planes, 1);
+    assert_eq!(probe.cursor_size, Some((64, 64)));
+    assert!(probe.buffer_error.is_none());
+    let report = doctor_report(&probe);
+    assert!(report.contains("driver: fake 1.0.0 (Fake DRM device)"));
+    assert!(report.contains(&format!("CRTCs: {}", CRTC)));
+    assert!(report.contains(&format!("primary  CRTCs 0x1  on CRTC {}", CRTC)));
+    assert!(report.contains("yes drmModeGetPlane"));
+    assert!(report.contains("No cursor calls seen yet"));
+
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.atomic_add(plane, "FB_ID", theirs as u64);
+    let report = doctor_report(&probe);
+    assert!(report.contains("yes drmModeAtomicAddProperty on a cursor plane"));
+    assert!(report.contains("- Atomic: "));
+    assert!(!report.contains("No cursor calls"));
+
+    dev.set_failing(DRM_IOCTL_MODE_CREATE_DUMB, true);
+    let probe = unsafe { doctor_probe(dev.fd) };
+    let error = probe.buffer_error.clone().unwrap();
+    assert!(error.starts_with("MODE_CREATE_DUMB failed"));
+    assert!(doctor_report(&probe).contains("We can't make a cursor buffer"));
+}
+
+#[test]
+fn failed_buffer_passes_their_cursor_through_and_retries() {
+    let dev = FakeDevice::open();
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    let handle = dev.handle_of(theirs);
+    dev.set_failing(DRM_IOCTL_MODE_ADDFB2, true);
+
+    let ret = unsafe { drmModeSetCursor2(dev.fd, CRTC, handle, 24, 24, 10, 12) };
+    assert_eq!(ret, 0);
+    let call = dev.last_cursor_call().unwrap();
+    assert_eq!(call.handle, handle);
+    assert_eq!((call.hot_x, call.hot_y), (10, 12));
+    assert!(!INITIALIZED.load(Ordering::SeqCst));
+    assert_eq!(
+        *BUFFER_ERROR.lock().unwrap(),
+        Some(CursorError::AddFb(libc::EINVAL))
+    );
+    // The dumb buffer made before ADDFB2 failed is gone again
+    assert_eq!(dev.dumb_buffers(), 1);
+
+    // Moves aren't shifted by a hotspot we don't show
+    unsafe {
+        drmModeMoveCursor(dev.fd, CRTC, 100, 200);
+    }
+    let call = dev.last_cursor_call().unwrap();
+    assert_eq!((call.x, call.y), (100, 200));
+
+    // No new attempt until the backoff is over
+    dev.set_failing(DRM_IOCTL_MODE_ADDFB2, false);
+    unsafe {
+        drmModeSetCursor2(dev.fd, CRTC, handle, 24, 24, 10, 12);
+    }
+    assert_eq!(dev.last_cursor_call().unwrap().handle, handle);
+    assert_eq!(BUFFER_FAILURES.load(Ordering::SeqCst), 1);
+
+    BUFFER_RETRY_MS.store(0, Ordering::SeqCst);
+    unsafe {
+        drmModeSetCursor2(dev.fd, CRTC, handle, 24, 24, 10, 12);
+    }
+    assert!(INITIALIZED.load(Ordering::SeqCst));
+    assert_eq!(
+        dev.last_cursor_call().unwrap().handle,
+        CURSOR_HANDLE.load(Ordering::SeqCst)
+    );
+    assert_eq!(*BUFFER_ERROR.lock().unwrap(), None);
+}
+
+#[test]
+fn atomic_properties_pass_through_without_a_buffer() {
+    let dev = FakeDevice::open();
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    dev.set_failing(DRM_IOCTL_MODE_CREATE_DUMB, true);
+
+    assert_eq!(
+        dev.atomic_add(plane, "FB_ID", theirs as u64),
+        Some(theirs as u64)
+    );
+    assert_eq!(dev.atomic_add(plane, "CRTC_W", 24), Some(24));
+    assert_eq!(
+        *BUFFER_ERROR.lock().unwrap(),
+        Some(CursorError::CreateDumb(libc::EINVAL))
+    );
+
+    // Out of tries, only a refresh starts over
+    BUFFER_FAILURES.store(BUFFER_MAX_ATTEMPTS, Ordering::SeqCst);
+    dev.set_failing(DRM_IOCTL_MODE_CREATE_DUMB, false);
+    assert_eq!(
+        dev.atomic_add(plane, "FB_ID", theirs as u64),
+        Some(theirs as u64)
+    );
+    REFRESH_CHECK_MS.store(u64::MAX, Ordering::Relaxed);
+    std::fs::write(control_path("refresh"), "").unwrap();
+    let fb = dev.atomic_add(plane, "FB_ID", theirs as u64);
+    assert_eq!(fb, Some(CURSOR_FB_ID.load(Ordering::SeqCst) as u64));
+}
+
+#[test]
+fn rejected_commits_go_through_with_the_compositors_cursor() {
+    let dev = FakeDevice::open();
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+
+    let req = dev.atomic_request();
+    let ours = dev
+        .atomic_add_to(req, plane, "FB_ID", theirs as u64)
+        .unwrap();
+    assert_eq!(ours, CURSOR_FB_ID.load(Ordering::SeqCst) as u64);
+    assert_eq!(dev.atomic_commit(req), 0);
+    assert_eq!(dev.commits(), 1);
+    dev.atomic_free(req);
+
+    // A flip still pending is nobody's fault, that's not retried
+    let req = dev.atomic_request();
+    dev.atomic_add_to(req, plane, "FB_ID", theirs as u64);
+    dev.fail_next_commit(libc::EBUSY);
+    assert_eq!(dev.atomic_commit(req), -libc::EBUSY);
+    assert_eq!(dev.commits(), 2);
+    assert_eq!(STAT_COMMIT_RETRIES.load(Ordering::Relaxed), 0);
+    dev.atomic_free(req);
+
+    // The driver won't scan out our FB, the compositor never hears about it.
+    // Trying plane setups with TEST_ONLY doesn't count against the plane.
+    dev.reject_property("FB_ID", ours);
+    for _ in 0..=PLANE_MAX_REJECTS {
+        let req = dev.atomic_request();
+        dev.atomic_add_to(req, plane, "FB_ID", theirs as u64);
+        assert_eq!(dev.atomic_test(req), 0);
+        dev.atomic_free(req);
+    }
+    assert_eq!(unsafe { CURSOR_PLANE_REJECTS[0] }, 0);
+    STAT_COMMIT_RETRIES.store(0, Ordering::Relaxed);
+    let before = dev.commits();
+    for attempt in 1..=PLANE_MAX_REJECTS {
+        let req = dev.atomic_request();
+        assert_eq!(
+            dev.atomic_add_to(req, plane, "FB_ID", theirs as u64),
+            Some(ours)
+        );
+        assert_eq!(dev.atomic_commit(req), 0);
+        assert_eq!(STAT_COMMIT_RETRIES.load(Ordering::Relaxed), attempt as u64);
+        dev.atomic_free(req);
+    }
+    assert_eq!(dev.commits(), before + 2 * PLANE_MAX_REJECTS);
+    assert!(unsafe { CURSOR_PLANE_GIVEN_UP[0] });
+    assert!(ATOMIC_REQUESTS.lock().unwrap().is_empty());
+
+    // From now on that plane is theirs
+    let req = dev.atomic_request();
+    assert_eq!(
+        dev.atomic_add_to(req, plane, "FB_ID", theirs as u64),
+        Some(theirs as u64)
+    );
+    assert_eq!(dev.atomic_add_to(req, plane, "CRTC_W", 24), Some(24));
+    assert_eq!(dev.atomic_commit(req), 0);
+    assert_eq!(dev.commits(), before + 1 + 2 * PLANE_MAX_REJECTS);
+
+    // Something else failing isn't put on us
+    dev.reject_property("CRTC_W", 24);
+    assert_eq!(dev.atomic_commit(req), -libc::EINVAL);
+    assert_eq!(
+        STAT_COMMIT_RETRIES.load(Ordering::Relaxed),
+        PLANE_MAX_REJECTS as u64
+    );
+}
+
+#[test]
+fn requests_keep_the_compositors_values() {
+    let dev = FakeDevice::open();
+    let plane = atomic_cursor(&dev);
+    let theirs = dev.add_framebuffer(24, 24, &[0; 24 * 24]);
+    let fb_prop = dev.prop_id("FB_ID");
+    let width_prop = dev.prop_id("CRTC_W");
+    let edited = |req| -> Vec<(u32, u64)> {
+        request_edits(req)
+            .iter()
+            .map(|e| (e.property_id, e.theirs))
+            .collect()
+    };
+
+    let req = dev.atomic_request();
+    let ours = dev
+        .atomic_add_to(req, plane, "FB_ID", theirs as u64)
+        .unwrap();
+    assert_eq!(dev.atomic_add_to(req, plane, "CRTC_X", 100), Some(100));
+    dev.atomic_add_to(req, plane, "CRTC_W", 24);
+    assert_eq!(
+        edited(req),
+        vec![(fb_prop, theirs as u64), (width_prop, 24)]
+    );
+
+    // libdrm didn't take it, so it's not in the request's count either
+    dev.fail_next_add();
+    assert_eq!(dev.atomic_add_to(req, plane, "CRTC_Y", 100), None);
+    let cursor = |req: *mut c_void| {
+        ATOMIC_REQUESTS
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|r| r.req == req as usize)
+            .map(|r| r.cursor)
+    };
+    assert_eq!(cursor(req), Some(3));
+
+    // Rolled back to just the FB
+    dev.atomic_set_cursor(req, 1);
+    assert_eq!(edited(req), vec![(fb_prop, theirs as u64)]);
+
+    let copy = dev.atomic_duplicate(req);
+    assert_eq!(edited(copy), vec![(fb_prop, theirs as u64)]);
+    let extra = dev.atomic_request();
+    dev.atomic_add_to(extra, plane, "CRTC_W", 24);
+    dev.atomic_merge(copy, extra);
+    assert_eq!(
+        edited(copy),
+        vec![(fb_prop, theirs as u64), (width_prop, 24)]
+    );
+    assert_eq!(request_edits(copy)[1].at, 1);
+
+    // A failed commit goes back out with exactly what they had
+    dev.reject_property("FB_ID", ours);
+    assert_eq!(dev.atomic_commit(copy), 0);
+    assert!(edited(copy).is_empty());
+    assert_eq!(edited(req), vec![(fb_prop, theirs as u64)]);
+
+    // Nothing to copy from a request we never saw
+    let unknown = dev.atomic_duplicate(0xdead0 as *mut c_void);
+    assert_eq!(cursor(unknown), None);
+    assert_eq!(cursor(0xdead0 as *mut c_void), None);
+
+    for r in [req, copy, extra] {
+        dev.atomic_free(r);
+    }
+    assert!(ATOMIC_REQUESTS.lock().unwrap().is_empty());
+}